@@ -0,0 +1,118 @@
+//! ADTS framing of raw AAC-LC frames, as HTTP players expect for `audio/aac`.
+
+pub const HEADER_LEN: usize = 7;
+
+/// Sampling frequency index of the header, by rate.
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+/// Audio object type minus one.
+const PROFILE_AAC_LC: u8 = 1;
+const MAX_FRAME_LEN: usize = (1 << 13) - 1;
+
+/// Returns `None` if the rate has no index, the channels count is not 1 to 7,
+/// or the frame is too long.
+pub fn header(rate: u32, channels: u32, payload_len: usize) -> Option<[u8; HEADER_LEN]> {
+    let rate_idx = rate_index(rate)?;
+    let channels = channel_config(channels)?;
+    let len = payload_len + HEADER_LEN;
+    if len > MAX_FRAME_LEN {
+        return None;
+    }
+
+    Some([
+        0xFF,
+        // MPEG-4, layer 0, no CRC.
+        0xF1,
+        (PROFILE_AAC_LC << 6) | (rate_idx << 2) | (channels >> 2),
+        ((channels & 0x3) << 6) | (len >> 11) as u8,
+        (len >> 3) as u8,
+        // Buffer fullness is 0x7FF, meaning variable bit rate.
+        ((len & 0x7) << 5) as u8 | 0x1F,
+        0xFC,
+    ])
+}
+
+/// Appends the frame with an ADTS header to `out`. Returns `false` if it can't be framed.
+pub fn write_frame(rate: u32, channels: u32, frame: &[u8], out: &mut Vec<u8>) -> bool {
+    match header(rate, channels, frame.len()) {
+        Some(header) => {
+            out.extend_from_slice(&header);
+            out.extend_from_slice(frame);
+            true
+        }
+        None => false,
+    }
+}
+
+/// MPEG-4 AudioSpecificConfig of AAC-LC, as in the `config` of an SDP.
+pub fn audio_specific_config(rate: u32, channels: u32) -> Option<[u8; 2]> {
+    let rate_idx = rate_index(rate)?;
+    let channels = channel_config(channels)?;
+    let object_type = PROFILE_AAC_LC + 1;
+
+    Some([
+        (object_type << 3) | (rate_idx >> 1),
+        ((rate_idx & 0x1) << 7) | (channels << 3),
+    ])
+}
+
+fn rate_index(rate: u32) -> Option<u8> {
+    SAMPLE_RATES
+        .iter()
+        .position(|&r| r == rate)
+        .map(|idx| idx as u8)
+}
+
+/// Channels count 1 to 7 is its own configuration.
+fn channel_config(channels: u32) -> Option<u8> {
+    if channels == 0 || channels > 7 {
+        return None;
+    }
+    Some(channels as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_of_stereo_44100() {
+        assert_eq!(
+            header(44100, 2, 100),
+            Some([0xFF, 0xF1, 0x50, 0x80, 0x0D, 0x7F, 0xFC])
+        );
+    }
+
+    #[test]
+    fn header_carries_long_frame_len() {
+        let header = header(48000, 1, MAX_FRAME_LEN - HEADER_LEN).unwrap();
+        let len = (usize::from(header[3] & 0x3) << 11)
+            | (usize::from(header[4]) << 3)
+            | usize::from(header[5] >> 5);
+        assert_eq!(len, MAX_FRAME_LEN);
+    }
+
+    #[test]
+    fn header_rejects_what_it_cant_describe() {
+        assert_eq!(header(44000, 2, 100), None);
+        assert_eq!(header(44100, 8, 100), None);
+        assert_eq!(header(44100, 2, MAX_FRAME_LEN - HEADER_LEN + 1), None);
+    }
+
+    #[test]
+    fn frame_is_prefixed_with_header() {
+        let mut out = vec![0xAA];
+        assert!(write_frame(44100, 2, &[1, 2, 3], &mut out));
+        assert_eq!(
+            out,
+            [0xAA, 0xFF, 0xF1, 0x50, 0x80, 0x01, 0x5F, 0xFC, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn audio_specific_config_of_stereo_44100() {
+        assert_eq!(audio_specific_config(44100, 2), Some([0x12, 0x10]));
+        assert_eq!(audio_specific_config(48000, 1), Some([0x11, 0x88]));
+    }
+}
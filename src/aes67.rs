@@ -0,0 +1,477 @@
+//! Uncompressed L16/L24 RTP multicast in the AES67 style, announced with SAP (RFC 2974),
+//! so that receivers on the LAN find the stream by themselves.
+//!
+//! There is no PTP here, the RTP clock is the capture device clock and the SDP says so
+//! with `ts-refclk:local`. Receivers that insist on a PTP reference won't lock to it.
+
+use crate::alsa;
+use crate::channel;
+use crate::error::{Error, IoError};
+use net2::UdpSocketExt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// AES67 mandates 48 kHz.
+pub const RATE: u32 = 48000;
+const RTP_VERSION: u8 = 2;
+const RTP_PAYLOAD_TYPE: u8 = 98;
+const SAP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 255);
+const SAP_PORT: u16 = 9875;
+const SAP_VERSION: u8 = 1;
+const SAP_DELETION: u8 = 0x04;
+const SDP_MIME: &[u8] = b"application/sdp\0";
+/// Packets waiting for their time to be sent, newer ones are dropped when it's full.
+const PACER_QUEUE_MS: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// Big-endian 16 bit.
+    L16,
+    /// Big-endian 24 bit.
+    L24,
+}
+
+/// AES67 receivers must support 1 ms, 4 ms is common for lower packet rates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PacketTime {
+    Ms1,
+    Ms4,
+}
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// IPv4 multicast group and port of the RTP stream.
+    pub group: SocketAddrV4,
+    /// Interface to send from. The default route is used if not set.
+    pub interface: Option<Ipv4Addr>,
+    pub encoding: Encoding,
+    pub ptime: PacketTime,
+    pub ttl: u32,
+    /// Shown by receivers in the list of streams.
+    pub session_name: String,
+    pub sap_interval: Duration,
+}
+
+/// Packs captured frames into RTP packets of exactly `ptime`, and announces the stream
+/// from a separate thread until dropped.
+pub struct Aes67Sender {
+    encoding: Encoding,
+    input: alsa::Params,
+    frames_per_pkt: usize,
+    /// Converted samples waiting for a whole packet.
+    pending: Vec<u8>,
+    seq: u16,
+    timestamp: u32,
+    ssrc: u32,
+    /// Dropped first on drop, which stops the pacer.
+    pacer: Option<channel::Sender<Vec<u8>>>,
+    pacer_thread: Option<JoinHandle<()>>,
+    sap_stop: channel::Sender<()>,
+    sap_thread: Option<JoinHandle<()>>,
+}
+
+/// Sends the packets one `ptime` apart. The capture hands a whole period over at once,
+/// sent as it comes it would be a burst that receivers with small buffers can't take.
+struct Pacer {
+    socket: UdpSocket,
+    group: SocketAddr,
+    ptime: Duration,
+    receiver: channel::Receiver<Vec<u8>>,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::L16 => "L16",
+            Encoding::L24 => "L24",
+        }
+    }
+
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            Encoding::L16 => 2,
+            Encoding::L24 => 3,
+        }
+    }
+}
+
+impl PacketTime {
+    fn frames(self) -> usize {
+        match self {
+            PacketTime::Ms1 => RATE as usize / 1000,
+            PacketTime::Ms4 => RATE as usize / 250,
+        }
+    }
+
+    fn ms(self) -> u32 {
+        match self {
+            PacketTime::Ms1 => 1,
+            PacketTime::Ms4 => 4,
+        }
+    }
+
+    fn duration(self) -> Duration {
+        Duration::from_millis(u64::from(self.ms()))
+    }
+}
+
+impl Aes67Sender {
+    /// `input` is the format `write` is given, it must be 48 kHz. U8 can't be sent.
+    pub fn new(settings: Settings, input: alsa::Params) -> Result<Self, Error> {
+        if input.rate != RATE
+            || input.format == alsa::Format::U8
+            || !settings.group.ip().is_multicast()
+        {
+            return Err(invalid_input(format!(
+                "AES67 needs a multicast group and {} Hz of any format but U8, got {} and {:?}",
+                RATE, settings.group, input
+            )));
+        }
+
+        let socket =
+            UdpSocket::bind("0.0.0.0:0").map_err(|e| IoError::new("creating AES67 socket", e))?;
+        socket
+            .set_multicast_ttl_v4(settings.ttl)
+            .map_err(|e| IoError::new("setting multicast TTL", e))?;
+        if let Some(interface) = &settings.interface {
+            socket
+                .set_multicast_if_v4(interface)
+                .map_err(|e| IoError::new(format!("sending multicast from {}", interface), e))?;
+        }
+
+        let origin = match settings.interface {
+            Some(interface) => interface,
+            None => default_source_ip(settings.group)?,
+        };
+
+        // Not a secret, only has to differ between runs.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let ssrc = now.subsec_nanos() ^ now.as_secs() as u32;
+
+        // RFC 3550 wants a random start, the SDP tells receivers where the media clock is.
+        let timestamp = ssrc.rotate_left(16);
+        let sdp = sdp(
+            &settings,
+            input.channels,
+            origin,
+            ssrc,
+            now.as_secs(),
+            timestamp,
+        );
+        let (sap_stop, stop_receiver) = channel::bounded(1);
+        let sap_thread = {
+            let announcer = SapAnnouncer::new(origin, sdp, settings.ttl, settings.interface)?;
+            let interval = settings.sap_interval;
+            thread::Builder::new()
+                .name("SAP".to_owned())
+                .spawn(move || announcer.run(interval, stop_receiver))
+                .map_err(|e| IoError::new("spawning SAP thread", e))?
+        };
+
+        let (pacer, pkt_receiver) =
+            channel::bounded((PACER_QUEUE_MS / settings.ptime.ms()) as usize);
+        let pacer_thread = {
+            let pacer = Pacer {
+                socket,
+                group: SocketAddr::V4(settings.group),
+                ptime: settings.ptime.duration(),
+                receiver: pkt_receiver,
+            };
+            thread::Builder::new()
+                .name("AES67".to_owned())
+                .spawn(move || pacer.run())
+                .map_err(|e| IoError::new("spawning AES67 thread", e))?
+        };
+
+        let frames_per_pkt = settings.ptime.frames();
+        Ok(Self {
+            encoding: settings.encoding,
+            input,
+            frames_per_pkt,
+            pending: Vec::new(),
+            seq: ssrc as u16,
+            timestamp,
+            ssrc,
+            pacer: Some(pacer),
+            pacer_thread: Some(pacer_thread),
+            sap_stop,
+            sap_thread: Some(sap_thread),
+        })
+    }
+
+    /// Sends as many whole packets as the frames make, keeping the rest for the next call.
+    pub fn write(&mut self, pcm: &[u8]) -> Result<(), Error> {
+        match self.input.format {
+            alsa::Format::S16Le => {
+                for sample in pcm.chunks_exact(2) {
+                    let v = i16::from_le_bytes([sample[0], sample[1]]);
+                    self.push_sample(i32::from(v) << 16);
+                }
+            }
+            alsa::Format::FloatLe => {
+                for sample in pcm.chunks_exact(4) {
+                    let v = f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+                    let v = (f64::from(v.clamp(-1.0, 1.0)) * f64::from(i32::MAX)) as i32;
+                    self.push_sample(v);
+                }
+            }
+            alsa::Format::U8 => unreachable!("rejected in new()"),
+            format => {
+                for sample in pcm.chunks_exact(format.bytes_per_sample()) {
+                    let v = f64::from(format.sample_to_f32(sample).clamp(-1.0, 1.0));
+                    self.push_sample((v * f64::from(i32::MAX)) as i32);
+                }
+            }
+        }
+
+        let pkt_len =
+            self.frames_per_pkt * self.input.channels as usize * self.encoding.bytes_per_sample();
+        let mut sent = 0;
+        while self.pending.len() - sent >= pkt_len {
+            self.send_pkt(sent, pkt_len)?;
+            sent += pkt_len;
+        }
+        self.pending.drain(..sent);
+
+        Ok(())
+    }
+
+    /// Appends a sample given as the top bits of an `i32`, in the network byte order.
+    fn push_sample(&mut self, v: i32) {
+        let bytes = v.to_be_bytes();
+        let len = self.encoding.bytes_per_sample();
+        self.pending.extend_from_slice(&bytes[..len]);
+    }
+
+    /// Hands the packet over to the pacer.
+    fn send_pkt(&mut self, start: usize, len: usize) -> Result<(), Error> {
+        let mut pkt = Vec::with_capacity(12 + len);
+        pkt.push(RTP_VERSION << 6);
+        pkt.push(RTP_PAYLOAD_TYPE);
+        pkt.extend_from_slice(&self.seq.to_be_bytes());
+        pkt.extend_from_slice(&self.timestamp.to_be_bytes());
+        pkt.extend_from_slice(&self.ssrc.to_be_bytes());
+        pkt.extend_from_slice(&self.pending[start..start + len]);
+
+        let pacer = self
+            .pacer
+            .as_ref()
+            .expect("the pacer is only taken on drop");
+        match pacer.try_send(pkt) {
+            Ok(()) => (),
+            Err(channel::TrySendError::Full(_)) => {
+                eprintln!("AES67 packet #{} dropped, the sender is behind", self.seq)
+            }
+            Err(channel::TrySendError::Disconnected(_)) => {
+                return Err(IoError::new(
+                    "sending AES67 packet",
+                    io::Error::new(io::ErrorKind::BrokenPipe, "the sending thread stopped"),
+                )
+                .into())
+            }
+        }
+
+        self.seq = self.seq.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(self.frames_per_pkt as u32);
+        Ok(())
+    }
+}
+impl Drop for Aes67Sender {
+    fn drop(&mut self) {
+        self.pacer = None;
+        if let Some(thread) = self.pacer_thread.take() {
+            if thread.join().is_err() {
+                eprintln!("AES67 thread panicked");
+            }
+        }
+
+        let _ = self.sap_stop.send(());
+        if let Some(thread) = self.sap_thread.take() {
+            if thread.join().is_err() {
+                eprintln!("SAP thread panicked");
+            }
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            group: "239.69.1.1:5004".parse().unwrap(),
+            interface: None,
+            encoding: Encoding::L24,
+            ptime: PacketTime::Ms1,
+            ttl: 15,
+            session_name: "Stream Audio".to_owned(),
+            sap_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Pacer {
+    /// Sends until the sender is dropped or a packet can't be sent.
+    fn run(self) {
+        let mut due = Instant::now();
+        loop {
+            let was_empty = self.receiver.is_empty();
+            let pkt = match self.receiver.recv() {
+                Ok(pkt) => pkt,
+                Err(_) => return,
+            };
+
+            let now = Instant::now();
+            if now > due {
+                // Only the packets that were already waiting are sent late to catch up,
+                // after a pause the schedule starts over.
+                if was_empty {
+                    due = now;
+                }
+            } else {
+                thread::sleep(due - now);
+            }
+
+            if let Err(e) = self.socket.send_to(&pkt, self.group) {
+                eprintln!("Error sending AES67 packet to {}: {}", self.group, e);
+                return;
+            }
+            due += self.ptime;
+        }
+    }
+}
+
+struct SapAnnouncer {
+    socket: UdpSocket,
+    announcement: Vec<u8>,
+    deletion: Vec<u8>,
+}
+
+impl SapAnnouncer {
+    fn new(
+        origin: Ipv4Addr,
+        sdp: String,
+        ttl: u32,
+        interface: Option<Ipv4Addr>,
+    ) -> Result<Self, Error> {
+        let socket =
+            UdpSocket::bind("0.0.0.0:0").map_err(|e| IoError::new("creating SAP socket", e))?;
+        socket
+            .set_multicast_ttl_v4(ttl)
+            .map_err(|e| IoError::new("setting SAP multicast TTL", e))?;
+        if let Some(interface) = &interface {
+            socket
+                .set_multicast_if_v4(interface)
+                .map_err(|e| IoError::new(format!("sending SAP from {}", interface), e))?;
+        }
+
+        let msg_id_hash = sdp
+            .bytes()
+            .fold(0u16, |hash, b| hash.rotate_left(5) ^ u16::from(b));
+        Ok(Self {
+            socket,
+            announcement: sap_pkt(false, msg_id_hash, origin, &sdp),
+            deletion: sap_pkt(true, msg_id_hash, origin, &sdp),
+        })
+    }
+
+    /// Announces every `interval` until stopped, then tells the receivers the stream is gone.
+    fn run(self, interval: Duration, stop: channel::Receiver<()>) {
+        loop {
+            self.send(&self.announcement);
+            match stop.recv_timeout(interval) {
+                Err(channel::RecvTimeoutError::Timeout) => (),
+                Ok(()) | Err(channel::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        self.send(&self.deletion);
+    }
+
+    fn send(&self, pkt: &[u8]) {
+        if let Err(e) = self.socket.send_to(pkt, (SAP_GROUP, SAP_PORT)) {
+            eprintln!("Error sending SAP announcement: {}", e);
+        }
+    }
+}
+
+/// Header without authentication or encryption, IPv4 origin.
+fn sap_pkt(is_deletion: bool, msg_id_hash: u16, origin: Ipv4Addr, sdp: &str) -> Vec<u8> {
+    let mut flags = SAP_VERSION << 5;
+    if is_deletion {
+        flags |= SAP_DELETION;
+    }
+
+    let mut pkt = Vec::with_capacity(8 + SDP_MIME.len() + sdp.len());
+    pkt.push(flags);
+    // Authentication data length.
+    pkt.push(0);
+    pkt.extend_from_slice(&msg_id_hash.to_be_bytes());
+    pkt.extend_from_slice(&origin.octets());
+    pkt.extend_from_slice(SDP_MIME);
+    pkt.extend_from_slice(sdp.as_bytes());
+    pkt
+}
+
+/// `timestamp` is the first RTP timestamp, the media clock offset.
+fn sdp(
+    settings: &Settings,
+    channels: u32,
+    origin: Ipv4Addr,
+    ssrc: u32,
+    version: u64,
+    timestamp: u32,
+) -> String {
+    format!(
+        "v=0\r\n\
+         o=- {ssrc} {version} IN IP4 {origin}\r\n\
+         s={name}\r\n\
+         c=IN IP4 {group}/{ttl}\r\n\
+         t=0 0\r\n\
+         m=audio {port} RTP/AVP {pt}\r\n\
+         a=rtpmap:{pt} {encoding}/{rate}/{channels}\r\n\
+         a=ptime:{ptime}\r\n\
+         a=maxptime:{ptime}\r\n\
+         a=recvonly\r\n\
+         a=ts-refclk:local\r\n\
+         a=mediaclk:direct={timestamp}\r\n",
+        ssrc = ssrc,
+        version = version,
+        origin = origin,
+        name = settings.session_name,
+        group = settings.group.ip(),
+        ttl = settings.ttl,
+        port = settings.group.port(),
+        pt = RTP_PAYLOAD_TYPE,
+        encoding = settings.encoding.name(),
+        rate = RATE,
+        channels = channels,
+        ptime = settings.ptime.ms(),
+        timestamp = timestamp,
+    )
+}
+
+/// The address the kernel would send to the group from, for the SDP origin.
+fn default_source_ip(group: SocketAddrV4) -> Result<Ipv4Addr, Error> {
+    let ctx = || format!("finding the source address for {}", group);
+
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| IoError::new(ctx(), e))?;
+    socket.connect(group).map_err(|e| IoError::new(ctx(), e))?;
+    match socket
+        .local_addr()
+        .map_err(|e| IoError::new(ctx(), e))?
+        .ip()
+    {
+        IpAddr::V4(ip) => Ok(ip),
+        IpAddr::V6(_) => Err(invalid_input(ctx())),
+    }
+}
+
+fn invalid_input(msg: String) -> Error {
+    IoError::new(
+        "starting AES67 stream",
+        io::Error::new(io::ErrorKind::InvalidInput, msg),
+    )
+    .into()
+}
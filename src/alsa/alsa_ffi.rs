@@ -1,4 +1,4 @@
-use libc::{c_char, c_int, c_long, c_uint, c_ulong, c_void, size_t};
+use libc::{c_char, c_int, c_long, c_uint, c_ulong, c_ushort, c_void, pollfd, size_t};
 
 #[repr(C)]
 pub struct snd_pcm_t {
@@ -40,8 +40,41 @@ pub struct snd_output_t {
     _private: [u8; 0],
 }
 
+#[repr(C)]
+pub struct snd_mixer_t {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct snd_mixer_elem_t {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct snd_mixer_class_t {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct snd_mixer_selem_regopt {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct snd_pcm_channel_area_t {
+    pub addr: *mut c_void,
+    /// Offset of the first sample in bits.
+    pub first: c_uint,
+    /// Distance between samples in bits.
+    pub step: c_uint,
+}
+
+pub const ENOENT: c_int = 2;
+pub const ENODEV: c_int = 19;
 pub const EAGAIN: c_int = 11;
+pub const EINVAL: c_int = 22;
 pub const EPIPE: c_int = 32;
+pub const ESTRPIPE: c_int = 86;
 
 pub type snd_pcm_uframes_t = c_ulong;
 pub type snd_pcm_sframes_t = c_long;
@@ -130,6 +163,11 @@ pub const SND_PCM_STATE_SUSPENDED: ::libc::c_uint = 7;
 pub const SND_PCM_STATE_DISCONNECTED: ::libc::c_uint = 8;
 pub const SND_PCM_STATE_LAST: ::libc::c_uint = 8;
 
+pub type snd_mixer_selem_channel_id_t = c_int;
+pub const SND_MIXER_SCHN_FRONT_LEFT: c_int = 0;
+
+pub const SND_PCM_NONBLOCK: c_int = 1;
+
 pub const SND_CTL_NONBLOCK: c_int = 1;
 pub const SND_CTL_ASYNC: c_int = 2;
 pub const SND_CTL_READONLY: c_int = 4;
@@ -163,6 +201,7 @@ extern "C" {
 
     pub fn snd_pcm_hw_params(pcm: *mut snd_pcm_t, params: *mut snd_pcm_hw_params_t) -> c_int;
     pub fn snd_pcm_prepare(pcm: *mut snd_pcm_t) -> c_int;
+    pub fn snd_pcm_resume(pcm: *mut snd_pcm_t) -> c_int;
 
     pub fn snd_ctl_pcm_next_device(ctl: *mut snd_ctl_t, device: *mut c_int) -> c_int;
 
@@ -185,7 +224,54 @@ extern "C" {
         size: snd_pcm_uframes_t,
     ) -> snd_pcm_sframes_t;
 
+    pub fn snd_pcm_readn(
+        pcm: *mut snd_pcm_t,
+        bufs: *mut *mut c_void,
+        size: snd_pcm_uframes_t,
+    ) -> snd_pcm_sframes_t;
+
+    pub fn snd_pcm_writen(
+        pcm: *mut snd_pcm_t,
+        bufs: *mut *mut c_void,
+        size: snd_pcm_uframes_t,
+    ) -> snd_pcm_sframes_t;
+
     pub fn snd_pcm_wait(pcm: *mut snd_pcm_t, timeout: c_int) -> c_int;
+    pub fn snd_pcm_state(pcm: *mut snd_pcm_t) -> snd_pcm_state_t;
+    pub fn snd_pcm_nonblock(pcm: *mut snd_pcm_t, nonblock: c_int) -> c_int;
+
+    pub fn snd_pcm_poll_descriptors_count(pcm: *mut snd_pcm_t) -> c_int;
+    pub fn snd_pcm_poll_descriptors(pcm: *mut snd_pcm_t, pfds: *mut pollfd, space: c_uint)
+        -> c_int;
+    pub fn snd_pcm_poll_descriptors_revents(
+        pcm: *mut snd_pcm_t,
+        pfds: *mut pollfd,
+        nfds: c_uint,
+        revents: *mut c_ushort,
+    ) -> c_int;
+    pub fn snd_pcm_avail_update(pcm: *mut snd_pcm_t) -> snd_pcm_sframes_t;
+
+    pub fn snd_pcm_mmap_begin(
+        pcm: *mut snd_pcm_t,
+        areas: *mut *const snd_pcm_channel_area_t,
+        offset: *mut snd_pcm_uframes_t,
+        frames: *mut snd_pcm_uframes_t,
+    ) -> c_int;
+    pub fn snd_pcm_mmap_commit(
+        pcm: *mut snd_pcm_t,
+        offset: snd_pcm_uframes_t,
+        frames: snd_pcm_uframes_t,
+    ) -> snd_pcm_sframes_t;
+    pub fn snd_pcm_mmap_readi(
+        pcm: *mut snd_pcm_t,
+        buffer: *mut c_void,
+        size: snd_pcm_uframes_t,
+    ) -> snd_pcm_sframes_t;
+    pub fn snd_pcm_mmap_writei(
+        pcm: *mut snd_pcm_t,
+        buffer: *const c_void,
+        size: snd_pcm_uframes_t,
+    ) -> snd_pcm_sframes_t;
 
     pub fn snd_pcm_info_get_device(info: *const snd_pcm_info_t) -> c_uint;
     pub fn snd_pcm_info_get_id(info: *const snd_pcm_info_t) -> *const c_char;
@@ -229,6 +315,24 @@ extern "C" {
         dir: *mut c_int,
     ) -> c_int;
 
+    pub fn snd_pcm_hw_params_set_period_size_near(
+        pcm: *mut snd_pcm_t,
+        params: *mut snd_pcm_hw_params_t,
+        val: *mut snd_pcm_uframes_t,
+        dir: *mut c_int,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_set_periods_near(
+        pcm: *mut snd_pcm_t,
+        params: *mut snd_pcm_hw_params_t,
+        val: *mut c_uint,
+        dir: *mut c_int,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_set_buffer_size_near(
+        pcm: *mut snd_pcm_t,
+        params: *mut snd_pcm_hw_params_t,
+        val: *mut snd_pcm_uframes_t,
+    ) -> c_int;
+
     pub fn snd_pcm_hw_params_get_period_size(
         params: *const snd_pcm_hw_params_t,
         val: *mut snd_pcm_uframes_t,
@@ -243,6 +347,52 @@ extern "C" {
         val: *mut c_uint,
         dir: *mut c_int,
     ) -> c_int;
+    pub fn snd_pcm_hw_params_get_channels_min(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut c_uint,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_get_channels_max(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut c_uint,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_get_rate_min(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut c_uint,
+        dir: *mut c_int,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_get_rate_max(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut c_uint,
+        dir: *mut c_int,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_get_period_size_min(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut snd_pcm_uframes_t,
+        dir: *mut c_int,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_get_period_size_max(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut snd_pcm_uframes_t,
+        dir: *mut c_int,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_get_periods_min(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut c_uint,
+        dir: *mut c_int,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_get_periods_max(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut c_uint,
+        dir: *mut c_int,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_get_buffer_size_min(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut snd_pcm_uframes_t,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_get_buffer_size_max(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut snd_pcm_uframes_t,
+    ) -> c_int;
 
     pub fn snd_pcm_sw_params_malloc(ptr: *mut *mut snd_pcm_sw_params_t) -> c_int;
     pub fn snd_pcm_sw_params_free(ptr: *mut snd_pcm_sw_params_t);
@@ -268,6 +418,120 @@ extern "C" {
     pub fn snd_pcm_status(pcm: *mut snd_pcm_t, status: *mut snd_pcm_status_t) -> c_int;
     pub fn snd_pcm_status_get_state(status: *const snd_pcm_status_t) -> snd_pcm_state_t;
 
+    pub fn snd_mixer_open(mixer: *mut *mut snd_mixer_t, mode: c_int) -> c_int;
+    pub fn snd_mixer_close(mixer: *mut snd_mixer_t) -> c_int;
+    pub fn snd_mixer_attach(mixer: *mut snd_mixer_t, name: *const c_char) -> c_int;
+    pub fn snd_mixer_selem_register(
+        mixer: *mut snd_mixer_t,
+        options: *mut snd_mixer_selem_regopt,
+        classp: *mut *mut snd_mixer_class_t,
+    ) -> c_int;
+    pub fn snd_mixer_load(mixer: *mut snd_mixer_t) -> c_int;
+    pub fn snd_mixer_handle_events(mixer: *mut snd_mixer_t) -> c_int;
+    pub fn snd_mixer_first_elem(mixer: *mut snd_mixer_t) -> *mut snd_mixer_elem_t;
+    pub fn snd_mixer_elem_next(elem: *mut snd_mixer_elem_t) -> *mut snd_mixer_elem_t;
+
+    pub fn snd_mixer_poll_descriptors_count(mixer: *mut snd_mixer_t) -> c_int;
+    pub fn snd_mixer_poll_descriptors(
+        mixer: *mut snd_mixer_t,
+        pfds: *mut pollfd,
+        space: c_uint,
+    ) -> c_int;
+    pub fn snd_mixer_poll_descriptors_revents(
+        mixer: *mut snd_mixer_t,
+        pfds: *mut pollfd,
+        nfds: c_uint,
+        revents: *mut c_ushort,
+    ) -> c_int;
+
+    pub fn snd_mixer_selem_get_name(elem: *mut snd_mixer_elem_t) -> *const c_char;
+    pub fn snd_mixer_selem_get_index(elem: *mut snd_mixer_elem_t) -> c_uint;
+    pub fn snd_mixer_selem_is_active(elem: *mut snd_mixer_elem_t) -> c_int;
+    pub fn snd_mixer_selem_has_playback_volume(elem: *mut snd_mixer_elem_t) -> c_int;
+    pub fn snd_mixer_selem_has_capture_volume(elem: *mut snd_mixer_elem_t) -> c_int;
+    pub fn snd_mixer_selem_has_playback_switch(elem: *mut snd_mixer_elem_t) -> c_int;
+    pub fn snd_mixer_selem_has_capture_switch(elem: *mut snd_mixer_elem_t) -> c_int;
+
+    pub fn snd_mixer_selem_get_playback_volume_range(
+        elem: *mut snd_mixer_elem_t,
+        min: *mut c_long,
+        max: *mut c_long,
+    ) -> c_int;
+    pub fn snd_mixer_selem_get_capture_volume_range(
+        elem: *mut snd_mixer_elem_t,
+        min: *mut c_long,
+        max: *mut c_long,
+    ) -> c_int;
+    pub fn snd_mixer_selem_get_playback_dB_range(
+        elem: *mut snd_mixer_elem_t,
+        min: *mut c_long,
+        max: *mut c_long,
+    ) -> c_int;
+    pub fn snd_mixer_selem_get_capture_dB_range(
+        elem: *mut snd_mixer_elem_t,
+        min: *mut c_long,
+        max: *mut c_long,
+    ) -> c_int;
+
+    pub fn snd_mixer_selem_get_playback_volume(
+        elem: *mut snd_mixer_elem_t,
+        channel: snd_mixer_selem_channel_id_t,
+        value: *mut c_long,
+    ) -> c_int;
+    pub fn snd_mixer_selem_get_capture_volume(
+        elem: *mut snd_mixer_elem_t,
+        channel: snd_mixer_selem_channel_id_t,
+        value: *mut c_long,
+    ) -> c_int;
+    pub fn snd_mixer_selem_set_playback_volume_all(
+        elem: *mut snd_mixer_elem_t,
+        value: c_long,
+    ) -> c_int;
+    pub fn snd_mixer_selem_set_capture_volume_all(
+        elem: *mut snd_mixer_elem_t,
+        value: c_long,
+    ) -> c_int;
+
+    pub fn snd_mixer_selem_get_playback_dB(
+        elem: *mut snd_mixer_elem_t,
+        channel: snd_mixer_selem_channel_id_t,
+        value: *mut c_long,
+    ) -> c_int;
+    pub fn snd_mixer_selem_get_capture_dB(
+        elem: *mut snd_mixer_elem_t,
+        channel: snd_mixer_selem_channel_id_t,
+        value: *mut c_long,
+    ) -> c_int;
+    pub fn snd_mixer_selem_set_playback_dB_all(
+        elem: *mut snd_mixer_elem_t,
+        value: c_long,
+        dir: c_int,
+    ) -> c_int;
+    pub fn snd_mixer_selem_set_capture_dB_all(
+        elem: *mut snd_mixer_elem_t,
+        value: c_long,
+        dir: c_int,
+    ) -> c_int;
+
+    pub fn snd_mixer_selem_get_playback_switch(
+        elem: *mut snd_mixer_elem_t,
+        channel: snd_mixer_selem_channel_id_t,
+        value: *mut c_int,
+    ) -> c_int;
+    pub fn snd_mixer_selem_get_capture_switch(
+        elem: *mut snd_mixer_elem_t,
+        channel: snd_mixer_selem_channel_id_t,
+        value: *mut c_int,
+    ) -> c_int;
+    pub fn snd_mixer_selem_set_playback_switch_all(
+        elem: *mut snd_mixer_elem_t,
+        value: c_int,
+    ) -> c_int;
+    pub fn snd_mixer_selem_set_capture_switch_all(
+        elem: *mut snd_mixer_elem_t,
+        value: c_int,
+    ) -> c_int;
+
     pub fn snd_ctl_pcm_info(ctl: *mut snd_ctl_t, info: *mut snd_pcm_info_t) -> c_int;
 
     pub fn snd_ctl_open(ctlp: *mut *mut snd_ctl_t, name: *const c_char, mode: c_int) -> c_int;
@@ -275,6 +539,14 @@ extern "C" {
 
     pub fn snd_card_next(rcard: *mut c_int) -> c_int;
 
+    pub fn snd_device_name_hint(
+        card: c_int,
+        iface: *const c_char,
+        hints: *mut *mut *mut c_void,
+    ) -> c_int;
+    pub fn snd_device_name_get_hint(hint: *const c_void, id: *const c_char) -> *mut c_char;
+    pub fn snd_device_name_free_hint(hints: *mut *mut c_void) -> c_int;
+
     pub fn snd_ctl_card_info_malloc(infop: *mut *mut snd_ctl_card_info_t) -> c_int;
     pub fn snd_ctl_card_info_free(info: *mut snd_ctl_card_info_t);
     pub fn snd_ctl_card_info(ctl: *mut snd_ctl_t, info: *mut snd_ctl_card_info_t) -> c_int;
@@ -0,0 +1,396 @@
+//! Simple mixer elements of a card: volumes and switches of its playback and capture.
+//! Changes made by others are picked up by `Mixer::handle_events` when its poll
+//! descriptors are ready.
+
+use super::{alsa_ffi, poll_interest, revents_to_ready, AlsaError, Stream};
+use crate::error::*;
+use libc::{c_int, c_long, c_uint};
+use std::ffi;
+use std::io;
+use std::marker::PhantomData;
+use std::ptr;
+
+/// ALSA gives dB in hundredths.
+const DB_SCALE: f64 = 100.0;
+
+pub struct Mixer {
+    raw_ptr: *mut alsa_ffi::snd_mixer_t,
+}
+
+/// A simple element, e.g. "Master" or "Capture". It lives as long as its mixer isn't
+/// borrowed mutably, see `Mixer::handle_events`.
+#[derive(Clone, Copy)]
+pub struct Element<'a> {
+    raw_ptr: *mut alsa_ffi::snd_mixer_elem_t,
+    _mixer: PhantomData<&'a Mixer>,
+}
+
+impl Mixer {
+    /// Opens the mixer of a card by its name, like "default" or "hw:0".
+    pub fn open(name: &str) -> Result<Self, Error> {
+        let c_name = ffi::CString::new(name)?;
+        let mut res = Self {
+            raw_ptr: ptr::null_mut(),
+        };
+        unsafe {
+            try_snd!(alsa_ffi::snd_mixer_open(&mut res.raw_ptr, 0));
+            try_snd!(
+                alsa_ffi::snd_mixer_attach(res.raw_ptr, c_name.as_ptr()),
+                format!("Attaching mixer to '{}'", name)
+            );
+            try_snd!(alsa_ffi::snd_mixer_selem_register(
+                res.raw_ptr,
+                ptr::null_mut(),
+                ptr::null_mut()
+            ));
+            try_snd!(alsa_ffi::snd_mixer_load(res.raw_ptr));
+        }
+        Ok(res)
+    }
+
+    pub fn elements(&self) -> Vec<Element<'_>> {
+        let mut res = Vec::new();
+        unsafe {
+            let mut elem = alsa_ffi::snd_mixer_first_elem(self.raw_ptr);
+            while !elem.is_null() {
+                res.push(Element {
+                    raw_ptr: elem,
+                    _mixer: PhantomData,
+                });
+                elem = alsa_ffi::snd_mixer_elem_next(elem);
+            }
+        }
+        res
+    }
+
+    /// The element of the name and index, the index tells apart the ones of the same name.
+    pub fn find(&self, name: &str, index: u32) -> Option<Element<'_>> {
+        self.elements()
+            .into_iter()
+            .find(|e| e.index() == index && e.name() == name)
+    }
+
+    /// Takes in the changes of the elements, to be called when a descriptor is ready.
+    /// Returns the number of events handled. Removed elements are freed, so none of them
+    /// may be borrowed meanwhile.
+    pub fn handle_events(&mut self) -> Result<u32, Error> {
+        let res = unsafe { try_snd!(alsa_ffi::snd_mixer_handle_events(self.raw_ptr)) };
+        Ok(res as u32)
+    }
+
+    /// The descriptors that get ready when the elements change.
+    pub fn poll_descriptors(&self) -> Result<Vec<libc::pollfd>, Error> {
+        Ok(self.raw_poll_descriptors()?)
+    }
+
+    /// What the polled `fds` mean for the mixer, readable when there are changes.
+    pub fn revents(&self, fds: &mut [libc::pollfd]) -> Result<mio::Ready, Error> {
+        let mut revents = 0;
+        unsafe {
+            try_snd!(alsa_ffi::snd_mixer_poll_descriptors_revents(
+                self.raw_ptr,
+                fds.as_mut_ptr(),
+                fds.len() as c_uint,
+                &mut revents,
+            ));
+        }
+        Ok(revents_to_ready(revents as libc::c_short))
+    }
+
+    fn raw_poll_descriptors(&self) -> Result<Vec<libc::pollfd>, AlsaError> {
+        unsafe {
+            let count = try_snd!(alsa_ffi::snd_mixer_poll_descriptors_count(self.raw_ptr));
+            let mut fds = vec![
+                libc::pollfd {
+                    fd: -1,
+                    events: 0,
+                    revents: 0,
+                };
+                count as usize
+            ];
+            let filled = try_snd!(alsa_ffi::snd_mixer_poll_descriptors(
+                self.raw_ptr,
+                fds.as_mut_ptr(),
+                count as c_uint
+            ));
+            fds.truncate(filled as usize);
+            Ok(fds)
+        }
+    }
+}
+impl Drop for Mixer {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.raw_ptr.is_null() {
+                alsa_ffi::snd_mixer_close(self.raw_ptr);
+            }
+        }
+    }
+}
+unsafe impl Send for Mixer {}
+
+/// Registers the descriptors of the mixer under the token, see `Mixer::handle_events`.
+impl mio::Evented for Mixer {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        _interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        for fd in self.raw_poll_descriptors()? {
+            mio::unix::EventedFd(&fd.fd).register(poll, token, poll_interest(&fd), opts)?;
+        }
+        Ok(())
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        _interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        for fd in self.raw_poll_descriptors()? {
+            mio::unix::EventedFd(&fd.fd).reregister(poll, token, poll_interest(&fd), opts)?;
+        }
+        Ok(())
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        for fd in self.raw_poll_descriptors()? {
+            mio::unix::EventedFd(&fd.fd).deregister(poll)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Element<'a> {
+    pub fn name(&self) -> String {
+        unsafe {
+            let name = alsa_ffi::snd_mixer_selem_get_name(self.raw_ptr);
+            ffi::CStr::from_ptr(name).to_string_lossy().into_owned()
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        unsafe { alsa_ffi::snd_mixer_selem_get_index(self.raw_ptr) as u32 }
+    }
+
+    /// Inactive elements are there, but have no effect now.
+    pub fn is_active(&self) -> bool {
+        unsafe { alsa_ffi::snd_mixer_selem_is_active(self.raw_ptr) != 0 }
+    }
+
+    pub fn has_volume(&self, stream: Stream) -> bool {
+        let res = unsafe {
+            match stream {
+                Stream::Playback => alsa_ffi::snd_mixer_selem_has_playback_volume(self.raw_ptr),
+                Stream::Capture => alsa_ffi::snd_mixer_selem_has_capture_volume(self.raw_ptr),
+            }
+        };
+        res != 0
+    }
+
+    pub fn has_switch(&self, stream: Stream) -> bool {
+        let res = unsafe {
+            match stream {
+                Stream::Playback => alsa_ffi::snd_mixer_selem_has_playback_switch(self.raw_ptr),
+                Stream::Capture => alsa_ffi::snd_mixer_selem_has_capture_switch(self.raw_ptr),
+            }
+        };
+        res != 0
+    }
+
+    /// The lowest and highest raw volume.
+    // `c_long` is only `i64` on 64 bits.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn volume_range(&self, stream: Stream) -> Result<(i64, i64), Error> {
+        self.check(self.has_volume(stream), stream, "volume")?;
+        let (mut min, mut max): (c_long, c_long) = (0, 0);
+        unsafe {
+            match stream {
+                Stream::Playback => try_snd!(alsa_ffi::snd_mixer_selem_get_playback_volume_range(
+                    self.raw_ptr,
+                    &mut min,
+                    &mut max
+                )),
+                Stream::Capture => try_snd!(alsa_ffi::snd_mixer_selem_get_capture_volume_range(
+                    self.raw_ptr,
+                    &mut min,
+                    &mut max
+                )),
+            };
+        }
+        Ok((min as i64, max as i64))
+    }
+
+    /// The lowest and highest volume in dB, for the elements that know it.
+    pub fn db_range(&self, stream: Stream) -> Result<(f64, f64), Error> {
+        self.check(self.has_volume(stream), stream, "volume")?;
+        let (mut min, mut max): (c_long, c_long) = (0, 0);
+        unsafe {
+            match stream {
+                Stream::Playback => try_snd!(alsa_ffi::snd_mixer_selem_get_playback_dB_range(
+                    self.raw_ptr,
+                    &mut min,
+                    &mut max
+                )),
+                Stream::Capture => try_snd!(alsa_ffi::snd_mixer_selem_get_capture_dB_range(
+                    self.raw_ptr,
+                    &mut min,
+                    &mut max
+                )),
+            };
+        }
+        Ok((min as f64 / DB_SCALE, max as f64 / DB_SCALE))
+    }
+
+    /// The raw volume of the first channel.
+    // `c_long` is only `i64` on 64 bits.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn volume(&self, stream: Stream) -> Result<i64, Error> {
+        self.check(self.has_volume(stream), stream, "volume")?;
+        let mut value: c_long = 0;
+        unsafe {
+            match stream {
+                Stream::Playback => try_snd!(alsa_ffi::snd_mixer_selem_get_playback_volume(
+                    self.raw_ptr,
+                    alsa_ffi::SND_MIXER_SCHN_FRONT_LEFT,
+                    &mut value
+                )),
+                Stream::Capture => try_snd!(alsa_ffi::snd_mixer_selem_get_capture_volume(
+                    self.raw_ptr,
+                    alsa_ffi::SND_MIXER_SCHN_FRONT_LEFT,
+                    &mut value
+                )),
+            };
+        }
+        Ok(value as i64)
+    }
+
+    /// Sets the raw volume of all the channels.
+    pub fn set_volume(&self, stream: Stream, value: i64) -> Result<(), Error> {
+        self.check(self.has_volume(stream), stream, "volume")?;
+        unsafe {
+            match stream {
+                Stream::Playback => try_snd!(alsa_ffi::snd_mixer_selem_set_playback_volume_all(
+                    self.raw_ptr,
+                    value as c_long
+                )),
+                Stream::Capture => try_snd!(alsa_ffi::snd_mixer_selem_set_capture_volume_all(
+                    self.raw_ptr,
+                    value as c_long
+                )),
+            };
+        }
+        Ok(())
+    }
+
+    /// Sets the volume of all the channels to a `fraction` of the raw range, 0 to 1.
+    pub fn set_volume_fraction(&self, stream: Stream, fraction: f64) -> Result<(), Error> {
+        let (min, max) = self.volume_range(stream)?;
+        let fraction = fraction.clamp(0.0, 1.0);
+        let value = min + ((max - min) as f64 * fraction).round() as i64;
+        self.set_volume(stream, value)
+    }
+
+    /// The volume of the first channel in dB.
+    pub fn db(&self, stream: Stream) -> Result<f64, Error> {
+        self.check(self.has_volume(stream), stream, "volume")?;
+        let mut value: c_long = 0;
+        unsafe {
+            match stream {
+                Stream::Playback => try_snd!(alsa_ffi::snd_mixer_selem_get_playback_dB(
+                    self.raw_ptr,
+                    alsa_ffi::SND_MIXER_SCHN_FRONT_LEFT,
+                    &mut value
+                )),
+                Stream::Capture => try_snd!(alsa_ffi::snd_mixer_selem_get_capture_dB(
+                    self.raw_ptr,
+                    alsa_ffi::SND_MIXER_SCHN_FRONT_LEFT,
+                    &mut value
+                )),
+            };
+        }
+        Ok(value as f64 / DB_SCALE)
+    }
+
+    /// Sets all the channels to the nearest volume not above `db`.
+    pub fn set_db(&self, stream: Stream, db: f64) -> Result<(), Error> {
+        self.check(self.has_volume(stream), stream, "volume")?;
+        let value = (db * DB_SCALE).round() as c_long;
+        // Rounding down, so that the volume never ends up louder than asked.
+        let dir: c_int = -1;
+        unsafe {
+            match stream {
+                Stream::Playback => try_snd!(alsa_ffi::snd_mixer_selem_set_playback_dB_all(
+                    self.raw_ptr,
+                    value,
+                    dir
+                )),
+                Stream::Capture => try_snd!(alsa_ffi::snd_mixer_selem_set_capture_dB_all(
+                    self.raw_ptr,
+                    value,
+                    dir
+                )),
+            };
+        }
+        Ok(())
+    }
+
+    /// The switch of the first channel, `true` is unmuted.
+    pub fn switch(&self, stream: Stream) -> Result<bool, Error> {
+        self.check(self.has_switch(stream), stream, "switch")?;
+        let mut value: c_int = 0;
+        unsafe {
+            match stream {
+                Stream::Playback => try_snd!(alsa_ffi::snd_mixer_selem_get_playback_switch(
+                    self.raw_ptr,
+                    alsa_ffi::SND_MIXER_SCHN_FRONT_LEFT,
+                    &mut value
+                )),
+                Stream::Capture => try_snd!(alsa_ffi::snd_mixer_selem_get_capture_switch(
+                    self.raw_ptr,
+                    alsa_ffi::SND_MIXER_SCHN_FRONT_LEFT,
+                    &mut value
+                )),
+            };
+        }
+        Ok(value != 0)
+    }
+
+    /// Sets the switch of all the channels, `true` is unmuted.
+    pub fn set_switch(&self, stream: Stream, on: bool) -> Result<(), Error> {
+        self.check(self.has_switch(stream), stream, "switch")?;
+        unsafe {
+            match stream {
+                Stream::Playback => try_snd!(alsa_ffi::snd_mixer_selem_set_playback_switch_all(
+                    self.raw_ptr,
+                    on as c_int
+                )),
+                Stream::Capture => try_snd!(alsa_ffi::snd_mixer_selem_set_capture_switch_all(
+                    self.raw_ptr,
+                    on as c_int
+                )),
+            };
+        }
+        Ok(())
+    }
+
+    fn check(&self, has: bool, stream: Stream, what: &str) -> Result<(), Error> {
+        if has {
+            return Ok(());
+        }
+        Err(AlsaError::new(
+            -alsa_ffi::EINVAL,
+            format!(
+                "Mixer element '{}' has no {:?} {}",
+                self.name(),
+                stream,
+                what
+            ),
+        )
+        .into())
+    }
+}
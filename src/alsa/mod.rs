@@ -7,9 +7,18 @@ use crate::ffmpeg;
 use alsa_ffi::{snd_pcm_sframes_t, snd_pcm_uframes_t};
 use libc::{c_int, c_uint, c_void};
 use std::borrow::Cow;
+use std::cell::Cell;
 use std::cmp;
 use std::ffi;
+use std::io;
+use std::ops::RangeInclusive;
 use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a suspended device is asked to resume.
+const RESUME_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Debug)]
 pub struct AlsaError {
@@ -35,6 +44,8 @@ macro_rules! try_snd {
     }};
 }
 
+pub mod mixer;
+
 impl AlsaError {
     fn new<S: Into<Cow<'static, str>>>(errnum: i32, context: S) -> Self {
         Self {
@@ -45,6 +56,13 @@ impl AlsaError {
     }
 }
 
+impl AlsaError {
+    /// The device went away, it has to be opened anew once it's back.
+    pub fn is_disconnected(&self) -> bool {
+        self.errnum == -alsa_ffi::ENODEV
+    }
+}
+
 impl std::fmt::Display for AlsaError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         write!(
@@ -59,6 +77,11 @@ impl std::fmt::Display for AlsaError {
     }
 }
 impl std::error::Error for AlsaError {}
+impl From<AlsaError> for io::Error {
+    fn from(e: AlsaError) -> Self {
+        io::Error::new(io::Error::from_raw_os_error(-e.errnum).kind(), e)
+    }
+}
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Params {
@@ -71,7 +94,19 @@ pub struct Params {
 pub enum Format {
     U8,
     S16Le,
+    S16Be,
+    /// 24 bits in the low bytes of 4.
+    S24Le,
+    S24Be,
+    /// 24 bits packed in 3 bytes.
+    S24_3Le,
+    S24_3Be,
+    S32Le,
+    S32Be,
     FloatLe,
+    FloatBe,
+    Float64Le,
+    Float64Be,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -80,11 +115,122 @@ pub enum Stream {
     Capture,
 }
 
+/// How the frames get to and from the device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    /// Copied through `snd_pcm_readi`/`snd_pcm_writei`.
+    ReadWrite,
+    /// The ring buffer is mapped, `SndPcm::mmap_begin` lends it without copying.
+    /// `read_interleaved` and `write_interleaved` still work, copying from the map.
+    Mmap,
+    /// Copied through `snd_pcm_readn`/`snd_pcm_writen`, a buffer per channel.
+    /// Only `read_noninterleaved` and `write_noninterleaved` work.
+    NonInterleaved,
+}
+
+/// Period and buffer sizes to ask the hardware for, it settles on the nearest it supports.
+/// The unset ones are left to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BufferConfig {
+    pub period: Option<Size>,
+    /// Periods in the buffer.
+    pub periods: Option<u32>,
+    /// Limited to the largest buffer the hardware has.
+    pub buffer: Option<Size>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Size {
+    Time(Duration),
+    Frames(usize),
+}
+
+/// How to open a `SndPcm` besides its `Params`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub access: Access,
+    pub buffer: BufferConfig,
+    pub recovery: RecoveryPolicy,
+}
+
+/// How reads and writes get over overruns, underruns and suspends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecoveryPolicy {
+    /// Recoveries in a row, without a frame transferred between, before giving up.
+    pub max_attempts: u32,
+    /// How long to wait for a suspended device to resume before preparing it anew.
+    pub resume_timeout: Duration,
+}
+
+/// Breaks of the stream the PCM recovered from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct XrunStats {
+    /// Overruns of the capture or underruns of the playback.
+    pub xruns: u32,
+    pub last_xrun: Option<Instant>,
+    pub suspends: u32,
+    pub last_suspend: Option<Instant>,
+}
+
+/// The sizes the hardware settled on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BufferSizes {
+    pub period_frames: usize,
+    pub buffer_frames: usize,
+    pub period_time: Duration,
+    pub buffer_time: Duration,
+}
+
+/// What a PCM supports in a direction, before anything is set up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub stream: Stream,
+    /// In the order they are preferred when the asked one is missing.
+    pub formats: Vec<Format>,
+    pub channels: RangeInclusive<u32>,
+    pub rate: RangeInclusive<u32>,
+    pub period_frames: RangeInclusive<usize>,
+    pub periods: RangeInclusive<u32>,
+    pub buffer_frames: RangeInclusive<usize>,
+}
+
+/// A PCM to open by its name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PcmHint {
+    pub name: String,
+    /// Readable by a human, may span lines.
+    pub description: Option<String>,
+    /// `None` when it does both.
+    pub stream: Option<Stream>,
+}
+
 pub struct SndPcm {
     raw_ptr: *mut alsa_ffi::snd_pcm_t,
     stream: Stream,
     params: Params,
+    access: Access,
     buffer_size: snd_pcm_uframes_t,
+    buffer_sizes: BufferSizes,
+    recovery: RecoveryPolicy,
+    /// Recoveries since the last frame transferred.
+    recoveries: Cell<u32>,
+    xrun_stats: Cell<XrunStats>,
+}
+
+/// Frames of the ring buffer lent by `SndPcm::mmap_begin`: the captured ones to read or
+/// the free ones to fill for playback. They are given back with `commit`.
+/// The PCM stays mutably borrowed meanwhile, so no other transfer can hand out the frames.
+pub struct MmapArea<'a> {
+    pcm: &'a mut SndPcm,
+    offset: snd_pcm_uframes_t,
+    frames: snd_pcm_uframes_t,
+    data: &'a mut [u8],
+}
+
+impl Params {
+    pub fn bytes_per_frame(&self) -> usize {
+        self.format.bytes_per_sample() * self.channels as usize
+    }
 }
 
 impl Into<ffmpeg::AudioParams> for Params {
@@ -95,63 +241,84 @@ impl Into<ffmpeg::AudioParams> for Params {
         }
     }
 }
+/// The formats ffmpeg lacks map to FloatLe, their samples have to be converted
+/// with `Format::to_float_le` first. Its formats are all interleaved, planar samples
+/// are given to it through `Format::interleave`.
 impl Into<ffmpeg::AudioSampleFormat> for Format {
     fn into(self) -> ffmpeg::AudioSampleFormat {
         match self {
             Format::U8 => ffmpeg::AudioSampleFormat::U8,
             Format::S16Le => ffmpeg::AudioSampleFormat::S16Le,
-            Format::FloatLe => ffmpeg::AudioSampleFormat::FloatLe,
+            _ => ffmpeg::AudioSampleFormat::FloatLe,
         }
     }
 }
 
 impl SndPcm {
     pub fn open(name: String, stream: Stream, params: Params) -> Result<Self, Error> {
-        unsafe {
-            let mut res: *mut alsa_ffi::snd_pcm_t = ptr::null_mut();
-
-            let name = ffi::CString::new(name)?;
-
-            try_snd!(alsa_ffi::snd_pcm_open(
-                &mut res,
-                name.as_ptr(),
-                stream.to_ffi(),
-                0
-            ));
-
-            let mut res = Self {
-                raw_ptr: res,
-                stream,
-                params,
-                buffer_size: 0,
-            };
-
-            let mut hw_params = SndPcmHwParams::new()?;
-            hw_params.set_any(&res)?;
-            hw_params.set_interleaved(&res)?;
-            res.params.format = hw_params.set_format(&res, params.format)?;
-            hw_params.set_channels(&res, params.channels)?;
-            res.params.rate = hw_params.set_rate_near(&res, params.rate)?;
-
-            let buffer_time = cmp::min(hw_params.get_buffer_time_max()?, 500000);
-            let period_time = buffer_time / 4;
-
-            hw_params.set_period_time_near(&res, period_time)?;
-            hw_params.set_buffer_time_near(&res, buffer_time)?;
+        Self::open_with_config(name, stream, params, Config::default())
+    }
+
+    pub fn open_with_config(
+        name: String,
+        stream: Stream,
+        params: Params,
+        config: Config,
+    ) -> Result<Self, Error> {
+        let mut res = Self::open_raw(name, stream, params, config, 0)?;
+
+        let mut hw_params = SndPcmHwParams::new()?;
+        hw_params.set_any(&res)?;
+        hw_params.set_access(&res, config.access)?;
+        res.params.format = hw_params.set_format(&res, params.format)?;
+        hw_params.set_channels(&res, params.channels)?;
+        res.params.rate = hw_params.set_rate_near(&res, params.rate)?;
+        hw_params.set_buffer_config(&res, config.buffer)?;
+
+        res.set_hw_params(&hw_params)?;
+
+        let period_frames = hw_params.get_period_size_in_frames()? as usize;
+        let buffer_frames = hw_params.get_buffer_size_in_frames()? as usize;
+        let frames_to_time =
+            |frames| Duration::from_micros(frames as u64 * 1_000_000 / u64::from(res.params.rate));
+        res.buffer_sizes = BufferSizes {
+            period_frames,
+            buffer_frames,
+            period_time: frames_to_time(period_frames),
+            buffer_time: frames_to_time(buffer_frames),
+        };
 
-            res.set_hw_params(&hw_params)?;
+        let mut sw_params = SndPcmSwParams::new()?;
+        sw_params.set_current(&res)?;
+        sw_params.set_start_threshold(&res, res.calc_start_threshold(&hw_params)?)?;
 
-            let mut sw_params = SndPcmSwParams::new()?;
-            sw_params.set_current(&res)?;
-            sw_params.set_start_threshold(&res, res.calc_start_threshold(&hw_params)?)?;
+        res.set_sw_params(&sw_params)?;
 
-            res.set_sw_params(&sw_params)?;
+        res.buffer_size = hw_params.get_period_size_in_frames()?;
+        dbg!(res.buffer_size);
 
-            res.buffer_size = hw_params.get_period_size_in_frames()?;
-            dbg!(res.buffer_size);
+        Ok(res)
+    }
 
-            Ok(res)
-        }
+    /// What the device `name` supports for `stream`. It's opened without waiting, so a busy
+    /// device fails right away.
+    pub fn probe(name: String, stream: Stream) -> Result<Capabilities, Error> {
+        // Nothing is set up, the params are never used.
+        let params = Params {
+            format: Format::S16Le,
+            channels: 0,
+            rate: 0,
+        };
+        let pcm = Self::open_raw(
+            name,
+            stream,
+            params,
+            Config::default(),
+            alsa_ffi::SND_PCM_NONBLOCK,
+        )?;
+        let mut hw_params = SndPcmHwParams::new()?;
+        hw_params.set_any(&pcm)?;
+        hw_params.get_capabilities(&pcm, stream)
     }
 
     pub fn info(&self) -> Result<SndPcmInfo, Error> {
@@ -175,20 +342,25 @@ impl SndPcm {
             let empty_buffer_part = &mut buffer[total_bytes_read..];
 
             let res = unsafe {
-                alsa_ffi::snd_pcm_readi(
-                    self.raw_ptr,
-                    empty_buffer_part.as_mut_ptr() as *mut c_void,
-                    frames_to_read,
-                )
+                let buf = empty_buffer_part.as_mut_ptr() as *mut c_void;
+                match self.access {
+                    Access::ReadWrite => alsa_ffi::snd_pcm_readi(self.raw_ptr, buf, frames_to_read),
+                    Access::Mmap => alsa_ffi::snd_pcm_mmap_readi(self.raw_ptr, buf, frames_to_read),
+                    Access::NonInterleaved => -alsa_ffi::EINVAL as snd_pcm_sframes_t,
+                }
             };
             if res == -alsa_ffi::EAGAIN as snd_pcm_sframes_t {
                 self.wait_for_data(100)?;
                 continue;
+            } else if is_recoverable(res) {
+                self.recover(res as c_int)?;
+                continue;
             }
             if res < 0 {
                 dbg!(res);
             }
             try_snd!(res);
+            self.recoveries.set(0);
 
             let bytes_read = self.frames_qty_to_bytes_qty(res);
 
@@ -223,6 +395,164 @@ impl SndPcm {
         Ok(())
     }
 
+    /// Reads the same number of frames into each channel's buffer, as many as the shortest
+    /// one holds. Returns the number of frames.
+    pub fn read_noninterleaved(&self, channels: &mut [&mut [u8]]) -> Result<usize, Error> {
+        let total_frames = self.planar_frames(channels.iter().map(|c| c.len()))?;
+        let sample_size = self.params.format.bytes_per_sample();
+
+        let mut frames_read = 0;
+        while frames_read < total_frames {
+            let mut bufs: Vec<*mut c_void> = channels
+                .iter_mut()
+                .map(|c| c[frames_read * sample_size..].as_mut_ptr() as *mut c_void)
+                .collect();
+            let res = unsafe {
+                alsa_ffi::snd_pcm_readn(
+                    self.raw_ptr,
+                    bufs.as_mut_ptr(),
+                    (total_frames - frames_read) as snd_pcm_uframes_t,
+                )
+            };
+            if res == -alsa_ffi::EAGAIN as snd_pcm_sframes_t {
+                self.wait_for_data(100)?;
+                continue;
+            } else if is_recoverable(res) {
+                self.recover(res as c_int)?;
+                continue;
+            }
+            try_snd!(res);
+            self.recoveries.set(0);
+
+            frames_read += res as usize;
+            if frames_read < total_frames {
+                self.wait_for_data(100)?;
+            }
+        }
+
+        Ok(total_frames)
+    }
+
+    /// Writes the same number of frames from each channel's buffer, as many as the shortest
+    /// one holds.
+    pub fn write_noninterleaved(&self, channels: &[&[u8]]) -> Result<(), Error> {
+        let total_frames = self.planar_frames(channels.iter().map(|c| c.len()))?;
+        let sample_size = self.params.format.bytes_per_sample();
+
+        let mut frames_written = 0;
+        while frames_written < total_frames {
+            let mut bufs: Vec<*mut c_void> = channels
+                .iter()
+                .map(|c| c[frames_written * sample_size..].as_ptr() as *mut c_void)
+                .collect();
+            let res = unsafe {
+                alsa_ffi::snd_pcm_writen(
+                    self.raw_ptr,
+                    bufs.as_mut_ptr(),
+                    (total_frames - frames_written) as snd_pcm_uframes_t,
+                )
+            };
+            if res == -alsa_ffi::EAGAIN as snd_pcm_sframes_t {
+                self.wait_for_data(100)?;
+                continue;
+            } else if is_recoverable(res) {
+                self.recover(res as c_int)?;
+                continue;
+            }
+            try_snd!(res);
+            self.recoveries.set(0);
+
+            frames_written += res as usize;
+            if frames_written < total_frames {
+                self.wait_for_data(100)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the frames available now, without waiting. Returns 0 when there are none,
+    /// and after recovering from an overrun.
+    pub fn try_read_interleaved(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let frames = self.bytes_qty_to_frames_qty(buffer.len());
+        let res = unsafe {
+            let buf = buffer.as_mut_ptr() as *mut c_void;
+            match self.access {
+                Access::ReadWrite => alsa_ffi::snd_pcm_readi(self.raw_ptr, buf, frames),
+                Access::Mmap => alsa_ffi::snd_pcm_mmap_readi(self.raw_ptr, buf, frames),
+                Access::NonInterleaved => -alsa_ffi::EINVAL as snd_pcm_sframes_t,
+            }
+        };
+        if res == -alsa_ffi::EAGAIN as snd_pcm_sframes_t {
+            return Ok(0);
+        } else if is_recoverable(res) {
+            self.recover(res as c_int)?;
+            return Ok(0);
+        }
+        try_snd!(res, "Reading samples");
+        self.recoveries.set(0);
+
+        Ok(self.frames_qty_to_bytes_qty(res))
+    }
+
+    /// Starts a prepared PCM. Reads and writes do it on their own, polling doesn't.
+    pub fn start(&self) -> Result<(), Error> {
+        if self.state() != PcmState::Prepared {
+            return Ok(());
+        }
+        unsafe {
+            try_snd!(alsa_ffi::snd_pcm_start(self.raw_ptr));
+        }
+        Ok(())
+    }
+
+    /// In the non-blocking mode reads and writes fail with EAGAIN instead of waiting,
+    /// see `try_read_interleaved`.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+        unsafe {
+            try_snd!(alsa_ffi::snd_pcm_nonblock(
+                self.raw_ptr,
+                nonblocking as c_int
+            ));
+        }
+        Ok(())
+    }
+
+    /// The descriptors to poll for the PCM, with the events to poll them for.
+    /// Plugins may give several, and ones that aren't of the device.
+    pub fn poll_descriptors(&self) -> Result<Vec<libc::pollfd>, Error> {
+        Ok(self.raw_poll_descriptors()?)
+    }
+
+    /// What the polled `fds` mean for the PCM itself: readable for the frames to capture,
+    /// writable for the space to play to.
+    pub fn revents(&self, fds: &mut [libc::pollfd]) -> Result<mio::Ready, Error> {
+        let mut revents = 0;
+        unsafe {
+            try_snd!(alsa_ffi::snd_pcm_poll_descriptors_revents(
+                self.raw_ptr,
+                fds.as_mut_ptr(),
+                fds.len() as c_uint,
+                &mut revents,
+            ));
+        }
+
+        Ok(revents_to_ready(revents as libc::c_short))
+    }
+
+    /// Polls the descriptors without waiting and returns their `revents`.
+    /// An event on the token the PCM is registered with may be spurious, check it with this.
+    pub fn ready(&self) -> Result<mio::Ready, Error> {
+        let mut fds = self.poll_descriptors()?;
+        let res = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 0) };
+        if res < 0 {
+            return Err(
+                IoError::new("polling alsa descriptors", io::Error::last_os_error()).into(),
+            );
+        }
+        self.revents(&mut fds)
+    }
+
     /// Stops a PCM preserving pending frames.
     pub fn stop(&self) -> Result<(), Error> {
         unsafe {
@@ -251,6 +581,115 @@ impl SndPcm {
         self.params
     }
 
+    pub fn get_access(&self) -> Access {
+        self.access
+    }
+
+    pub fn get_buffer_sizes(&self) -> BufferSizes {
+        self.buffer_sizes
+    }
+
+    pub fn get_xrun_stats(&self) -> XrunStats {
+        self.xrun_stats.get()
+    }
+
+    /// Transfers fail with an error `is_device_disconnected` after this.
+    pub fn is_disconnected(&self) -> bool {
+        self.state() == PcmState::Disconnected
+    }
+
+    /// Lends up to `max_frames` of the ring buffer, waiting until there are some.
+    /// The PCM has to be opened with `Access::Mmap`.
+    pub fn mmap_begin(&mut self, max_frames: usize) -> Result<MmapArea<'_>, Error> {
+        if self.access != Access::Mmap {
+            return Err(AlsaError::new(
+                -alsa_ffi::EINVAL,
+                "Mapping the buffer of a PCM opened without Access::Mmap",
+            )
+            .into());
+        }
+
+        loop {
+            // Unlike snd_pcm_readi, nothing starts the capture on its own.
+            if self.stream == Stream::Capture {
+                self.start()?;
+            }
+
+            let avail = unsafe { alsa_ffi::snd_pcm_avail_update(self.raw_ptr) };
+            if is_recoverable(avail) {
+                self.recover(avail as c_int)?;
+                continue;
+            }
+            try_snd!(avail, "Getting available frames");
+            if avail > 0 {
+                break;
+            }
+            self.wait_for_data(100)?;
+        }
+
+        let mut areas = ptr::null();
+        let mut offset = 0;
+        let mut frames = max_frames as snd_pcm_uframes_t;
+        unsafe {
+            try_snd!(alsa_ffi::snd_pcm_mmap_begin(
+                self.raw_ptr,
+                &mut areas,
+                &mut offset,
+                &mut frames
+            ));
+
+            // The access is interleaved, so the first channel's area spans all of them.
+            let area = &*areas;
+            let start = (area.first as usize + offset as usize * area.step as usize) / 8;
+            let data = slice::from_raw_parts_mut(
+                (area.addr as *mut u8).add(start),
+                frames as usize * self.bytes_per_frame(),
+            );
+            Ok(MmapArea {
+                pcm: self,
+                offset,
+                frames,
+                data,
+            })
+        }
+    }
+
+    fn open_raw(
+        name: String,
+        stream: Stream,
+        params: Params,
+        config: Config,
+        mode: c_int,
+    ) -> Result<Self, Error> {
+        let mut raw_ptr: *mut alsa_ffi::snd_pcm_t = ptr::null_mut();
+        let name = ffi::CString::new(name)?;
+        unsafe {
+            try_snd!(alsa_ffi::snd_pcm_open(
+                &mut raw_ptr,
+                name.as_ptr(),
+                stream.to_ffi(),
+                mode
+            ));
+        }
+
+        Ok(Self {
+            raw_ptr,
+            stream,
+            params,
+            access: config.access,
+            buffer_size: 0,
+            buffer_sizes: BufferSizes {
+                period_frames: 0,
+                buffer_frames: 0,
+                period_time: Duration::from_secs(0),
+                buffer_time: Duration::from_secs(0),
+            },
+            recovery: config.recovery,
+            recoveries: Cell::new(0),
+            xrun_stats: Cell::new(XrunStats::default()),
+        })
+    }
+
     fn write_i(&self, buffer: &[u8]) -> Result<(), Error> {
         let mut bytes_to_write = buffer.len();
 
@@ -261,20 +700,27 @@ impl SndPcm {
             let buffer_part_to_write = &buffer[total_bytes_written..];
 
             let res = unsafe {
-                alsa_ffi::snd_pcm_writei(
-                    self.raw_ptr,
-                    buffer_part_to_write.as_ptr() as *mut c_void,
-                    frames_to_write,
-                )
+                let buf = buffer_part_to_write.as_ptr() as *const c_void;
+                match self.access {
+                    Access::ReadWrite => {
+                        alsa_ffi::snd_pcm_writei(self.raw_ptr, buf, frames_to_write)
+                    }
+                    Access::Mmap => {
+                        alsa_ffi::snd_pcm_mmap_writei(self.raw_ptr, buf, frames_to_write)
+                    }
+                    Access::NonInterleaved => -alsa_ffi::EINVAL as snd_pcm_sframes_t,
+                }
             };
             if res == -alsa_ffi::EAGAIN as snd_pcm_sframes_t {
                 self.wait_for_data(100)?;
                 continue;
-            } else if res == -alsa_ffi::EPIPE as snd_pcm_sframes_t {
-                self.xrun(res as c_int)?;
+            } else if is_recoverable(res) {
+                self.recover(res as c_int)?;
                 //self.wait_for_data(100)?;
                 continue;
             }
+            try_snd!(res, "Writing samples");
+            self.recoveries.set(0);
 
             let bytes_written = self.frames_qty_to_bytes_qty(res);
 
@@ -306,7 +752,7 @@ impl SndPcm {
     }
 
     fn bytes_per_frame(&self) -> usize {
-        self.params.format.bytes_per_sample() * self.params.channels as usize
+        self.params.bytes_per_frame()
     }
 
     fn bytes_qty_to_frames_qty(&self, bytes_qty: usize) -> alsa_ffi::snd_pcm_uframes_t {
@@ -318,22 +764,115 @@ impl SndPcm {
     }
 
     fn wait_for_data(&self, timeout: i32) -> Result<(), Error> {
-        unsafe {
-            try_snd!(alsa_ffi::snd_pcm_wait(self.raw_ptr, timeout as c_int));
+        let res = unsafe { alsa_ffi::snd_pcm_wait(self.raw_ptr, timeout as c_int) };
+        if is_recoverable(res as snd_pcm_sframes_t) {
+            return self.recover(res);
         }
+        try_snd!(res);
 
         Ok(())
     }
 
-    fn xrun(&self, errnum: c_int) -> Result<(), Error> {
-        let pcm_status = SndPcmStatus::new(self)?;
-        if pcm_status.get_state() == PcmState::XRun {
+    /// Gets over the overrun, underrun (EPIPE) or suspend (ESTRPIPE) `errnum` tells of,
+    /// as the `RecoveryPolicy` allows.
+    fn recover(&self, errnum: c_int) -> Result<(), Error> {
+        let attempts = self.recoveries.get() + 1;
+        if attempts > self.recovery.max_attempts {
+            return Err(AlsaError::new(
+                errnum,
+                format!("Giving up after {} recoveries in a row", attempts - 1),
+            )
+            .into());
+        }
+        self.recoveries.set(attempts);
+
+        let mut stats = self.xrun_stats.get();
+        if errnum == -alsa_ffi::ESTRPIPE {
+            eprintln!("The device is suspended, resuming");
+            stats.suspends += 1;
+            stats.last_suspend = Some(Instant::now());
+            self.xrun_stats.set(stats);
+            self.resume()?;
+        } else {
+            match SndPcmStatus::new(self)?.get_state() {
+                PcmState::XRun => (),
+                PcmState::Disconnected => {
+                    return Err(AlsaError::new(-alsa_ffi::ENODEV, "Device disconnected").into())
+                }
+                _ => return Err(AlsaError::new(errnum, "Transferring samples").into()),
+            }
             eprintln!("Underrun or Overrun are detected");
+            stats.xruns += 1;
+            stats.last_xrun = Some(Instant::now());
+            self.xrun_stats.set(stats);
             self.prepare()?;
-            Ok(())
-        } else {
-            Err(AlsaError::new(errnum as i32, "Writing samples").into())
         }
+
+        // Only a read starts the capture again, and a poll would wait for it forever.
+        if self.stream == Stream::Capture {
+            self.start()?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the device to wake up. The ones that can't resume, or take longer
+    /// than the policy allows, are prepared to start anew.
+    fn resume(&self) -> Result<(), Error> {
+        let started = Instant::now();
+        loop {
+            let res = unsafe { alsa_ffi::snd_pcm_resume(self.raw_ptr) };
+            if res == -alsa_ffi::EAGAIN && started.elapsed() < self.recovery.resume_timeout {
+                thread::sleep(RESUME_INTERVAL);
+                continue;
+            }
+            if res < 0 {
+                self.prepare()?;
+            }
+            return Ok(());
+        }
+    }
+
+    /// Frames fitting in all the per-channel buffers of the given lengths.
+    fn planar_frames<I: ExactSizeIterator<Item = usize>>(&self, lens: I) -> Result<usize, Error> {
+        if self.access != Access::NonInterleaved || lens.len() != self.params.channels as usize {
+            return Err(AlsaError::new(
+                -alsa_ffi::EINVAL,
+                format!(
+                    "Passing {} channel buffers to a PCM of {} channels with {:?} access",
+                    lens.len(),
+                    self.params.channels,
+                    self.access
+                ),
+            )
+            .into());
+        }
+        let sample_size = self.params.format.bytes_per_sample();
+        Ok(lens.map(|len| len / sample_size).min().unwrap_or(0))
+    }
+
+    fn raw_poll_descriptors(&self) -> Result<Vec<libc::pollfd>, AlsaError> {
+        unsafe {
+            let count = try_snd!(alsa_ffi::snd_pcm_poll_descriptors_count(self.raw_ptr));
+            let mut fds = vec![
+                libc::pollfd {
+                    fd: -1,
+                    events: 0,
+                    revents: 0,
+                };
+                count as usize
+            ];
+            let filled = try_snd!(alsa_ffi::snd_pcm_poll_descriptors(
+                self.raw_ptr,
+                fds.as_mut_ptr(),
+                count as c_uint
+            ));
+            fds.truncate(filled as usize);
+            Ok(fds)
+        }
+    }
+
+    fn state(&self) -> PcmState {
+        unsafe { PcmState::from_ffi(alsa_ffi::snd_pcm_state(self.raw_ptr)) }
     }
 
     fn prepare(&self) -> Result<(), Error> {
@@ -367,6 +906,176 @@ impl Drop for SndPcm {
 }
 unsafe impl Send for SndPcm {}
 
+/// Registers all the poll descriptors of the PCM under the token. The interest follows
+/// the events ALSA wants on each descriptor, so the one passed is ignored.
+/// The events may be spurious, see `SndPcm::ready`.
+impl mio::Evented for SndPcm {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        _interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        for fd in self.raw_poll_descriptors()? {
+            mio::unix::EventedFd(&fd.fd).register(poll, token, poll_interest(&fd), opts)?;
+        }
+        Ok(())
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        _interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        for fd in self.raw_poll_descriptors()? {
+            mio::unix::EventedFd(&fd.fd).reregister(poll, token, poll_interest(&fd), opts)?;
+        }
+        Ok(())
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        for fd in self.raw_poll_descriptors()? {
+            mio::unix::EventedFd(&fd.fd).deregister(poll)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the result of a transfer is an xrun or a suspend `SndPcm::recover` handles.
+fn is_recoverable(res: snd_pcm_sframes_t) -> bool {
+    res == -alsa_ffi::EPIPE as snd_pcm_sframes_t || res == -alsa_ffi::ESTRPIPE as snd_pcm_sframes_t
+}
+
+fn is_alsa_errnum(err: &Error, errnum: c_int) -> bool {
+    match err.get_repr() {
+        ErrorRepr::Alsa(err) => err.errnum == errnum,
+        _ => false,
+    }
+}
+
+fn c_str(s: &'static [u8]) -> *const libc::c_char {
+    debug_assert_eq!(s.last(), Some(&0));
+    s.as_ptr() as *const libc::c_char
+}
+
+/// Copies a string alsa allocated for the caller and frees it.
+unsafe fn take_c_string(raw: *mut libc::c_char) -> Option<String> {
+    if raw.is_null() {
+        return None;
+    }
+    let res = ffi::CStr::from_ptr(raw).to_string_lossy().into_owned();
+    libc::free(raw as *mut c_void);
+    Some(res)
+}
+
+fn revents_to_ready(revents: libc::c_short) -> mio::Ready {
+    let mut ready = mio::Ready::empty();
+    if revents & libc::POLLIN != 0 {
+        ready |= mio::Ready::readable();
+    }
+    if revents & libc::POLLOUT != 0 {
+        ready |= mio::Ready::writable();
+    }
+    if revents & libc::POLLERR != 0 {
+        ready |= mio::unix::UnixReady::error();
+    }
+    ready
+}
+
+fn poll_interest(fd: &libc::pollfd) -> mio::Ready {
+    let mut interest = mio::Ready::empty();
+    if fd.events & libc::POLLIN != 0 {
+        interest |= mio::Ready::readable();
+    }
+    if fd.events & libc::POLLOUT != 0 {
+        interest |= mio::Ready::writable();
+    }
+    interest
+}
+
+impl<'a> MmapArea<'a> {
+    pub fn frames(&self) -> usize {
+        self.frames as usize
+    }
+
+    /// Interleaved frames in the format of the PCM.
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    /// Gives back the first `frames`: consumed ones for capture, filled ones for playback.
+    /// The rest are lent again by the next `mmap_begin`.
+    pub fn commit(self, frames: usize) -> Result<(), Error> {
+        let frames = cmp::min(frames as snd_pcm_uframes_t, self.frames);
+        let res = unsafe { alsa_ffi::snd_pcm_mmap_commit(self.pcm.raw_ptr, self.offset, frames) };
+        if is_recoverable(res) {
+            return self.pcm.recover(res as c_int);
+        }
+        try_snd!(res, "Committing mapped frames");
+        if res as snd_pcm_uframes_t != frames {
+            // A short commit means the buffer was overrun meanwhile.
+            return self.pcm.recover(-alsa_ffi::EPIPE);
+        }
+        self.pcm.recoveries.set(0);
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            access: Access::ReadWrite,
+            buffer: BufferConfig::default(),
+            recovery: RecoveryPolicy::default(),
+        }
+    }
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            resume_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl BufferConfig {
+    /// About 30 ms of buffer in 10 ms periods.
+    pub fn low_latency() -> Self {
+        Self {
+            period: Some(Size::Time(Duration::from_millis(10))),
+            periods: Some(3),
+            buffer: None,
+        }
+    }
+}
+impl Default for BufferConfig {
+    /// Half a second of buffer in 4 periods.
+    fn default() -> Self {
+        Self {
+            period: None,
+            periods: Some(4),
+            buffer: Some(Size::Time(Duration::from_millis(500))),
+        }
+    }
+}
+
+impl BufferSizes {
+    pub fn periods(&self) -> usize {
+        self.buffer_frames
+            .checked_div(self.period_frames)
+            .unwrap_or(0)
+    }
+}
+
 impl Stream {
     fn to_ffi(&self) -> alsa_ffi::snd_pcm_stream_t {
         match self {
@@ -381,23 +1090,156 @@ impl Format {
         match self {
             Format::U8 => alsa_ffi::SND_PCM_FORMAT_U8,
             Format::S16Le => alsa_ffi::SND_PCM_FORMAT_S16_LE,
+            Format::S16Be => alsa_ffi::SND_PCM_FORMAT_S16_BE,
+            Format::S24Le => alsa_ffi::SND_PCM_FORMAT_S24_LE,
+            Format::S24Be => alsa_ffi::SND_PCM_FORMAT_S24_BE,
+            Format::S24_3Le => alsa_ffi::SND_PCM_FORMAT_S24_3LE,
+            Format::S24_3Be => alsa_ffi::SND_PCM_FORMAT_S24_3BE,
+            Format::S32Le => alsa_ffi::SND_PCM_FORMAT_S32_LE,
+            Format::S32Be => alsa_ffi::SND_PCM_FORMAT_S32_BE,
             Format::FloatLe => alsa_ffi::SND_PCM_FORMAT_FLOAT_LE,
+            Format::FloatBe => alsa_ffi::SND_PCM_FORMAT_FLOAT_BE,
+            Format::Float64Le => alsa_ffi::SND_PCM_FORMAT_FLOAT64_LE,
+            Format::Float64Be => alsa_ffi::SND_PCM_FORMAT_FLOAT64_BE,
         }
     }
 
-    fn bytes_per_sample(&self) -> usize {
+    pub fn bytes_per_sample(&self) -> usize {
         match self {
             Format::U8 => 1,
-            Format::S16Le => 2,
-            Format::FloatLe => 4,
+            Format::S16Le | Format::S16Be => 2,
+            Format::S24_3Le | Format::S24_3Be => 3,
+            Format::S24Le
+            | Format::S24Be
+            | Format::S32Le
+            | Format::S32Be
+            | Format::FloatLe
+            | Format::FloatBe => 4,
+            Format::Float64Le | Format::Float64Be => 8,
         }
     }
 
-    pub fn to_audio_saver_format(&self) -> audio_saver::Format {
+    /// Whether ffmpeg takes the samples as they are, see `to_float_le` for the rest.
+    pub fn is_ffmpeg_native(&self) -> bool {
+        matches!(self, Format::U8 | Format::S16Le | Format::FloatLe)
+    }
+
+    /// Writes the frames made of the channels' samples to `out`, as many as fit and the
+    /// shortest channel has. Returns the number of bytes written.
+    pub fn interleave(&self, channels: &[&[u8]], out: &mut [u8]) -> usize {
+        let sample_size = self.bytes_per_sample();
+        let frame_size = sample_size * channels.len();
+        if frame_size == 0 {
+            return 0;
+        }
+        let frames = channels
+            .iter()
+            .map(|c| c.len() / sample_size)
+            .min()
+            .unwrap_or(0);
+        let frames = cmp::min(frames, out.len() / frame_size);
+        for (i, frame) in out.chunks_exact_mut(frame_size).take(frames).enumerate() {
+            for (c, sample) in channels.iter().zip(frame.chunks_exact_mut(sample_size)) {
+                sample.copy_from_slice(&c[i * sample_size..(i + 1) * sample_size]);
+            }
+        }
+        frames * frame_size
+    }
+
+    /// Appends the samples to `out` as FloatLe.
+    pub fn to_float_le(&self, data: &[u8], out: &mut Vec<u8>) {
+        for sample in data.chunks_exact(self.bytes_per_sample()) {
+            out.extend_from_slice(&self.sample_to_f32(sample).to_le_bytes());
+        }
+    }
+
+    /// Appends 16 bit samples, as decoders make them, to `out` in this format.
+    pub fn extend_from_s16(&self, samples: &[i16], out: &mut Vec<u8>) {
+        let sample_size = self.bytes_per_sample();
+        for s in samples {
+            let start = out.len();
+            out.resize(start + sample_size, 0);
+            self.sample_from_f32(f32::from(*s) / 32768.0, &mut out[start..]);
+        }
+    }
+
+    /// One sample of this format scaled to [-1, 1].
+    pub fn sample_to_f32(&self, s: &[u8]) -> f32 {
+        // Integers are aligned to the top bits of an i32 first.
+        let int = match self {
+            Format::U8 => i32::from(s[0] as i8 ^ i8::MIN) << 24,
+            Format::S16Le => i32::from(i16::from_le_bytes([s[0], s[1]])) << 16,
+            Format::S16Be => i32::from(i16::from_be_bytes([s[0], s[1]])) << 16,
+            Format::S24Le => i32::from_le_bytes([s[0], s[1], s[2], s[3]]) << 8,
+            Format::S24Be => i32::from_be_bytes([s[0], s[1], s[2], s[3]]) << 8,
+            Format::S24_3Le => i32::from_le_bytes([0, s[0], s[1], s[2]]),
+            Format::S24_3Be => i32::from_be_bytes([s[0], s[1], s[2], 0]),
+            Format::S32Le => i32::from_le_bytes([s[0], s[1], s[2], s[3]]),
+            Format::S32Be => i32::from_be_bytes([s[0], s[1], s[2], s[3]]),
+            Format::FloatLe => return f32::from_le_bytes([s[0], s[1], s[2], s[3]]),
+            Format::FloatBe => return f32::from_be_bytes([s[0], s[1], s[2], s[3]]),
+            Format::Float64Le => {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(&s[..8]);
+                return f64::from_le_bytes(b) as f32;
+            }
+            Format::Float64Be => {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(&s[..8]);
+                return f64::from_be_bytes(b) as f32;
+            }
+        };
+        (f64::from(int) / -f64::from(i32::MIN)) as f32
+    }
+
+    /// Writes `v` as one sample of this format to the start of `out`, clipping it to [-1, 1].
+    /// Integers are rounded to the nearest step, so `sample_to_f32` is reversed exactly.
+    pub fn sample_from_f32(&self, v: f32, out: &mut [u8]) {
+        let int = |bits: u32| {
+            let max = f64::from(1u32 << (bits - 1));
+            (f64::from(v) * max).round().clamp(-max, max - 1.0) as i32
+        };
+        match self {
+            Format::U8 => out[0] = (int(8) as i8 ^ i8::MIN) as u8,
+            Format::S16Le => out[..2].copy_from_slice(&(int(16) as i16).to_le_bytes()),
+            Format::S16Be => out[..2].copy_from_slice(&(int(16) as i16).to_be_bytes()),
+            Format::S24Le => out[..4].copy_from_slice(&int(24).to_le_bytes()),
+            Format::S24Be => out[..4].copy_from_slice(&int(24).to_be_bytes()),
+            Format::S24_3Le => out[..3].copy_from_slice(&int(24).to_le_bytes()[..3]),
+            Format::S24_3Be => out[..3].copy_from_slice(&int(24).to_be_bytes()[1..]),
+            Format::S32Le => out[..4].copy_from_slice(&int(32).to_le_bytes()),
+            Format::S32Be => out[..4].copy_from_slice(&int(32).to_be_bytes()),
+            Format::FloatLe => out[..4].copy_from_slice(&v.to_le_bytes()),
+            Format::FloatBe => out[..4].copy_from_slice(&v.to_be_bytes()),
+            Format::Float64Le => out[..8].copy_from_slice(&f64::from(v).to_le_bytes()),
+            Format::Float64Be => out[..8].copy_from_slice(&f64::from(v).to_be_bytes()),
+        }
+    }
+
+    /// Whether the samples are all digital silence.
+    pub fn is_silence(&self, data: &[u8]) -> bool {
+        let silence = self.silence_byte();
+        data.iter().all(|&b| b == silence)
+    }
+
+    /// Digital silence consists of these bytes only.
+    pub fn silence_byte(&self) -> u8 {
         match self {
-            Format::U8 => audio_saver::Format::U8,
-            Format::S16Le => audio_saver::Format::S16Le,
-            Format::FloatLe => audio_saver::Format::FloatLe,
+            Format::U8 => 0x80,
+            _ => 0,
+        }
+    }
+
+    /// `None` for the formats WAV can't hold: big-endian and 64 bit floats.
+    pub fn to_audio_saver_format(&self) -> Option<audio_saver::Format> {
+        match self {
+            Format::U8 => Some(audio_saver::Format::U8),
+            Format::S16Le => Some(audio_saver::Format::S16Le),
+            Format::S24Le => Some(audio_saver::Format::S24Le),
+            Format::S24_3Le => Some(audio_saver::Format::S24_3Le),
+            Format::S32Le => Some(audio_saver::Format::S32Le),
+            Format::FloatLe => Some(audio_saver::Format::FloatLe),
+            _ => None,
         }
     }
 }
@@ -425,7 +1267,85 @@ impl SndCtl {
     }
 
     pub fn list_cards() -> SndCtlIterator {
-        SndCtlIterator { card: 0 }
+        SndCtlIterator {
+            cards: CardNums::new(alsa_ffi::snd_card_next),
+        }
+    }
+
+    /// The PCMs alsa has names for: the cards' ones and the ones of the configs,
+    /// like "pulse", "dmix" or those of asoundrc.
+    pub fn list_pcm_hints() -> Result<Vec<PcmHint>, Error> {
+        let mut hints: *mut *mut c_void = ptr::null_mut();
+        let mut res = Vec::new();
+        unsafe {
+            try_snd!(
+                alsa_ffi::snd_device_name_hint(-1, c_str(b"pcm\0"), &mut hints),
+                "Listing PCM name hints"
+            );
+
+            let mut hint = hints;
+            while !(*hint).is_null() {
+                let get = |id: &'static [u8]| {
+                    take_c_string(alsa_ffi::snd_device_name_get_hint(*hint, c_str(id)))
+                };
+                // Hints without a name can't be opened.
+                if let Some(name) = get(b"NAME\0") {
+                    let stream = match get(b"IOID\0").as_deref() {
+                        Some("Output") => Some(Stream::Playback),
+                        Some("Input") => Some(Stream::Capture),
+                        _ => None,
+                    };
+                    res.push(PcmHint {
+                        name,
+                        description: get(b"DESC\0"),
+                        stream,
+                    });
+                }
+                hint = hint.add(1);
+            }
+            alsa_ffi::snd_device_name_free_hint(hints);
+        }
+        Ok(res)
+    }
+
+    /// The name hints, followed by the devices of the cards they miss as "hw:card,device".
+    pub fn list_pcms() -> Result<Vec<PcmHint>, Error> {
+        let mut res = Self::list_pcm_hints()?;
+        for ctl in Self::list_cards() {
+            let ctl = ctl?;
+            let card_info = ctl.card_info()?;
+
+            // A device is listed by each direction it has, `None` stands for both.
+            let mut devices: Vec<(SndPcmInfo, Option<Stream>)> = Vec::new();
+            for &stream in &[Stream::Playback, Stream::Capture] {
+                for dev_info in ctl.list_stream_devices_info(stream) {
+                    let dev_info = dev_info?;
+                    let dev_num = dev_info.get_dev_num();
+                    match devices.iter_mut().find(|(d, _)| d.get_dev_num() == dev_num) {
+                        Some((_, both)) => *both = None,
+                        None => devices.push((dev_info, Some(stream))),
+                    }
+                }
+            }
+
+            for (dev_info, stream) in devices {
+                let hint_name = format!(
+                    "hw:CARD={},DEV={}",
+                    card_info.get_id(),
+                    dev_info.get_dev_num()
+                );
+                if res.iter().any(|hint| hint.name == hint_name) {
+                    continue;
+                }
+
+                res.push(PcmHint {
+                    name: format!("hw:{},{}", ctl.get_card_num(), dev_info.get_dev_num()),
+                    description: Some(format!("{}, {}", card_info.get_name(), dev_info.get_name())),
+                    stream,
+                });
+            }
+        }
+        Ok(res)
     }
 
     pub fn get_card_num(&self) -> i32 {
@@ -441,8 +1361,14 @@ impl SndCtl {
     }
 
     pub fn list_devices_info(&self) -> SndDeviceInfoIterator {
+        self.list_stream_devices_info(Stream::Capture)
+    }
+
+    /// The devices that have the direction `stream`.
+    pub fn list_stream_devices_info(&self, stream: Stream) -> SndDeviceInfoIterator {
         SndDeviceInfoIterator {
             dev: -1,
+            stream,
             raw_ctl: self.raw_ptr,
         }
     }
@@ -456,23 +1382,43 @@ impl Drop for SndCtl {
 }
 
 pub struct SndCtlIterator {
-    card: c_int,
+    cards: CardNums,
 }
 impl Iterator for SndCtlIterator {
     type Item = Result<SndCtl, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let err = unsafe { alsa_ffi::snd_card_next(&mut self.card) };
+        self.cards.next().map(SndCtl::open)
+    }
+}
+
+/// The card numbers `next` walks through, `snd_card_next` outside the tests.
+struct CardNums {
+    /// -1 before the first card.
+    card: c_int,
+    next: unsafe extern "C" fn(*mut c_int) -> c_int,
+}
+impl CardNums {
+    fn new(next: unsafe extern "C" fn(*mut c_int) -> c_int) -> Self {
+        Self { card: -1, next }
+    }
+}
+impl Iterator for CardNums {
+    type Item = c_int;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let err = unsafe { (self.next)(&mut self.card) };
         if err < 0 || self.card < 0 {
             return None;
         }
 
-        Some(SndCtl::open(self.card))
+        Some(self.card)
     }
 }
 
 pub struct SndDeviceInfoIterator {
     dev: c_int,
+    stream: Stream,
     raw_ctl: *mut alsa_ffi::snd_ctl_t,
 }
 impl Iterator for SndDeviceInfoIterator {
@@ -485,13 +1431,11 @@ impl Iterator for SndDeviceInfoIterator {
                 return None;
             }
 
-            let res = SndPcmInfo::open_device(self.dev, self.raw_ctl);
+            let res = SndPcmInfo::open_device(self.dev, self.stream, self.raw_ctl);
 
             if let Err(ref err) = res {
-                if let ErrorRepr::Alsa(err) = err.get_repr() {
-                    if err.errnum == -2 {
-                        continue;
-                    }
+                if is_alsa_errnum(err, -alsa_ffi::ENOENT) {
+                    continue;
                 }
             }
 
@@ -513,13 +1457,17 @@ impl SndPcmInfo {
         Ok(Self { raw_ptr: res })
     }
 
-    fn open_device(dev: c_int, raw_ctl: *mut alsa_ffi::snd_ctl_t) -> Result<Self, Error> {
+    fn open_device(
+        dev: c_int,
+        stream: Stream,
+        raw_ctl: *mut alsa_ffi::snd_ctl_t,
+    ) -> Result<Self, Error> {
         unsafe {
             let res = Self::new()?;
 
             alsa_ffi::snd_pcm_info_set_device(res.raw_ptr, dev as c_uint);
             alsa_ffi::snd_pcm_info_set_subdevice(res.raw_ptr, 0);
-            alsa_ffi::snd_pcm_info_set_stream(res.raw_ptr, alsa_ffi::SND_PCM_STREAM_CAPTURE);
+            alsa_ffi::snd_pcm_info_set_stream(res.raw_ptr, stream.to_ffi());
 
             try_snd!(alsa_ffi::snd_ctl_pcm_info(raw_ctl, res.raw_ptr));
             Ok(res)
@@ -612,6 +1560,23 @@ enum PcmState {
     Disconnected,
 }
 
+impl PcmState {
+    fn from_ffi(raw: alsa_ffi::snd_pcm_state_t) -> Self {
+        match raw {
+            alsa_ffi::SND_PCM_STATE_OPEN => PcmState::Open,
+            alsa_ffi::SND_PCM_STATE_SETUP => PcmState::Setup,
+            alsa_ffi::SND_PCM_STATE_PREPARED => PcmState::Prepared,
+            alsa_ffi::SND_PCM_STATE_RUNNING => PcmState::Running,
+            alsa_ffi::SND_PCM_STATE_XRUN => PcmState::XRun,
+            alsa_ffi::SND_PCM_STATE_DRAINING => PcmState::Draining,
+            alsa_ffi::SND_PCM_STATE_PAUSED => PcmState::Paused,
+            alsa_ffi::SND_PCM_STATE_SUSPENDED => PcmState::Suspended,
+            alsa_ffi::SND_PCM_STATE_DISCONNECTED => PcmState::Disconnected,
+            _ => unreachable!(),
+        }
+    }
+}
+
 struct SndPcmStatus {
     raw_ptr: *mut alsa_ffi::snd_pcm_status_t,
 }
@@ -630,21 +1595,7 @@ impl SndPcmStatus {
     }
 
     fn get_state(&self) -> PcmState {
-        unsafe {
-            let raw_res = alsa_ffi::snd_pcm_status_get_state(self.raw_ptr);
-            match raw_res {
-                alsa_ffi::SND_PCM_STATE_OPEN => PcmState::Open,
-                alsa_ffi::SND_PCM_STATE_SETUP => PcmState::Setup,
-                alsa_ffi::SND_PCM_STATE_PREPARED => PcmState::Prepared,
-                alsa_ffi::SND_PCM_STATE_RUNNING => PcmState::Running,
-                alsa_ffi::SND_PCM_STATE_XRUN => PcmState::XRun,
-                alsa_ffi::SND_PCM_STATE_DRAINING => PcmState::Draining,
-                alsa_ffi::SND_PCM_STATE_PAUSED => PcmState::Paused,
-                alsa_ffi::SND_PCM_STATE_SUSPENDED => PcmState::Suspended,
-                alsa_ffi::SND_PCM_STATE_DISCONNECTED => PcmState::Disconnected,
-                _ => unreachable!(),
-            }
-        }
+        unsafe { PcmState::from_ffi(alsa_ffi::snd_pcm_status_get_state(self.raw_ptr)) }
     }
 }
 impl Drop for SndPcmStatus {
@@ -676,13 +1627,17 @@ impl SndPcmHwParams {
         Ok(())
     }
 
-    fn set_interleaved(&mut self, pcm: &SndPcm) -> Result<(), Error> {
+    fn set_access(&mut self, pcm: &SndPcm, access: Access) -> Result<(), Error> {
+        let raw_access = match access {
+            Access::ReadWrite => alsa_ffi::SND_PCM_ACCESS_RW_INTERLEAVED,
+            Access::Mmap => alsa_ffi::SND_PCM_ACCESS_MMAP_INTERLEAVED,
+            Access::NonInterleaved => alsa_ffi::SND_PCM_ACCESS_RW_NONINTERLEAVED,
+        };
         unsafe {
-            try_snd!(alsa_ffi::snd_pcm_hw_params_set_access(
-                pcm.raw_ptr,
-                self.raw_ptr,
-                alsa_ffi::SND_PCM_ACCESS_RW_INTERLEAVED,
-            ));
+            try_snd!(
+                alsa_ffi::snd_pcm_hw_params_set_access(pcm.raw_ptr, self.raw_ptr, raw_access),
+                format!("Setting {:?} access", access)
+            );
         }
         Ok(())
     }
@@ -740,6 +1695,66 @@ impl SndPcmHwParams {
         Ok(rate)
     }
 
+    fn set_buffer_config(&self, pcm: &SndPcm, config: BufferConfig) -> Result<(), Error> {
+        match config.period {
+            Some(Size::Time(time)) => self.set_period_time_near(pcm, time.as_micros() as u32)?,
+            Some(Size::Frames(frames)) => self.set_period_size_near(pcm, frames)?,
+            None => (),
+        }
+        if let Some(periods) = config.periods {
+            self.set_periods_near(pcm, periods)?;
+        }
+        match config.buffer {
+            Some(Size::Time(time)) => {
+                let buffer_time = cmp::min(self.get_buffer_time_max()?, time.as_micros() as u32);
+                self.set_buffer_time_near(pcm, buffer_time)?
+            }
+            Some(Size::Frames(frames)) => self.set_buffer_size_near(pcm, frames)?,
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn set_period_size_near(&self, pcm: &SndPcm, frames: usize) -> Result<(), Error> {
+        let mut frames = frames as snd_pcm_uframes_t;
+        unsafe {
+            try_snd!(alsa_ffi::snd_pcm_hw_params_set_period_size_near(
+                pcm.raw_ptr,
+                self.raw_ptr,
+                &mut frames,
+                ptr::null_mut(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn set_periods_near(&self, pcm: &SndPcm, mut periods: u32) -> Result<(), Error> {
+        unsafe {
+            try_snd!(alsa_ffi::snd_pcm_hw_params_set_periods_near(
+                pcm.raw_ptr,
+                self.raw_ptr,
+                &mut periods,
+                ptr::null_mut(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn set_buffer_size_near(&self, pcm: &SndPcm, frames: usize) -> Result<(), Error> {
+        let mut frames = frames as snd_pcm_uframes_t;
+        unsafe {
+            try_snd!(alsa_ffi::snd_pcm_hw_params_set_buffer_size_near(
+                pcm.raw_ptr,
+                self.raw_ptr,
+                &mut frames,
+            ));
+        }
+
+        Ok(())
+    }
+
     fn set_period_time_near(&self, pcm: &SndPcm, mut period_time: u32) -> Result<(), Error> {
         unsafe {
             try_snd!(alsa_ffi::snd_pcm_hw_params_set_period_time_near(
@@ -804,8 +1819,90 @@ impl SndPcmHwParams {
         Ok(res)
     }
 
+    fn get_capabilities(&self, pcm: &SndPcm, stream: Stream) -> Result<Capabilities, Error> {
+        let (mut channels_min, mut channels_max) = (0, 0);
+        let (mut rate_min, mut rate_max) = (0, 0);
+        let (mut period_min, mut period_max): (snd_pcm_uframes_t, snd_pcm_uframes_t) = (0, 0);
+        let (mut periods_min, mut periods_max) = (0, 0);
+        let (mut buffer_min, mut buffer_max): (snd_pcm_uframes_t, snd_pcm_uframes_t) = (0, 0);
+        unsafe {
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_channels_min(
+                self.raw_ptr,
+                &mut channels_min
+            ));
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_channels_max(
+                self.raw_ptr,
+                &mut channels_max
+            ));
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_rate_min(
+                self.raw_ptr,
+                &mut rate_min,
+                ptr::null_mut()
+            ));
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_rate_max(
+                self.raw_ptr,
+                &mut rate_max,
+                ptr::null_mut()
+            ));
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_period_size_min(
+                self.raw_ptr,
+                &mut period_min,
+                ptr::null_mut()
+            ));
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_period_size_max(
+                self.raw_ptr,
+                &mut period_max,
+                ptr::null_mut()
+            ));
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_periods_min(
+                self.raw_ptr,
+                &mut periods_min,
+                ptr::null_mut()
+            ));
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_periods_max(
+                self.raw_ptr,
+                &mut periods_max,
+                ptr::null_mut()
+            ));
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_buffer_size_min(
+                self.raw_ptr,
+                &mut buffer_min
+            ));
+            try_snd!(alsa_ffi::snd_pcm_hw_params_get_buffer_size_max(
+                self.raw_ptr,
+                &mut buffer_max
+            ));
+        }
+
+        Ok(Capabilities {
+            stream,
+            formats: self.get_available_formats(pcm),
+            channels: channels_min..=channels_max,
+            rate: rate_min..=rate_max,
+            period_frames: period_min as usize..=period_max as usize,
+            periods: periods_min..=periods_max,
+            buffer_frames: buffer_min as usize..=buffer_max as usize,
+        })
+    }
+
     fn get_available_formats(&self, pcm: &SndPcm) -> Vec<Format> {
-        let all_formats = [Format::S16Le, Format::FloatLe, Format::U8];
+        // The first available one is taken when the asked one isn't, so the common and
+        // precise formats go first.
+        let all_formats = [
+            Format::S16Le,
+            Format::FloatLe,
+            Format::S32Le,
+            Format::S24_3Le,
+            Format::S24Le,
+            Format::Float64Le,
+            Format::U8,
+            Format::S16Be,
+            Format::S24Be,
+            Format::S24_3Be,
+            Format::S32Be,
+            Format::FloatBe,
+            Format::Float64Be,
+        ];
 
         let mut res = Vec::new();
         for format in &all_formats {
@@ -916,3 +2013,117 @@ fn snd_strerror(errnum: c_int) -> String {
             .to_owned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INT_FORMATS: [Format; 9] = [
+        Format::U8,
+        Format::S16Le,
+        Format::S16Be,
+        Format::S24Le,
+        Format::S24Be,
+        Format::S24_3Le,
+        Format::S24_3Be,
+        Format::S32Le,
+        Format::S32Be,
+    ];
+
+    /// `snd_card_next` of a system with the cards 0 and 1.
+    unsafe extern "C" fn two_cards_next(card: *mut c_int) -> c_int {
+        *card = if *card < 1 { *card + 1 } else { -1 };
+        0
+    }
+
+    #[test]
+    fn card_nums_include_the_first_card() {
+        let cards: Vec<_> = CardNums::new(two_cards_next).collect();
+        assert_eq!(cards, vec![0, 1]);
+    }
+
+    fn round_trip(format: Format, v: f32) -> f32 {
+        let mut buf = [0u8; 8];
+        format.sample_from_f32(v, &mut buf);
+        format.sample_to_f32(&buf[..format.bytes_per_sample()])
+    }
+
+    #[test]
+    fn integer_samples_round_trip_exactly() {
+        for format in &INT_FORMATS[..7] {
+            let bits = match format {
+                Format::U8 => 8,
+                Format::S16Le | Format::S16Be => 16,
+                _ => 24,
+            };
+            let max = 1i32 << (bits - 1);
+            for int in [-max, -max + 1, -1, 0, 1, max / 3, max - 1].iter() {
+                let mut buf = [0u8; 4];
+                let v = *int as f32 / max as f32;
+                format.sample_from_f32(v, &mut buf);
+                assert_eq!(format.sample_to_f32(&buf), v, "{:?} {}", format, int);
+            }
+        }
+    }
+
+    #[test]
+    fn s32_samples_round_trip_to_float_precision() {
+        for format in &[Format::S32Le, Format::S32Be] {
+            for v in [-1.0, -0.5, -1e-6, 0.0, 1e-6, 0.25, 0.999].iter() {
+                assert!((round_trip(*format, *v) - v).abs() <= f32::EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn float_samples_round_trip_unchanged() {
+        let formats = [
+            Format::FloatLe,
+            Format::FloatBe,
+            Format::Float64Le,
+            Format::Float64Be,
+        ];
+        for format in &formats {
+            for v in [-1.5, -1.0, -0.123, 0.0, 0.5, 1.0].iter() {
+                assert_eq!(round_trip(*format, *v), *v);
+            }
+        }
+    }
+
+    #[test]
+    fn integer_samples_clip() {
+        for format in &INT_FORMATS {
+            assert_eq!(round_trip(*format, -2.0), -1.0, "{:?}", format);
+            assert!(round_trip(*format, 2.0) <= 1.0, "{:?}", format);
+            assert!(round_trip(*format, 2.0) > 0.99, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn s16_is_extended_unchanged() {
+        let samples = [i16::MIN, -1, 0, 1, i16::MAX];
+        let mut out = Vec::new();
+        Format::S16Le.extend_from_s16(&samples, &mut out);
+        let expected: Vec<u8> = samples
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        assert_eq!(out, expected);
+
+        out.clear();
+        Format::S24_3Be.extend_from_s16(&samples, &mut out);
+        assert_eq!(&out[9..], [0x00, 0x01, 0x00, 0x7F, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn channels_are_interleaved_up_to_the_shortest() {
+        let left = [1, 2, 3, 4, 5, 6];
+        let right = [7, 8, 9, 10];
+        let mut out = [0; 12];
+        let written = Format::S16Le.interleave(&[&left, &right], &mut out);
+        assert_eq!(&out[..written], [1, 2, 7, 8, 3, 4, 9, 10]);
+
+        let written = Format::S16Le.interleave(&[&left, &right], &mut out[..6]);
+        assert_eq!(&out[..written], [1, 2, 7, 8]);
+    }
+}
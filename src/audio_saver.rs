@@ -79,6 +79,10 @@ pub struct Settings {
 pub enum Format {
     U8,
     S16Le,
+    /// 24 bits in the low bytes of 4.
+    S24Le,
+    /// 24 bits packed in 3 bytes.
+    S24_3Le,
     S32Le,
     FloatLe,
 }
@@ -108,13 +112,16 @@ impl Format {
         match self {
             Format::U8 => 8,
             Format::S16Le => 16,
+            Format::S24Le | Format::S24_3Le => 24,
             Format::S32Le | Format::FloatLe => 32,
         }
     }
 
     fn hound_format(&self) -> hound::SampleFormat {
         match self {
-            Format::U8 | Format::S16Le | Format::S32Le => hound::SampleFormat::Int,
+            Format::U8 | Format::S16Le | Format::S24Le | Format::S24_3Le | Format::S32Le => {
+                hound::SampleFormat::Int
+            }
             Format::FloatLe => hound::SampleFormat::Float,
         }
     }
@@ -181,12 +188,31 @@ impl WavHoundWriter {
         };
         self.write_samples_slice(samples)
     }
+
+    /// Samples of 24 bits in the low bytes of `size` bytes.
+    fn write_24_bit_samples(&mut self, data: &[u8], size: usize) -> Result<(), Error> {
+        let samples = data.chunks_exact(size);
+        if !samples.remainder().is_empty() {
+            return Err(Error::new(
+                ErrorKind::FormatError("Size of data is not multiple of sample size"),
+                self.settings.output.clone(),
+            ));
+        }
+
+        for s in samples {
+            // Sign extended from the top byte.
+            self.write_sample(i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8)?;
+        }
+        Ok(())
+    }
 }
 impl AudioWriter for WavHoundWriter {
     fn write_bytes_slice(&mut self, data: &[u8]) -> Result<(), Error> {
         match self.settings.format {
             Format::U8 => self.write_bytes_as_samples::<i8>(data),
             Format::S16Le => self.write_bytes_as_samples::<i16>(data),
+            Format::S24Le => self.write_24_bit_samples(data, 4),
+            Format::S24_3Le => self.write_24_bit_samples(data, 3),
             Format::S32Le => self.write_bytes_as_samples::<i32>(data),
             Format::FloatLe => self.write_bytes_as_samples::<f32>(data),
         }
@@ -1,46 +1,97 @@
+use audio_sharing_pc::aes67;
 use audio_sharing_pc::alsa;
 use audio_sharing_pc::audio_saver;
 use audio_sharing_pc::error::*;
 use audio_sharing_pc::exit_listener;
+use audio_sharing_pc::hls;
+use audio_sharing_pc::icecast;
+use audio_sharing_pc::ingest;
+use audio_sharing_pc::ingest::Source;
+#[cfg(feature = "fdk-aac")]
+use audio_sharing_pc::net_client;
 use audio_sharing_pc::net_server;
 use audio_sharing_pc::thread_buffer;
+#[cfg(feature = "opus")]
+use audio_sharing_pc::webrtc;
 use clap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::process::exit;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
+#[cfg(feature = "fdk-aac")]
+use std::{sync::atomic::AtomicBool, thread, time::Instant};
 use stream_audio_ffmpeg as ffmpeg;
 
-pub fn list_alsa_devices() -> Result<(), Error> {
-    for ctl in alsa::SndCtl::list_cards() {
-        let ctl = ctl?;
-        let info = ctl.card_info()?;
-
-        for dev_info in ctl.list_devices_info() {
-            let dev_info = dev_info?;
+/// Lists the PCMs of the cards and of the configs. With `probe`, also prints what each
+/// supports, busy ones can't tell.
+pub fn list_alsa_devices(probe: bool) -> Result<(), Error> {
+    for pcm in alsa::SndCtl::list_pcms()? {
+        let streams = match pcm.stream {
+            Some(stream) => vec![stream],
+            None => vec![alsa::Stream::Playback, alsa::Stream::Capture],
+        };
+        // Descriptions of the hints are split in lines.
+        let description = pcm.description.unwrap_or_default().replace('\n', ", ");
+        println!("{} {:?}: {}", pcm.name, streams, description);
 
-            println!(
-                "Card: #{}, {} [{}]. Device: #{}, {}, [{}]. Subdevices qty: {}",
-                ctl.get_card_num(),
-                info.get_id(),
-                info.get_name(),
-                dev_info.get_dev_num(),
-                dev_info.get_id(),
-                dev_info.get_name(),
-                dev_info.get_subdevice_count(),
-            );
+        if probe {
+            for stream in streams {
+                match alsa::SndPcm::probe(pcm.name.clone(), stream) {
+                    Ok(caps) => print_capabilities(&caps),
+                    Err(e) => println!("    {:?}: probing failed: {}", stream, e),
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+fn print_capabilities(caps: &alsa::Capabilities) {
+    println!(
+        "    {:?}: formats {:?}, channels {:?}, rate {:?}, period {:?} frames, \
+         periods {:?}, buffer {:?} frames",
+        caps.stream,
+        caps.formats,
+        caps.channels,
+        caps.rate,
+        caps.period_frames,
+        caps.periods,
+        caps.buffer_frames,
+    );
+}
+
 struct ThreadPlayer {
     _file_writer: Box<dyn audio_saver::AudioWriter>,
     player: alsa::SndPcm,
 }
 
+/// The relay reconnects after this delay, unless upstream gives a hint.
+#[cfg(feature = "fdk-aac")]
+const RELAY_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Upstream's reconnect hint is capped, it can't keep the relay waiting for days.
+#[cfg(feature = "fdk-aac")]
+const RELAY_MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// The exit flag is checked this often while waiting to reconnect.
+#[cfg(feature = "fdk-aac")]
+const RELAY_WAIT_SLICE: Duration = Duration::from_millis(100);
+/// Upstream is considered dead after this many receive timeouts in a row.
+#[cfg(feature = "fdk-aac")]
+const RELAY_MAX_TIMEOUTS: u32 = 10;
+
+/// AAC bit rate, also announced to Icecast and RTSP players.
+const ENCODER_BIT_RATE: u32 = 96000;
+
 struct ThreadServerWriter {
     server: net_server::NetServer,
     encoder: ffmpeg::Encoder,
+    params: alsa::Params,
+    dtx: Option<net_server::dtx::Dtx>,
+    icecast: Option<icecast::IcecastSink>,
+    hls: Option<hls::HlsServer>,
+    #[cfg(feature = "opus")]
+    webrtc: Option<webrtc::WebRtcServer>,
 }
 
 impl thread_buffer::DataReceiver for ThreadPlayer {
@@ -53,17 +104,88 @@ impl thread_buffer::DataReceiver for ThreadPlayer {
 
 impl thread_buffer::DataReceiver for ThreadServerWriter {
     fn new_slice(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.server.send_pcm(data)?;
+        #[cfg(feature = "opus")]
+        if let Some(webrtc) = &mut self.webrtc {
+            webrtc.send_pcm(data)?;
+        }
+
+        if let Some(dtx) = &mut self.dtx {
+            let frames = (data.len() / self.params.bytes_per_frame()) as u32;
+            if let Some(silence) = dtx.pcm(self.params.format.is_silence(data), frames) {
+                self.server.send_silence(silence)?;
+            }
+        }
+
+        // Encoding goes on through the silence, the encoder holds frames back.
         self.encoder.write(data)?;
         while let Some(data) = self.encoder.read()? {
-            self.server.send_to_all(data)?;
+            let packet = match &mut self.dtx {
+                Some(dtx) => dtx.encoded(),
+                None => net_server::dtx::Packet::Audio,
+            };
+            match packet {
+                net_server::dtx::Packet::Audio => self.server.send_to_all(data)?,
+                net_server::dtx::Packet::Silence(Some(silence)) => {
+                    self.server.send_silence(silence)?
+                }
+                net_server::dtx::Packet::Silence(None) => (),
+            }
+            // Icecast and HLS listeners have no silence markers.
+            if let Some(icecast) = &mut self.icecast {
+                icecast.send_frame(data);
+            }
+            if let Some(hls) = &mut self.hls {
+                hls.send_frame(data);
+            }
         }
         Ok(())
     }
 }
 
-fn record(name: String, params: alsa::Params, should_play_locally: bool) -> Result<(), Error> {
-    let pcm_recorder = alsa::SndPcm::open(name, alsa::Stream::Capture, params)?;
-    let record_params = pcm_recorder.get_params();
+fn open_alsa_source(
+    name: String,
+    params: alsa::Params,
+    config: alsa::Config,
+) -> Result<Box<dyn ingest::Source>, Error> {
+    let source = ingest::DeviceSource::open(name, params, config)?;
+    if let Some(pcm_recorder) = source.pcm() {
+        println!("Opened '{}'", pcm_recorder.info()?.get_id());
+        println!("Capture settings: {}", pcm_recorder.dump_settings()?);
+        println!("Capture buffer: {:?}", pcm_recorder.get_buffer_sizes());
+        pcm_recorder.reset()?;
+    }
+    Ok(Box::new(source))
+}
+
+/// Where the captured audio goes besides the stream clients.
+struct Outputs {
+    icecast: Option<icecast::Settings>,
+    icecast_title: Option<String>,
+    hls: Option<hls::Settings>,
+    #[cfg(feature = "opus")]
+    webrtc: Option<webrtc::Settings>,
+}
+
+fn record(
+    mut source: Box<dyn ingest::Source>,
+    params: alsa::Params,
+    should_play_locally: bool,
+    server_settings: net_server::Settings,
+    dtx: bool,
+    outputs: Outputs,
+) -> Result<(), Error> {
+    let mut record_params = source.params();
+    // ffmpeg lacks the formats of some devices, those are given to it as floats.
+    let convert_from = if record_params.format.is_ffmpeg_native() {
+        None
+    } else {
+        println!("Converting {:?} to FloatLe", record_params.format);
+        Some(std::mem::replace(
+            &mut record_params.format,
+            alsa::Format::FloatLe,
+        ))
+    };
     let pcm_player = if should_play_locally {
         Some(alsa::SndPcm::open(
             "default".to_owned(),
@@ -74,8 +196,6 @@ fn record(name: String, params: alsa::Params, should_play_locally: bool) -> Resu
         None
     };
 
-    println!("Opened '{}'", pcm_recorder.info()?.get_id());
-    println!("Capture settings: {}", pcm_recorder.dump_settings()?);
     if let Some(p) = &pcm_player {
         println!("Player settings: {}", p.dump_settings()?);
     }
@@ -88,20 +208,75 @@ fn record(name: String, params: alsa::Params, should_play_locally: bool) -> Resu
 
     let encoder_params = ffmpeg::CodecParams {
         codec: ffmpeg::Codec::Aac,
-        bit_rate: 96000,
+        bit_rate: i64::from(ENCODER_BIT_RATE),
         audio_params: params.into(),
     };
     let encoder = ffmpeg::Encoder::new(encoder_params)?;
 
+    let icecast = match outputs.icecast {
+        Some(settings) => {
+            let sink = icecast::IcecastSink::new(
+                icecast::Settings {
+                    bit_rate: Some(ENCODER_BIT_RATE),
+                    ..settings
+                },
+                params,
+            )?;
+            if let Some(title) = &outputs.icecast_title {
+                sink.set_metadata(title);
+            }
+            Some(sink)
+        }
+        None => None,
+    };
+
+    let hls = match outputs.hls {
+        Some(settings) => {
+            println!(
+                "HLS playlist on http://{}{}",
+                settings.addr,
+                hls::PLAYLIST_PATH
+            );
+            Some(hls::HlsServer::new(settings, params)?)
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "opus")]
+    let webrtc = match outputs.webrtc {
+        Some(settings) => {
+            let http_addr = settings.http_addr;
+            let server = webrtc::WebRtcServer::new(settings, params)?;
+            println!(
+                "WebRTC page on http://{}/, media on {:?}",
+                http_addr,
+                server.candidates()
+            );
+            Some(server)
+        }
+        None => None,
+    };
+
     let on_exit_receiver = exit_listener::listen_on_exit()?;
     let on_exit_flag = on_exit_receiver.signal_flag.clone();
 
-    let server = net_server::NetServer::new("0.0.0.0:25204".parse().unwrap(), on_exit_receiver)?;
+    let server = net_server::NetServer::new(server_settings, on_exit_receiver)?;
+    for addr in server.local_addrs() {
+        println!("Listening on {}", addr);
+    }
 
     let writer_settings = audio_saver::Settings {
         channels: params.channels as u16,
         sample_rate: params.rate as f64,
-        format: params.format.to_audio_saver_format(),
+        format: params.format.to_audio_saver_format().ok_or_else(|| {
+            IoError::new(
+                "creating the audio dump",
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("WAV can't hold {:?}", params.format),
+                ),
+            )
+        })?,
         output: audio_saver::OutputType::File("/tmp/audio.dump".to_owned()),
     };
 
@@ -117,19 +292,41 @@ fn record(name: String, params: alsa::Params, should_play_locally: bool) -> Resu
         None
     };
 
-    let mut thread_server_writer =
-        thread_buffer::ThreadBuffer::new(Box::new(ThreadServerWriter { server, encoder }));
+    let mut thread_server_writer = thread_buffer::ThreadBuffer::new(Box::new(ThreadServerWriter {
+        server,
+        encoder,
+        params,
+        dtx: if dtx {
+            Some(net_server::dtx::Dtx::new(params.rate))
+        } else {
+            None
+        },
+        icecast,
+        hls,
+        #[cfg(feature = "opus")]
+        webrtc,
+    }));
 
     let mut buffer = vec![0; 4068];
-    pcm_recorder.reset()?;
+    let mut converted = Vec::new();
     loop {
         if on_exit_flag.load(Ordering::SeqCst) {
             eprintln!("Caught Signal, finishing job");
             break;
         }
 
-        let read = pcm_recorder.read_interleaved(buffer.as_mut_slice())?;
-        let data = &buffer[..read];
+        let read = source.read(buffer.as_mut_slice())?;
+        if read == 0 {
+            continue;
+        }
+        let data = match convert_from {
+            Some(format) => {
+                converted.clear();
+                format.to_float_le(&buffer[..read], &mut converted);
+                &converted[..]
+            }
+            None => &buffer[..read],
+        };
         let data = match &mut resampler {
             Some(resampler) => resampler.resample(data)?,
             None => data,
@@ -141,13 +338,259 @@ fn record(name: String, params: alsa::Params, should_play_locally: bool) -> Resu
         thread_server_writer.write_data(data)?;
     }
 
-    pcm_recorder.stop()?;
+    source.stop()?;
     thread_player.as_mut().map(|t| t.stop_and_join());
     thread_server_writer.stop_and_join();
 
     Ok(())
 }
 
+/// Sends the captured frames to a multicast group as they are, without encoding.
+fn multicast(
+    name: String,
+    params: alsa::Params,
+    config: alsa::Config,
+    settings: aes67::Settings,
+) -> Result<(), Error> {
+    let on_exit = exit_listener::listen_on_exit()?;
+
+    let mut source = ingest::DeviceSource::open(name, params, config)?;
+    if let Some(pcm_recorder) = source.pcm() {
+        println!("Capture settings: {}", pcm_recorder.dump_settings()?);
+        println!("Capture buffer: {:?}", pcm_recorder.get_buffer_sizes());
+        pcm_recorder.reset()?;
+    }
+    let events = source.events();
+
+    println!(
+        "Sending {:?} to {} every {:?}",
+        settings.encoding, settings.group, settings.ptime
+    );
+    let mut sender = aes67::Aes67Sender::new(settings, source.params())?;
+
+    // With Access::Mmap, packets are made straight from the ring buffer.
+    let mut buffer = vec![0; 4096];
+    let mut disconnects = 0;
+    while !on_exit.has_signal() {
+        source.read_with(&mut buffer, |data| sender.write(data))?;
+        for event in events.try_iter() {
+            if event == ingest::DeviceEvent::Disconnected {
+                disconnects += 1;
+            }
+        }
+    }
+    eprintln!("Caught Signal, finishing job");
+    if let Some(pcm_recorder) = source.pcm() {
+        println!("Capture xruns: {:?}", pcm_recorder.get_xrun_stats());
+    }
+    println!("Capture disconnects: {}", disconnects);
+
+    source.stop()
+}
+
+/// Receives the stream of another server and serves it to the local clients
+/// without re-encoding, keeping the sequence numbers.
+#[cfg(feature = "fdk-aac")]
+fn relay(upstream: SocketAddr, server_settings: net_server::Settings) -> Result<(), Error> {
+    let on_exit_receiver = exit_listener::listen_on_exit()?;
+    let on_exit_flag = on_exit_receiver.signal_flag.clone();
+
+    let server = net_server::NetServer::new(server_settings, on_exit_receiver)?;
+    for addr in server.local_addrs() {
+        println!("Listening on {}", addr);
+    }
+
+    let client_settings = net_client::Settings {
+        server: upstream,
+        ..Default::default()
+    };
+    let mut reconnect_after = None;
+
+    while !on_exit_flag.load(Ordering::SeqCst) {
+        if let Some(delay) = reconnect_after.take() {
+            if !wait_unless_exit(delay, &on_exit_flag) {
+                break;
+            }
+        }
+
+        let mut client = match net_client::NetClient::connect(client_settings.clone()) {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("Error connecting to upstream {}: {}", upstream, e);
+                reconnect_after = Some(RELAY_RECONNECT_DELAY);
+                continue;
+            }
+        };
+        println!("Relaying {}", upstream);
+        server.set_info(client.info());
+
+        let mut timeouts = 0;
+        while !on_exit_flag.load(Ordering::SeqCst) {
+            let event = match client.recv() {
+                Ok(event) => event,
+                Err(e) => {
+                    eprintln!("Error receiving from upstream: {}", e);
+                    reconnect_after = Some(RELAY_RECONNECT_DELAY);
+                    break;
+                }
+            };
+
+            match event {
+                net_client::Event::Bye {
+                    reason,
+                    reconnect_after: hint,
+                } => {
+                    eprintln!("Upstream said goodbye: {:?}", reason);
+                    let delay = hint.unwrap_or(RELAY_RECONNECT_DELAY);
+                    reconnect_after = Some(delay.min(RELAY_MAX_RECONNECT_DELAY));
+                    break;
+                }
+                net_client::Event::Timeout => {
+                    timeouts += 1;
+                    if timeouts >= RELAY_MAX_TIMEOUTS {
+                        eprintln!("Upstream is silent, reconnecting");
+                        break;
+                    }
+                }
+                event => {
+                    timeouts = 0;
+                    if let Some(pkt) = event.to_pkt() {
+                        server.forward_to_all(&pkt)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sleeps for `delay` in slices. Returns `false` if the exit flag was set meanwhile.
+#[cfg(feature = "fdk-aac")]
+fn wait_unless_exit(delay: Duration, on_exit_flag: &AtomicBool) -> bool {
+    let deadline = Instant::now() + delay;
+    while !on_exit_flag.load(Ordering::SeqCst) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            return true;
+        }
+        thread::sleep(left.min(RELAY_WAIT_SLICE));
+    }
+    false
+}
+
+/// Parses hex digits, as SDP carries binary parameters.
+fn parse_hex(v: &str) -> Result<Vec<u8>, String> {
+    let err = || format!("'{}' is not an even number of hex digits", v);
+    if v.is_empty() || !v.is_ascii() {
+        return Err(err());
+    }
+    v.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(err)
+        })
+        .collect()
+}
+
+/// Splits `host:port/mount` into parts, the mount keeps the leading `/`.
+fn parse_icecast_location(v: &str) -> Result<(String, u16, String), String> {
+    let slash = v
+        .find('/')
+        .ok_or("The mount point is missing, e.g. host:8000/stream")?;
+    let (addr, mount) = v.split_at(slash);
+    let colon = addr
+        .rfind(':')
+        .ok_or("The port is missing, e.g. host:8000/stream")?;
+    let port = addr[colon + 1..].parse().map_err(|e| format!("{}", e))?;
+    Ok((addr[..colon].to_owned(), port, mount.to_owned()))
+}
+
+/// Options of what decodes AAC, so needs libfdk-aac: the relay, the client microphone
+/// and AAC over RTP input.
+#[cfg(feature = "fdk-aac")]
+fn fdk_aac_args() -> Vec<clap::Arg<'static, 'static>> {
+    vec![
+        clap::Arg::with_name("relay")
+            .long("relay")
+            .takes_value(true)
+            .validator(|v| {
+                v.parse::<SocketAddr>()
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+            .help("Instead of capturing audio, relay the stream of another server"),
+        clap::Arg::with_name("mic_key")
+            .long("mic-key")
+            .takes_value(true)
+            .help(
+                "Play the microphone of the client that authorizes with the key. \
+                 The key is sent in clear text, for a trusted LAN only",
+            ),
+        clap::Arg::with_name("mic_device")
+            .long("mic-device")
+            .takes_value(true)
+            .default_value("default")
+            .help("Name of the alsa device to play the client microphone to"),
+        clap::Arg::with_name("mic_gain")
+            .long("mic-gain")
+            .takes_value(true)
+            .default_value("0.5")
+            .validator(|v| match v.parse::<f32>() {
+                Ok(gain) if gain >= 0.0 => Ok(()),
+                Ok(_) => Err("must not be negative".to_owned()),
+                Err(e) => Err(e.to_string()),
+            })
+            .help("Gain of the client microphone, keep it low if the speakers are near it"),
+        clap::Arg::with_name("rtp_config")
+            .long("rtp-config")
+            .takes_value(true)
+            .validator(|v| parse_hex(&v).map(|_| ()))
+            .help(
+                "AudioSpecificConfig of rtp-aac input in hex, the config= of its SDP. \
+                 By default AAC-LC of the input rate and channels",
+            ),
+    ]
+}
+#[cfg(not(feature = "fdk-aac"))]
+fn fdk_aac_args() -> Vec<clap::Arg<'static, 'static>> {
+    Vec::new()
+}
+
+/// Options of the WebRTC endpoint, which needs libopus.
+#[cfg(feature = "opus")]
+fn opus_args() -> Vec<clap::Arg<'static, 'static>> {
+    vec![
+        clap::Arg::with_name("webrtc")
+            .long("webrtc")
+            .takes_value(true)
+            .validator(|v| {
+                v.parse::<SocketAddr>()
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+            .help("Also serve a page playing the stream over WebRTC, e.g. 0.0.0.0:8080"),
+        clap::Arg::with_name("webrtc_media")
+            .long("webrtc-media")
+            .takes_value(true)
+            .default_value("0.0.0.0:25206")
+            .validator(|v| {
+                v.parse::<SocketAddr>()
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+            .help("UDP address of the WebRTC audio, every interface is offered for 0.0.0.0"),
+    ]
+}
+#[cfg(not(feature = "opus"))]
+fn opus_args() -> Vec<clap::Arg<'static, 'static>> {
+    Vec::new()
+}
+
 fn real_main() -> Result<(), Error> {
     let matches = clap::App::new("Audio Streaming Server")
         .version("1.0")
@@ -167,6 +610,13 @@ fn real_main() -> Result<(), Error> {
                 .takes_value(false)
                 .help("List alsa devices and exit"),
         )
+        .arg(
+            clap::Arg::with_name("probe")
+                .long("probe")
+                .takes_value(false)
+                .requires("list_devices")
+                .help("List what the alsa devices support too"),
+        )
         .arg(
             clap::Arg::with_name("hw_name")
                 .short("d")
@@ -175,15 +625,305 @@ fn real_main() -> Result<(), Error> {
                 .default_value("hw:3,1")
                 .help("Name of the alsa aloop device to listen audio from"),
         )
+        .arg(
+            clap::Arg::with_name("mmap")
+                .long("mmap")
+                .takes_value(false)
+                .help("Capture through the memory-mapped ring buffer instead of copying reads"),
+        )
+        .arg(
+            clap::Arg::with_name("planar")
+                .long("planar")
+                .takes_value(false)
+                .conflicts_with("mmap")
+                .help("Capture a buffer per channel, for devices without interleaved access"),
+        )
+        .arg(
+            clap::Arg::with_name("low_latency")
+                .long("low-latency")
+                .takes_value(false)
+                .conflicts_with_all(&["period", "periods"])
+                .help("Capture in short periods, 10 ms each by default"),
+        )
+        .arg(
+            clap::Arg::with_name("period")
+                .long("period")
+                .takes_value(true)
+                .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Capture period in milliseconds"),
+        )
+        .arg(
+            clap::Arg::with_name("periods")
+                .long("periods")
+                .takes_value(true)
+                .validator(|v| v.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Capture periods in the buffer"),
+        )
+        .arg(
+            clap::Arg::with_name("bind")
+                .short("b")
+                .long("bind")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .default_value("0.0.0.0:25204")
+                .validator(|v| {
+                    v.parse::<SocketAddr>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help("Address to listen for clients on, can be repeated. [::]:port is dual-stack"),
+        )
+        .arg(
+            clap::Arg::with_name("dtx")
+                .long("dtx")
+                .takes_value(false)
+                .help("Send compact silence markers instead of encoded digital silence"),
+        )
+        .arg(
+            clap::Arg::with_name("heartbeat")
+                .long("heartbeat")
+                .takes_value(true)
+                .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Send a heartbeat after this many idle milliseconds, for clients that know them"),
+        )
+        .arg(
+            clap::Arg::with_name("capture")
+                .long("capture")
+                .takes_value(true)
+                .help("Write every sent packet to the file, see stream-audio-replay"),
+        )
+        .arg(
+            clap::Arg::with_name("aes67")
+                .long("aes67")
+                .takes_value(true)
+                .validator(|v| {
+                    v.parse::<SocketAddrV4>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help("Instead of encoding, multicast raw RTP announced with SAP, e.g. 239.69.1.1:5004"),
+        )
+        .arg(
+            clap::Arg::with_name("aes67_encoding")
+                .long("aes67-encoding")
+                .takes_value(true)
+                .possible_values(&["l16", "l24"])
+                .default_value("l24")
+                .help("Sample format of the multicast stream"),
+        )
+        .arg(
+            clap::Arg::with_name("aes67_ptime")
+                .long("aes67-ptime")
+                .takes_value(true)
+                .possible_values(&["1", "4"])
+                .default_value("1")
+                .help("Packet time of the multicast stream in milliseconds"),
+        )
+        .arg(
+            clap::Arg::with_name("aes67_interface")
+                .long("aes67-interface")
+                .takes_value(true)
+                .validator(|v| {
+                    v.parse::<Ipv4Addr>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help("Address of the interface to multicast from"),
+        )
+        .arg(
+            clap::Arg::with_name("rtsp")
+                .long("rtsp")
+                .takes_value(true)
+                .validator(|v| {
+                    v.parse::<SocketAddr>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help("Also serve rtsp://host:port/stream on the address, e.g. 0.0.0.0:8554"),
+        )
+        .arg(
+            clap::Arg::with_name("snapcast")
+                .long("snapcast")
+                .takes_value(true)
+                .validator(|v| {
+                    v.parse::<SocketAddr>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help("Also serve snapclients on the address, e.g. 0.0.0.0:1704"),
+        )
+        .arg(
+            clap::Arg::with_name("hls")
+                .long("hls")
+                .takes_value(true)
+                .validator(|v| {
+                    v.parse::<SocketAddr>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help("Also serve an HLS live playlist at /live.m3u8 on the address, e.g. 0.0.0.0:8081"),
+        )
+        .arg(
+            clap::Arg::with_name("hls_segment")
+                .long("hls-segment")
+                .takes_value(true)
+                .default_value("4")
+                .validator(|v| match v.parse::<u64>() {
+                    Ok(0) => Err("must be at least 1 second".to_owned()),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e.to_string()),
+                })
+                .help("HLS segment duration in seconds"),
+        )
+        .arg(
+            clap::Arg::with_name("hls_window")
+                .long("hls-window")
+                .takes_value(true)
+                .default_value("5")
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(0) => Err("must be at least 1 segment".to_owned()),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e.to_string()),
+                })
+                .help("Segments listed in the HLS playlist, older ones expire"),
+        )
+        .arg(
+            clap::Arg::with_name("icecast")
+                .long("icecast")
+                .takes_value(true)
+                .validator(|v| parse_icecast_location(&v).map(|_| ()))
+                .help("Also publish to an Icecast server, e.g. localhost:8000/stream"),
+        )
+        .arg(
+            clap::Arg::with_name("icecast_user")
+                .long("icecast-user")
+                .takes_value(true)
+                .default_value("source")
+                .help("Icecast source user"),
+        )
+        .arg(
+            clap::Arg::with_name("icecast_password")
+                .long("icecast-password")
+                .takes_value(true)
+                .default_value("hackme")
+                .help("Icecast source password"),
+        )
+        .arg(
+            clap::Arg::with_name("icecast_legacy")
+                .long("icecast-legacy")
+                .takes_value(false)
+                .help("Use the SOURCE method, for Icecast older than 2.4 and Shoutcast v2"),
+        )
+        .arg(
+            clap::Arg::with_name("icecast_title")
+                .long("icecast-title")
+                .takes_value(true)
+                .help("Stream title shown by Icecast listeners"),
+        )
+        .arg(
+            clap::Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .takes_value(true)
+                .default_value("alsa")
+                .validator(|v| v.parse::<ingest::Input>().map(|_| ()))
+                .help(
+                    "Where to take audio from: alsa, tcp://addr or udp://addr for raw PCM, \
+                     rtp-l16://addr, rtp-l24://addr or rtp-aac://addr",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("input_format")
+                .long("input-format")
+                .takes_value(true)
+                .possible_values(&["u8", "s16le", "s24le", "s24_3le", "s32le", "f32le", "f64le"])
+                .default_value("s16le")
+                .help("Sample format of raw PCM input"),
+        )
+        .arg(
+            clap::Arg::with_name("input_rate")
+                .long("input-rate")
+                .takes_value(true)
+                .default_value("44100")
+                .validator(|v| v.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Sample rate of network input"),
+        )
+        .arg(
+            clap::Arg::with_name("input_channels")
+                .long("input-channels")
+                .takes_value(true)
+                .default_value("2")
+                .validator(|v| match v.parse::<u32>() {
+                    Ok(2) => Ok(()),
+                    Ok(_) => Err("Only stereo input is supported".to_owned()),
+                    Err(e) => Err(e.to_string()),
+                })
+                .help("Channels count of network input"),
+        )
+        .args(&fdk_aac_args())
+        .args(&opus_args())
         .get_matches();
 
     if matches.is_present("list_devices") {
-        list_alsa_devices()?;
+        list_alsa_devices(matches.is_present("probe"))?;
         return Ok(());
     }
 
     let should_play_locally = matches.is_present("play_locally");
     let hw_name = matches.value_of("hw_name").unwrap();
+    let mut buffer_config = if matches.is_present("low_latency") {
+        alsa::BufferConfig::low_latency()
+    } else {
+        alsa::BufferConfig::default()
+    };
+    if let Some(v) = matches.value_of("period") {
+        let period = Duration::from_millis(v.parse().unwrap());
+        buffer_config.period = Some(alsa::Size::Time(period));
+        // The buffer follows from the periods then.
+        buffer_config.buffer = None;
+    }
+    if let Some(v) = matches.value_of("periods") {
+        buffer_config.periods = Some(v.parse().unwrap());
+    }
+    let alsa_config = alsa::Config {
+        access: if matches.is_present("mmap") {
+            alsa::Access::Mmap
+        } else if matches.is_present("planar") {
+            alsa::Access::NonInterleaved
+        } else {
+            alsa::Access::ReadWrite
+        },
+        buffer: buffer_config,
+        ..Default::default()
+    };
+    if let Some(group) = matches.value_of("aes67") {
+        let encoding = match matches.value_of("aes67_encoding").unwrap() {
+            "l16" => aes67::Encoding::L16,
+            _ => aes67::Encoding::L24,
+        };
+        let settings = aes67::Settings {
+            group: group.parse().unwrap(),
+            interface: matches
+                .value_of("aes67_interface")
+                .map(|v| v.parse().unwrap()),
+            encoding,
+            ptime: match matches.value_of("aes67_ptime").unwrap() {
+                "4" => aes67::PacketTime::Ms4,
+                _ => aes67::PacketTime::Ms1,
+            },
+            ..Default::default()
+        };
+        let params = alsa::Params {
+            format: match encoding {
+                aes67::Encoding::L16 => alsa::Format::S16Le,
+                aes67::Encoding::L24 => alsa::Format::FloatLe,
+            },
+            channels: 2,
+            rate: aes67::RATE,
+        };
+        return multicast(hw_name.to_owned(), params, alsa_config, settings);
+    }
 
     let params = alsa::Params {
         format: alsa::Format::FloatLe,
@@ -191,7 +931,125 @@ fn real_main() -> Result<(), Error> {
         rate: 44100,
     };
 
-    record(hw_name.to_owned(), params, should_play_locally)?;
+    let rtsp_settings = matches
+        .value_of("rtsp")
+        .map(|v| net_server::rtsp::Settings {
+            addr: v.parse().unwrap(),
+            path: "/stream".to_owned(),
+            rate: params.rate,
+            channels: params.channels,
+            bit_rate: ENCODER_BIT_RATE,
+            session_timeout: Duration::from_secs(60),
+        });
+    let snapcast_settings = matches
+        .value_of("snapcast")
+        .map(|v| net_server::snapcast::Settings {
+            addr: v.parse().unwrap(),
+            params,
+            buffer: Duration::from_secs(1),
+        });
+    #[cfg(feature = "fdk-aac")]
+    let backchannel_settings =
+        matches
+            .value_of("mic_key")
+            .map(|v| net_server::backchannel::Settings {
+                key: v.to_owned(),
+                device: matches.value_of("mic_device").unwrap().to_owned(),
+                gain: matches.value_of("mic_gain").unwrap().parse().unwrap(),
+                ..Default::default()
+            });
+    let server_settings = net_server::Settings {
+        addrs: matches
+            .values_of("bind")
+            .unwrap()
+            .map(|v| v.parse().unwrap())
+            .collect(),
+        heartbeat_interval: matches
+            .value_of("heartbeat")
+            .map(|v| Duration::from_millis(v.parse().unwrap())),
+        capture: matches.value_of("capture").map(|v| v.to_owned()),
+        rtsp: rtsp_settings,
+        snapcast: snapcast_settings,
+        #[cfg(feature = "fdk-aac")]
+        backchannel: backchannel_settings,
+        ..Default::default()
+    };
+
+    #[cfg(feature = "fdk-aac")]
+    if let Some(upstream) = matches.value_of("relay") {
+        return relay(upstream.parse().unwrap(), server_settings);
+    }
+
+    let input_params = alsa::Params {
+        format: match matches.value_of("input_format").unwrap() {
+            "u8" => alsa::Format::U8,
+            "s24le" => alsa::Format::S24Le,
+            "s24_3le" => alsa::Format::S24_3Le,
+            "s32le" => alsa::Format::S32Le,
+            "f32le" => alsa::Format::FloatLe,
+            "f64le" => alsa::Format::Float64Le,
+            _ => alsa::Format::S16Le,
+        },
+        channels: matches.value_of("input_channels").unwrap().parse().unwrap(),
+        rate: matches.value_of("input_rate").unwrap().parse().unwrap(),
+    };
+    let input: ingest::Input = matches.value_of("input").unwrap().parse().unwrap();
+    let rtp_config = matches
+        .value_of("rtp_config")
+        .map(|v| parse_hex(v).unwrap());
+    let source = match input.open_network(input_params, rtp_config.as_deref())? {
+        Some(source) => {
+            println!("Receiving {:?}", input);
+            source
+        }
+        None => open_alsa_source(hw_name.to_owned(), params, alsa_config)?,
+    };
+
+    let icecast_settings = matches.value_of("icecast").map(|v| {
+        let (host, port, mount) = parse_icecast_location(v).unwrap();
+        icecast::Settings {
+            host,
+            port,
+            mount,
+            user: matches.value_of("icecast_user").unwrap().to_owned(),
+            password: matches.value_of("icecast_password").unwrap().to_owned(),
+            method: if matches.is_present("icecast_legacy") {
+                icecast::Method::Source
+            } else {
+                icecast::Method::Put
+            },
+            ..Default::default()
+        }
+    });
+
+    let hls_settings = matches.value_of("hls").map(|v| hls::Settings {
+        addr: v.parse().unwrap(),
+        segment_duration: Duration::from_secs(
+            matches.value_of("hls_segment").unwrap().parse().unwrap(),
+        ),
+        window: matches.value_of("hls_window").unwrap().parse().unwrap(),
+    });
+    #[cfg(feature = "opus")]
+    let webrtc_settings = matches.value_of("webrtc").map(|v| webrtc::Settings {
+        http_addr: v.parse().unwrap(),
+        media_addr: matches.value_of("webrtc_media").unwrap().parse().unwrap(),
+        ..Default::default()
+    });
+
+    record(
+        source,
+        params,
+        should_play_locally,
+        server_settings,
+        matches.is_present("dtx"),
+        Outputs {
+            icecast: icecast_settings,
+            icecast_title: matches.value_of("icecast_title").map(|v| v.to_owned()),
+            hls: hls_settings,
+            #[cfg(feature = "opus")]
+            webrtc: webrtc_settings,
+        },
+    )?;
 
     Ok(())
 }
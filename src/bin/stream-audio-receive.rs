@@ -0,0 +1,164 @@
+use audio_sharing_pc::alsa;
+use audio_sharing_pc::error::*;
+use audio_sharing_pc::exit_listener;
+use audio_sharing_pc::net_client;
+use clap;
+use std::net::SocketAddr;
+use std::process::exit;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+/// Bytes written to the device at once.
+const PLAYBACK_CHUNK_FRAMES: usize = 256;
+
+fn play(jitter_buffer: Arc<net_client::JitterBuffer>, player: alsa::SndPcm) {
+    let params = player.get_params();
+    let silence = params.format.silence_byte();
+    let mut buffer = vec![0; PLAYBACK_CHUNK_FRAMES * params.bytes_per_frame()];
+
+    while jitter_buffer.pop(&mut buffer, silence) {
+        if let Err(e) = player.write_interleaved(&buffer) {
+            eprintln!("Error playing audio: {}", e);
+            return;
+        }
+    }
+}
+
+fn receive(
+    client_settings: net_client::Settings,
+    device: String,
+    params: alsa::Params,
+    latency_ms: u32,
+) -> Result<(), Error> {
+    let on_exit_flag = exit_listener::listen_on_exit()?.signal_flag;
+
+    let player = alsa::SndPcm::open(device, alsa::Stream::Playback, params)?;
+    println!("Player settings: {}", player.dump_settings()?);
+    let params = player.get_params();
+
+    let mut decoder = net_client::StreamDecoder::new(params)?;
+
+    let target = (latency_ms * params.rate / 1000) as usize * params.bytes_per_frame();
+    let jitter_buffer = Arc::new(net_client::JitterBuffer::new(target, target * 4));
+
+    let player_thread = {
+        let jitter_buffer = jitter_buffer.clone();
+        thread::Builder::new()
+            .name("Player".to_owned())
+            .spawn(move || play(jitter_buffer, player))
+            .map_err(|e| IoError::new("spawning player thread", e))?
+    };
+
+    let mut client = net_client::NetClient::connect(client_settings)?;
+    println!(
+        "Connected to {}: {}",
+        client.server(),
+        String::from_utf8_lossy(client.info())
+    );
+
+    let mut pcm = Vec::new();
+    let res = loop {
+        if on_exit_flag.load(Ordering::SeqCst) {
+            eprintln!("Caught Signal, finishing job");
+            break Ok(());
+        }
+
+        let event = match client.recv() {
+            Ok(event) => event,
+            Err(e) => break Err(e),
+        };
+        match event {
+            net_client::Event::Bye {
+                reason,
+                reconnect_after,
+            } => {
+                println!(
+                    "Server said goodbye: {:?}, reconnect after: {:?}",
+                    reason, reconnect_after
+                );
+                break Ok(());
+            }
+            net_client::Event::Lost { seq } => eprintln!("Packet #{} is lost", seq),
+            _ => (),
+        }
+
+        pcm.clear();
+        if let Err(e) = decoder.decode(&event, &mut pcm) {
+            break Err(e);
+        }
+        jitter_buffer.push(&pcm);
+    };
+
+    jitter_buffer.close();
+    if player_thread.join().is_err() {
+        eprintln!("Player thread panicked");
+    }
+    println!("Underruns: {}", jitter_buffer.underruns());
+
+    res
+}
+
+fn real_main() -> Result<(), Error> {
+    let matches = clap::App::new("Stream Audio Receiver")
+        .version("1.0")
+        .about("Receives audio from the Stream Audio server and plays it with alsa")
+        .arg(
+            clap::Arg::with_name("server")
+                .required(true)
+                .validator(|v| {
+                    v.parse::<SocketAddr>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help("Address of the server, e.g. 192.168.1.2:25204"),
+        )
+        .arg(
+            clap::Arg::with_name("device")
+                .short("d")
+                .long("device")
+                .takes_value(true)
+                .default_value("default")
+                .help("Name of the alsa device to play audio to"),
+        )
+        .arg(
+            clap::Arg::with_name("latency")
+                .short("l")
+                .long("latency-ms")
+                .takes_value(true)
+                .default_value("100")
+                .validator(|v| v.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Jitter buffer size in milliseconds"),
+        )
+        .get_matches();
+
+    let client_settings = net_client::Settings {
+        server: matches.value_of("server").unwrap().parse().unwrap(),
+        ..Default::default()
+    };
+
+    let params = alsa::Params {
+        format: alsa::Format::FloatLe,
+        channels: 2,
+        rate: 44100,
+    };
+
+    receive(
+        client_settings,
+        matches.value_of("device").unwrap().to_owned(),
+        params,
+        matches.value_of("latency").unwrap().parse().unwrap(),
+    )
+}
+
+fn main() {
+    let ret_code = match real_main() {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error occurred: {}", e);
+            1
+        }
+    };
+
+    exit(ret_code);
+}
@@ -0,0 +1,105 @@
+use audio_sharing_pc::error::*;
+use audio_sharing_pc::net_server::capture;
+use clap;
+use std::net::{SocketAddr, UdpSocket};
+use std::process::exit;
+use std::thread;
+use std::time::Instant;
+
+/// Re-sends packets captured for `dest` to `target`, keeping the original intervals.
+fn replay(fname: String, target: SocketAddr, dest: Option<SocketAddr>) -> Result<(), Error> {
+    let bind_addr = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket =
+        UdpSocket::bind(bind_addr).map_err(|e| IoError::new("creating a replay socket", e))?;
+
+    let mut reader = capture::CaptureReader::open(fname)?;
+    let mut dest = dest;
+    let mut start = None;
+    let mut sent = 0;
+
+    while let Some(record) = reader.read_record()? {
+        match dest {
+            Some(dest) if dest != record.dest => continue,
+            Some(_) => (),
+            None => {
+                println!("Replaying packets sent to {}", record.dest);
+                dest = Some(record.dest);
+            }
+        }
+
+        let (started_at, first_sent_at) = *start.get_or_insert((Instant::now(), record.sent_at));
+        let offset = record
+            .sent_at
+            .checked_sub(first_sent_at)
+            .unwrap_or_default();
+        let elapsed = started_at.elapsed();
+        if offset > elapsed {
+            thread::sleep(offset - elapsed);
+        }
+
+        socket
+            .send_to(&record.pkt, target)
+            .map_err(|e| IoError::new(format!("sending a packet to {}", target), e))?;
+        sent += 1;
+    }
+
+    println!("Replayed {} packets", sent);
+    Ok(())
+}
+
+fn real_main() -> Result<(), Error> {
+    let addr_validator = |v: String| {
+        v.parse::<SocketAddr>()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    };
+
+    let matches = clap::App::new("Stream Audio Replay")
+        .version("1.0")
+        .about("Re-sends a packet capture made by the server with the original timing")
+        .arg(
+            clap::Arg::with_name("capture")
+                .required(true)
+                .help("Capture file written by the server"),
+        )
+        .arg(
+            clap::Arg::with_name("target")
+                .short("t")
+                .long("target")
+                .takes_value(true)
+                .required(true)
+                .validator(addr_validator)
+                .help("Address to send the packets to"),
+        )
+        .arg(
+            clap::Arg::with_name("dest")
+                .short("d")
+                .long("dest")
+                .takes_value(true)
+                .validator(addr_validator)
+                .help("Replay only packets sent to this client. Defaults to the first one"),
+        )
+        .get_matches();
+
+    replay(
+        matches.value_of("capture").unwrap().to_owned(),
+        matches.value_of("target").unwrap().parse().unwrap(),
+        matches.value_of("dest").map(|v| v.parse().unwrap()),
+    )
+}
+
+fn main() {
+    let ret_code = match real_main() {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error occurred: {}", e);
+            1
+        }
+    };
+
+    exit(ret_code);
+}
@@ -0,0 +1,77 @@
+use super::{fdk_aac_ffi, CodecError};
+use crate::error::Error;
+
+/// Samples of the longest frame: 2048 of HE-AAC, for up to 8 channels.
+const MAX_AAC_FRAME_SAMPLES: usize = 2048 * 8;
+
+/// Decodes raw AAC access units, as the encoder makes them and RTP carries them,
+/// to interleaved 16 bit samples.
+pub struct AacDecoder {
+    raw_ptr: fdk_aac_ffi::HANDLE_AACDECODER,
+    pcm: Vec<i16>,
+}
+
+impl AacDecoder {
+    /// `config` is the AudioSpecificConfig of the stream, see `adts::audio_specific_config`.
+    pub fn new(config: &[u8]) -> Result<Self, Error> {
+        let raw_ptr = unsafe { fdk_aac_ffi::aacDecoder_Open(fdk_aac_ffi::TT_MP4_RAW, 1) };
+        if raw_ptr.is_null() {
+            return Err(CodecError::new("fdk-aac", 0, "opening AAC decoder").into());
+        }
+        let res = Self {
+            raw_ptr,
+            pcm: vec![0; MAX_AAC_FRAME_SAMPLES],
+        };
+
+        let mut config = config.to_vec();
+        let mut conf_ptr = config.as_mut_ptr();
+        let len = config.len() as libc::c_uint;
+        let err = unsafe { fdk_aac_ffi::aacDecoder_ConfigRaw(res.raw_ptr, &mut conf_ptr, &len) };
+        if err != fdk_aac_ffi::AAC_DEC_OK {
+            return Err(CodecError::new("fdk-aac", err, "configuring AAC decoder").into());
+        }
+
+        Ok(res)
+    }
+
+    /// Decodes an access unit. It's empty if the decoder needs more to make a frame.
+    pub fn decode(&mut self, au: &[u8]) -> Result<&[i16], Error> {
+        let mut data = au.to_vec();
+        let mut data_ptr = data.as_mut_ptr();
+        let len = data.len() as libc::c_uint;
+        // A raw access unit is taken whole, nothing is left to fill again.
+        let mut left = len;
+        let err =
+            unsafe { fdk_aac_ffi::aacDecoder_Fill(self.raw_ptr, &mut data_ptr, &len, &mut left) };
+        if err != fdk_aac_ffi::AAC_DEC_OK {
+            return Err(CodecError::new("fdk-aac", err, "filling AAC decoder").into());
+        }
+
+        let err = unsafe {
+            fdk_aac_ffi::aacDecoder_DecodeFrame(
+                self.raw_ptr,
+                self.pcm.as_mut_ptr(),
+                self.pcm.len() as libc::c_int,
+                0,
+            )
+        };
+        match err {
+            fdk_aac_ffi::AAC_DEC_OK => (),
+            fdk_aac_ffi::AAC_DEC_NOT_ENOUGH_BITS => return Ok(&[]),
+            _ => return Err(CodecError::new("fdk-aac", err, "decoding AAC frame").into()),
+        }
+
+        let info = unsafe { fdk_aac_ffi::aacDecoder_GetStreamInfo(self.raw_ptr) };
+        if info.is_null() {
+            return Err(CodecError::new("fdk-aac", err, "getting AAC stream info").into());
+        }
+        let samples = unsafe { ((*info).frameSize * (*info).numChannels) as usize };
+        Ok(&self.pcm[..samples.min(self.pcm.len())])
+    }
+}
+impl Drop for AacDecoder {
+    fn drop(&mut self) {
+        unsafe { fdk_aac_ffi::aacDecoder_Close(self.raw_ptr) };
+    }
+}
+unsafe impl Send for AacDecoder {}
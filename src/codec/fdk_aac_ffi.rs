@@ -0,0 +1,43 @@
+use libc::{c_int, c_uint, c_void};
+
+pub type HANDLE_AACDECODER = *mut c_void;
+
+pub type TRANSPORT_TYPE = c_int;
+pub const TT_MP4_RAW: TRANSPORT_TYPE = 0;
+pub const TT_MP4_ADTS: TRANSPORT_TYPE = 2;
+
+pub type AAC_DECODER_ERROR = c_int;
+pub const AAC_DEC_OK: AAC_DECODER_ERROR = 0;
+pub const AAC_DEC_NOT_ENOUGH_BITS: AAC_DECODER_ERROR = 0x1002;
+
+/// Only the leading fields, the struct is read through the pointer the decoder owns.
+#[repr(C)]
+pub struct CStreamInfo {
+    pub sampleRate: c_int,
+    pub frameSize: c_int,
+    pub numChannels: c_int,
+}
+
+#[link(name = "fdk-aac")]
+extern "C" {
+    pub fn aacDecoder_Open(transportFmt: TRANSPORT_TYPE, nrOfLayers: c_uint) -> HANDLE_AACDECODER;
+    pub fn aacDecoder_ConfigRaw(
+        decoder: HANDLE_AACDECODER,
+        conf: *mut *mut u8,
+        length: *const c_uint,
+    ) -> AAC_DECODER_ERROR;
+    pub fn aacDecoder_Fill(
+        decoder: HANDLE_AACDECODER,
+        pBuffer: *mut *mut u8,
+        bufferSize: *const c_uint,
+        bytesValid: *mut c_uint,
+    ) -> AAC_DECODER_ERROR;
+    pub fn aacDecoder_DecodeFrame(
+        decoder: HANDLE_AACDECODER,
+        pTimeData: *mut i16,
+        timeDataSize: c_int,
+        flags: c_uint,
+    ) -> AAC_DECODER_ERROR;
+    pub fn aacDecoder_GetStreamInfo(decoder: HANDLE_AACDECODER) -> *mut CStreamInfo;
+    pub fn aacDecoder_Close(decoder: HANDLE_AACDECODER);
+}
@@ -0,0 +1,48 @@
+//! Codecs the ffmpeg crate doesn't provide, bound directly: AAC decoding with fdk-aac
+//! and Opus encoding with libopus. Each is behind the feature of its library.
+
+#[cfg(feature = "fdk-aac")]
+mod aac;
+#[cfg(feature = "fdk-aac")]
+#[allow(dead_code, non_snake_case, non_camel_case_types)]
+mod fdk_aac_ffi;
+#[cfg(feature = "opus")]
+mod opus;
+#[cfg(feature = "opus")]
+#[allow(non_snake_case)]
+mod opus_ffi;
+
+#[cfg(feature = "fdk-aac")]
+pub use aac::AacDecoder;
+#[cfg(feature = "opus")]
+pub use opus::OpusEncoder;
+
+use std::borrow::Cow;
+
+#[derive(Debug)]
+pub struct CodecError {
+    codec: &'static str,
+    code: i32,
+    context: Cow<'static, str>,
+}
+
+impl CodecError {
+    fn new<S: Into<Cow<'static, str>>>(codec: &'static str, code: i32, context: S) -> Self {
+        Self {
+            codec,
+            code,
+            context: context.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{} error {:#x}. In context: {}",
+            self.codec, self.code, self.context
+        )
+    }
+}
+impl std::error::Error for CodecError {}
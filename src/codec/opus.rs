@@ -0,0 +1,95 @@
+use super::{opus_ffi, CodecError};
+use crate::alsa;
+use crate::error::Error;
+
+/// The packet size libopus recommends as the limit.
+const MAX_OPUS_PKT_LEN: usize = 4000;
+
+/// Encodes interleaved PCM to Opus packets of a fixed duration.
+pub struct OpusEncoder {
+    raw_ptr: *mut opus_ffi::OpusEncoder,
+    format: alsa::Format,
+    /// Samples per channel in a frame.
+    frame_samples: usize,
+    /// Samples of all channels in a frame.
+    frame_len: usize,
+    /// Samples waiting for a whole frame.
+    pending: Vec<f32>,
+    pkt: Vec<u8>,
+}
+
+impl OpusEncoder {
+    /// `params` is the format of the PCM given to `write`. Every packet carries
+    /// `frame_samples` samples per channel, which must be 2.5, 5, 10, 20, 40 or 60 ms.
+    pub fn new(params: alsa::Params, bit_rate: u32, frame_samples: usize) -> Result<Self, Error> {
+        let mut err = 0;
+        let raw_ptr = unsafe {
+            opus_ffi::opus_encoder_create(
+                params.rate as i32,
+                params.channels as libc::c_int,
+                opus_ffi::OPUS_APPLICATION_AUDIO,
+                &mut err,
+            )
+        };
+        if raw_ptr.is_null() || err != opus_ffi::OPUS_OK {
+            return Err(CodecError::new("opus", err, "creating Opus encoder").into());
+        }
+        let res = Self {
+            raw_ptr,
+            format: params.format,
+            frame_samples,
+            frame_len: frame_samples * params.channels as usize,
+            pending: Vec::new(),
+            pkt: vec![0; MAX_OPUS_PKT_LEN],
+        };
+
+        let err = unsafe {
+            opus_ffi::opus_encoder_ctl(
+                res.raw_ptr,
+                opus_ffi::OPUS_SET_BITRATE_REQUEST,
+                bit_rate as i32,
+            )
+        };
+        if err != opus_ffi::OPUS_OK {
+            return Err(CodecError::new("opus", err, "setting Opus bit rate").into());
+        }
+
+        Ok(res)
+    }
+
+    pub fn write(&mut self, pcm: &[u8]) {
+        let format = self.format;
+        let samples = pcm.chunks_exact(format.bytes_per_sample());
+        self.pending
+            .extend(samples.map(|sample| format.sample_to_f32(sample)));
+    }
+
+    /// Encodes the next frame, if enough samples have been written.
+    pub fn read(&mut self) -> Result<Option<&[u8]>, Error> {
+        if self.pending.len() < self.frame_len {
+            return Ok(None);
+        }
+
+        let len = unsafe {
+            opus_ffi::opus_encode_float(
+                self.raw_ptr,
+                self.pending.as_ptr(),
+                self.frame_samples as libc::c_int,
+                self.pkt.as_mut_ptr(),
+                self.pkt.len() as i32,
+            )
+        };
+        self.pending.drain(..self.frame_len);
+        if len < 0 {
+            return Err(CodecError::new("opus", len, "encoding Opus frame").into());
+        }
+
+        Ok(Some(&self.pkt[..len as usize]))
+    }
+}
+impl Drop for OpusEncoder {
+    fn drop(&mut self) {
+        unsafe { opus_ffi::opus_encoder_destroy(self.raw_ptr) };
+    }
+}
+unsafe impl Send for OpusEncoder {}
@@ -0,0 +1,26 @@
+use libc::{c_int, c_void};
+
+pub type OpusEncoder = c_void;
+
+pub const OPUS_OK: c_int = 0;
+pub const OPUS_APPLICATION_AUDIO: c_int = 2049;
+pub const OPUS_SET_BITRATE_REQUEST: c_int = 4002;
+
+#[link(name = "opus")]
+extern "C" {
+    pub fn opus_encoder_create(
+        Fs: i32,
+        channels: c_int,
+        application: c_int,
+        error: *mut c_int,
+    ) -> *mut OpusEncoder;
+    pub fn opus_encoder_ctl(st: *mut OpusEncoder, request: c_int, ...) -> c_int;
+    pub fn opus_encode_float(
+        st: *mut OpusEncoder,
+        pcm: *const f32,
+        frame_size: c_int,
+        data: *mut u8,
+        max_data_bytes: i32,
+    ) -> i32;
+    pub fn opus_encoder_destroy(st: *mut OpusEncoder);
+}
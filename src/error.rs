@@ -1,6 +1,8 @@
 use crate::alsa;
 use crate::audio_saver;
 use crate::channel;
+#[cfg(any(feature = "fdk-aac", feature = "opus"))]
+use crate::codec;
 use crate::ffmpeg;
 use std::borrow::Cow;
 use std::io;
@@ -21,8 +23,11 @@ pub enum ErrorRepr {
     ChannelRecv(channel::RecvError),
     Alsa(alsa::AlsaError),
     Ffmpeg(ffmpeg::Error),
+    #[cfg(any(feature = "fdk-aac", feature = "opus"))]
+    Codec(codec::CodecError),
     Nul(std::ffi::NulError),
     BytesWithNull(std::ffi::FromBytesWithNulError),
+    Ssl(openssl::error::ErrorStack),
 }
 
 #[derive(Debug)]
@@ -46,6 +51,14 @@ impl Error {
     pub fn get_repr(&self) -> &ErrorRepr {
         &self.repr
     }
+
+    /// The audio device went away: unplugged, or its driver unloaded.
+    pub fn is_device_disconnected(&self) -> bool {
+        match *self.repr {
+            ErrorRepr::Alsa(ref e) => e.is_disconnected(),
+            _ => false,
+        }
+    }
 }
 
 impl FileError {
@@ -55,7 +68,7 @@ impl FileError {
 }
 
 impl IoError {
-    pub(crate) fn new<S: Into<Cow<'static, str>>>(context: S, error: io::Error) -> Self {
+    pub fn new<S: Into<Cow<'static, str>>>(context: S, error: io::Error) -> Self {
         Self {
             context: context.into(),
             error,
@@ -75,8 +88,11 @@ impl ::std::fmt::Display for Error {
             ErrorRepr::ChannelRecv(e) => write!(f, "Channel Error {}", e),
             ErrorRepr::Alsa(ref e) => write!(f, "Alsa Error {}", e),
             ErrorRepr::Ffmpeg(ref e) => write!(f, "ffmpeg Error {}", e),
+            #[cfg(any(feature = "fdk-aac", feature = "opus"))]
+            ErrorRepr::Codec(ref e) => write!(f, "Codec Error {}", e),
             ErrorRepr::Nul(ref e) => write!(f, "There is null byte in the string. {}", e),
             ErrorRepr::BytesWithNull(ref e) => e.fmt(f),
+            ErrorRepr::Ssl(ref e) => write!(f, "OpenSSL Error {}", e),
         }
     }
 }
@@ -125,6 +141,12 @@ impl From<ffmpeg::Error> for Error {
         }
     }
 }
+#[cfg(any(feature = "fdk-aac", feature = "opus"))]
+impl From<codec::CodecError> for Error {
+    fn from(e: codec::CodecError) -> Self {
+        Self::new(ErrorRepr::Codec(e))
+    }
+}
 impl From<std::ffi::NulError> for Error {
     fn from(e: std::ffi::NulError) -> Self {
         Self::new(ErrorRepr::Nul(e))
@@ -135,6 +157,11 @@ impl From<std::ffi::FromBytesWithNulError> for Error {
         Self::new(ErrorRepr::BytesWithNull(e))
     }
 }
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        Self::new(ErrorRepr::Ssl(e))
+    }
+}
 impl From<ErrorRepr> for Error {
     fn from(e: ErrorRepr) -> Self {
         Self::new(e)
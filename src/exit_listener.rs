@@ -11,14 +11,32 @@ pub struct SignalEvent {
     registration: mio::Registration,
 }
 
-pub fn listen_on_exit() -> Result<SignalEvent, Error> {
-    let (registration, set_register) = mio::Registration::new2();
+/// Sets off a `SignalEvent` on request, for the code that has to stop without a signal.
+#[derive(Clone, Debug)]
+pub struct ExitTrigger {
+    flag: Arc<AtomicBool>,
+    set_readiness: mio::SetReadiness,
+}
+
+/// An event that is set off only by its trigger.
+pub fn exit_event() -> (SignalEvent, ExitTrigger) {
+    let (registration, set_readiness) = mio::Registration::new2();
     let flag = Arc::new(AtomicBool::new(false));
 
     let event = SignalEvent {
         signal_flag: flag.clone(),
         registration,
     };
+    let trigger = ExitTrigger {
+        flag,
+        set_readiness,
+    };
+
+    (event, trigger)
+}
+
+pub fn listen_on_exit() -> Result<SignalEvent, Error> {
+    let (event, trigger) = exit_event();
 
     let signals = signal_hook::iterator::Signals::new(&[signal_hook::SIGINT, signal_hook::SIGTERM])
         .map_err(|e| IoError::new("registering signals", e))?;
@@ -27,14 +45,8 @@ pub fn listen_on_exit() -> Result<SignalEvent, Error> {
         .name("Signal Listener".to_owned())
         .spawn(move || {
             for _signal in signals.forever() {
-                flag.store(true, Ordering::SeqCst);
-                let err = set_register.set_readiness(mio::Ready::readable());
-
-                if err.is_err() {
-                    eprintln!(
-                        "Error sending Exit signal via mio::Poll user event: {:?}",
-                        err
-                    );
+                if let Err(e) = trigger.trigger() {
+                    eprintln!("{}", e);
                 }
             }
         })
@@ -43,6 +55,16 @@ pub fn listen_on_exit() -> Result<SignalEvent, Error> {
     Ok(event)
 }
 
+impl ExitTrigger {
+    pub fn trigger(&self) -> Result<(), Error> {
+        self.flag.store(true, Ordering::SeqCst);
+        self.set_readiness
+            .set_readiness(mio::Ready::readable())
+            .map_err(|e| IoError::new("sending Exit signal via mio::Poll user event", e))?;
+        Ok(())
+    }
+}
+
 impl SignalEvent {
     pub fn has_signal(&self) -> bool {
         self.signal_flag.load(Ordering::SeqCst)
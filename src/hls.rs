@@ -0,0 +1,294 @@
+//! HLS live output: the AAC frames are cut into packed audio segments (ADTS with the ID3
+//! timestamp HLS requires), kept in memory and served with a rolling playlist over HTTP.
+//! Smart TVs, iOS and Safari play `http://host:port/live.m3u8` natively.
+
+use crate::adts;
+use crate::alsa;
+use crate::error::{Error, IoError};
+use crate::http;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub const PLAYLIST_PATH: &str = "/live.m3u8";
+/// Samples in an AAC-LC frame.
+const AAC_FRAME_SAMPLES: u64 = 1024;
+/// MPEG-2 TS timestamps run at 90 kHz and wrap at 33 bits.
+const TS_CLOCK: u64 = 90000;
+const TS_MASK: u64 = (1 << 33) - 1;
+const TIMESTAMP_OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+/// Segments stay available for this many segments after leaving the playlist,
+/// for players that loaded it just before.
+const EXPIRED_KEPT: usize = 2;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub addr: SocketAddr,
+    /// Segments are cut at the last whole frame within this duration.
+    pub segment_duration: Duration,
+    /// Segments listed in the playlist.
+    pub window: usize,
+}
+
+/// Segments the frames given to `send_frame` and serves them from a separate thread
+/// until dropped.
+pub struct HlsServer {
+    playlist: Arc<Mutex<Playlist>>,
+    rate: u32,
+    channels: u32,
+    segment_frames: u64,
+    /// ADTS frames of the segment being made.
+    current: Vec<u8>,
+    current_frames: u64,
+    /// Samples sent before the current segment, for its timestamp.
+    samples: u64,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Playlist {
+    window: usize,
+    /// The sequence number of the first segment.
+    first_seq: u64,
+    segments: VecDeque<Segment>,
+}
+
+struct Segment {
+    duration: f64,
+    data: Arc<Vec<u8>>,
+}
+
+impl HlsServer {
+    /// `params` are the ones of the encoder, the frames are AAC-LC.
+    pub fn new(settings: Settings, params: alsa::Params) -> Result<Self, Error> {
+        if adts::header(params.rate, params.channels, 0).is_none() || settings.window == 0 {
+            return Err(IoError::new(
+                "starting HLS",
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "can't make segments of {:?} with the window of {}",
+                        params, settings.window
+                    ),
+                ),
+            )
+            .into());
+        }
+
+        let listener = TcpListener::bind(settings.addr)
+            .map_err(|e| IoError::new(format!("binding HLS to {}", settings.addr), e))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| IoError::new("setting HLS listener non-blocking", e))?;
+
+        let playlist = Arc::new(Mutex::new(Playlist {
+            window: settings.window,
+            first_seq: 0,
+            segments: VecDeque::new(),
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let playlist = playlist.clone();
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("HLS".to_owned())
+                .spawn(move || {
+                    let handler = move |req: &http::Request| on_request(&playlist, req);
+                    http::serve("HLS", listener, &stop, Arc::new(handler))
+                })
+                .map_err(|e| IoError::new("spawning HLS thread", e))?
+        };
+
+        let segment_samples =
+            settings.segment_duration.as_millis() as u64 * u64::from(params.rate) / 1000;
+        Ok(Self {
+            playlist,
+            rate: params.rate,
+            channels: params.channels,
+            segment_frames: (segment_samples / AAC_FRAME_SAMPLES).max(1),
+            current: Vec::new(),
+            current_frames: 0,
+            samples: 0,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Adds a raw AAC frame, publishing the segment when it is long enough.
+    pub fn send_frame(&mut self, aac: &[u8]) {
+        if self.current.is_empty() {
+            write_timestamp(
+                self.samples * TS_CLOCK / u64::from(self.rate),
+                &mut self.current,
+            );
+        }
+        if !adts::write_frame(self.rate, self.channels, aac, &mut self.current) {
+            eprintln!("AAC frame of {} bytes is too long for HLS", aac.len());
+            return;
+        }
+
+        self.current_frames += 1;
+        if self.current_frames >= self.segment_frames {
+            let samples = self.current_frames * AAC_FRAME_SAMPLES;
+            let segment = Segment {
+                duration: samples as f64 / f64::from(self.rate),
+                data: Arc::new(std::mem::take(&mut self.current)),
+            };
+            self.playlist.lock().unwrap().push(segment);
+            self.samples += samples;
+            self.current_frames = 0;
+        }
+    }
+}
+impl Drop for HlsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                eprintln!("HLS thread panicked");
+            }
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:8081".parse().unwrap(),
+            segment_duration: Duration::from_secs(4),
+            window: 5,
+        }
+    }
+}
+
+impl Playlist {
+    fn push(&mut self, segment: Segment) {
+        self.segments.push_back(segment);
+        while self.segments.len() > self.window + EXPIRED_KEPT {
+            self.segments.pop_front();
+            self.first_seq += 1;
+        }
+    }
+
+    /// `None` until there is a segment.
+    fn to_m3u8(&self) -> Option<String> {
+        let skip = self.segments.len().saturating_sub(self.window);
+        let listed = self.segments.iter().skip(skip);
+        let target = listed.clone().map(|s| s.duration.ceil() as u64).max()?;
+
+        let mut m3u8 = String::new();
+        let _ = write!(
+            m3u8,
+            "#EXTM3U\n\
+             #EXT-X-VERSION:3\n\
+             #EXT-X-TARGETDURATION:{}\n\
+             #EXT-X-MEDIA-SEQUENCE:{}\n",
+            target,
+            self.first_seq + skip as u64
+        );
+        for (i, segment) in listed.enumerate() {
+            let seq = self.first_seq + (skip + i) as u64;
+            let _ = write!(
+                m3u8,
+                "#EXTINF:{:.3},\nsegment{}.aac\n",
+                segment.duration, seq
+            );
+        }
+        Some(m3u8)
+    }
+
+    fn segment(&self, seq: u64) -> Option<Arc<Vec<u8>>> {
+        let idx = seq.checked_sub(self.first_seq)?;
+        self.segments.get(idx as usize).map(|s| s.data.clone())
+    }
+}
+
+fn on_request(playlist: &Mutex<Playlist>, req: &http::Request) -> http::Response {
+    if req.method != "GET" {
+        return http::Response::not_found();
+    }
+
+    if req.path == PLAYLIST_PATH {
+        return match playlist.lock().unwrap().to_m3u8() {
+            Some(m3u8) => http::Response::ok("application/vnd.apple.mpegurl", m3u8),
+            None => http::Response::not_found(),
+        };
+    }
+
+    let seq = req
+        .path
+        .trim_start_matches("/segment")
+        .trim_end_matches(".aac")
+        .parse::<u64>();
+    let segment = match seq {
+        Ok(seq) if req.path.starts_with("/segment") => playlist.lock().unwrap().segment(seq),
+        _ => None,
+    };
+    match segment {
+        Some(data) => http::Response::ok("audio/aac", data.to_vec()),
+        None => http::Response::not_found(),
+    }
+}
+
+/// The ID3v2.4 tag with the PRIV frame giving the 90 kHz timestamp of the first sample,
+/// every packed audio segment starts with it.
+fn write_timestamp(ts: u64, out: &mut Vec<u8>) {
+    let frame_len = TIMESTAMP_OWNER.len() + 8;
+    let tag_len = 10 + frame_len;
+
+    out.extend_from_slice(b"ID3\x04\x00\x00");
+    out.extend_from_slice(&syncsafe(tag_len));
+    out.extend_from_slice(b"PRIV");
+    out.extend_from_slice(&syncsafe(frame_len));
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(TIMESTAMP_OWNER);
+    out.extend_from_slice(&(ts & TS_MASK).to_be_bytes());
+}
+
+/// ID3 sizes have 7 bits per byte.
+fn syncsafe(len: usize) -> [u8; 4] {
+    [
+        (len >> 21) as u8 & 0x7F,
+        (len >> 14) as u8 & 0x7F,
+        (len >> 7) as u8 & 0x7F,
+        len as u8 & 0x7F,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_tag() {
+        let mut out = Vec::new();
+        write_timestamp(0x1_2345_6789, &mut out);
+
+        let mut expected = b"ID3\x04\x00\x00\x00\x00\x00\x3FPRIV\x00\x00\x00\x35\x00\x00".to_vec();
+        expected.extend_from_slice(b"com.apple.streaming.transportStreamTimestamp\0");
+        expected.extend_from_slice(&[0, 0, 0, 0x01, 0x23, 0x45, 0x67, 0x89]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn timestamp_wraps_at_33_bits() {
+        let mut wrapped = Vec::new();
+        write_timestamp((1 << 33) + 5, &mut wrapped);
+        let mut plain = Vec::new();
+        write_timestamp(5, &mut plain);
+        assert_eq!(wrapped, plain);
+    }
+
+    #[test]
+    fn syncsafe_sizes() {
+        assert_eq!(syncsafe(0x7F), [0, 0, 0, 0x7F]);
+        assert_eq!(syncsafe(300), [0, 0, 0x02, 0x2C]);
+        assert_eq!(syncsafe((1 << 28) - 1), [0x7F, 0x7F, 0x7F, 0x7F]);
+    }
+}
@@ -0,0 +1,208 @@
+//! A minimal HTTP/1.1 server for what players on the LAN fetch: pages, playlists, segments
+//! and signalling. One request per connection, each connection on its own thread,
+//! up to `MAX_CONNECTIONS` at once.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How often the listener checks for the stop.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Requests are small, SDP offers are the largest at a few kilobytes.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+/// Connections beyond this are closed right away. Plenty for the players of a LAN,
+/// while a misbehaving client can't make threads without a bound.
+const MAX_CONNECTIONS: usize = 64;
+
+pub(crate) struct Request {
+    pub method: String,
+    /// Without the query.
+    pub path: String,
+    #[cfg_attr(not(feature = "opus"), allow(dead_code))]
+    pub body: String,
+}
+
+pub(crate) struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok<B: Into<Vec<u8>>>(content_type: &'static str, body: B) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    pub fn bad_request(msg: &str) -> Self {
+        Self {
+            status: "400 Bad Request",
+            content_type: "text/plain",
+            body: msg.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self {
+            status: "404 Not Found",
+            content_type: "text/plain",
+            body: b"Not found".to_vec(),
+        }
+    }
+}
+
+/// Serves until `stop` is set, `name` of the service is for logs.
+pub(crate) fn serve<F>(
+    name: &'static str,
+    listener: TcpListener,
+    stop: &AtomicBool,
+    handler: Arc<F>,
+) where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let active = Arc::new(AtomicUsize::new(0));
+    while !stop.load(Ordering::SeqCst) {
+        let (stream, peer) = match listener.accept() {
+            Ok(v) => v,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Error accepting {} connection: {}", name, e);
+                thread::sleep(ACCEPT_INTERVAL);
+                continue;
+            }
+        };
+
+        if active.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+            eprintln!("Too many {} connections, {} is refused", name, peer);
+            continue;
+        }
+
+        let handler = handler.clone();
+        let active_guard = ActiveGuard::new(active.clone());
+        let res = thread::Builder::new()
+            .name(format!("{} {}", name, peer))
+            .spawn(move || {
+                let _active_guard = active_guard;
+                if let Err(e) = handle(stream, &*handler) {
+                    eprintln!("Error serving {} to {}: {}", name, peer, e);
+                }
+            });
+        if let Err(e) = res {
+            eprintln!("Error spawning {} connection thread: {}", name, e);
+        }
+    }
+}
+
+/// Counts a connection as active until dropped, even if its thread panics.
+struct ActiveGuard(Arc<AtomicUsize>);
+
+impl ActiveGuard {
+    fn new(active: Arc<AtomicUsize>) -> Self {
+        active.fetch_add(1, Ordering::SeqCst);
+        Self(active)
+    }
+}
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn handle<F>(mut stream: TcpStream, handler: &F) -> io::Result<()>
+where
+    F: Fn(&Request) -> Response,
+{
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let response = match read_request(&mut stream)? {
+        Some(request) => handler(&request),
+        None => Response::bad_request("Bad request"),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-store\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Connection: close\r\n\
+         \r\n",
+        response.status,
+        response.content_type,
+        response.body.len(),
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// `None` if the request is malformed or too long.
+fn read_request(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let header_end = loop {
+        if let Some(pos) = find(&data, b"\r\n\r\n") {
+            break pos;
+        }
+        if data.len() > MAX_REQUEST_LEN {
+            return Ok(None);
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+
+    let head = match std::str::from_utf8(&data[..header_end]) {
+        Ok(head) => head.to_owned(),
+        Err(_) => return Ok(None),
+    };
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let (method, target) = match (request_line.next(), request_line.next()) {
+        (Some(method), Some(target)) => (method.to_owned(), target),
+        _ => return Ok(None),
+    };
+    let path = target.split('?').next().unwrap_or("").to_owned();
+
+    let mut content_len = 0;
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        if name.eq_ignore_ascii_case("content-length") {
+            content_len = match parts.next().unwrap_or("").trim().parse() {
+                Ok(len) if len <= MAX_REQUEST_LEN => len,
+                _ => return Ok(None),
+            };
+        }
+    }
+
+    let body_start = header_end + 4;
+    while data.len() < body_start + content_len {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    let body = String::from_utf8_lossy(&data[body_start..body_start + content_len]).into_owned();
+
+    Ok(Some(Request { method, path, body }))
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
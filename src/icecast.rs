@@ -0,0 +1,394 @@
+//! Publishes the encoded stream to an Icecast (or Shoutcast v2) server as a source client.
+
+use crate::adts;
+use crate::alsa;
+use crate::channel;
+use crate::error::{Error, IoError};
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_RESPONSE_LEN: usize = 4096;
+const USER_AGENT: &str = "stream-audio";
+
+/// How the stream is started.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    /// HTTP `PUT`, Icecast 2.4 and newer.
+    Put,
+    /// The legacy `SOURCE` method, older Icecast and Shoutcast v2.
+    Source,
+}
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub host: String,
+    pub port: u16,
+    /// Starts with `/`.
+    pub mount: String,
+    pub user: String,
+    pub password: String,
+    pub method: Method,
+    /// Advertised as `ice-name`, `ice-description` and `ice-genre`.
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub genre: Option<String>,
+    /// Whether the server may list the stream in public directories.
+    pub public: bool,
+    /// Advertised bit rate in bits per second.
+    pub bit_rate: Option<u32>,
+    /// First reconnect delay, doubles up to `reconnect_max` while the server stays unavailable.
+    pub reconnect_min: Duration,
+    pub reconnect_max: Duration,
+    /// Frames waiting to be sent, newer frames are dropped when it's full.
+    pub queue_len: usize,
+}
+
+/// Streams AAC frames to the server from a worker thread, so that a slow or
+/// unreachable server never blocks the capture.
+pub struct IcecastSink {
+    sender: channel::Sender<Msg>,
+    params: alsa::Params,
+    frame: Vec<u8>,
+    dropped: u64,
+    thread: Option<JoinHandle<()>>,
+}
+
+enum Msg {
+    Data(Vec<u8>),
+    Metadata(String),
+    Stop,
+}
+
+struct Worker {
+    settings: Settings,
+    receiver: channel::Receiver<Msg>,
+    /// The current title, sent again after a reconnect.
+    title: Option<String>,
+}
+
+impl IcecastSink {
+    /// `params` are the encoder params, they're needed for ADTS framing.
+    pub fn new(settings: Settings, params: alsa::Params) -> Result<Self, Error> {
+        if adts::header(params.rate, params.channels, 0).is_none() {
+            return Err(IoError::new(
+                "starting the Icecast source",
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} can't be framed as ADTS", params),
+                ),
+            )
+            .into());
+        }
+
+        let (sender, receiver) = channel::bounded(settings.queue_len);
+        let worker = Worker {
+            settings,
+            receiver,
+            title: None,
+        };
+        let thread = thread::Builder::new()
+            .name("Icecast".to_owned())
+            .spawn(move || worker.run())
+            .map_err(|e| IoError::new("spawning Icecast thread", e))?;
+
+        Ok(Self {
+            sender,
+            params,
+            frame: Vec::new(),
+            dropped: 0,
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues a raw AAC frame as it comes out of the encoder.
+    pub fn send_frame(&mut self, aac: &[u8]) {
+        self.frame.clear();
+        if !adts::write_frame(self.params.rate, self.params.channels, aac, &mut self.frame) {
+            eprintln!("AAC frame of {} bytes is too long for ADTS", aac.len());
+            return;
+        }
+        if self.sender.try_send(Msg::Data(self.frame.clone())).is_err() {
+            self.dropped += 1;
+        }
+    }
+
+    /// Updates the stream title shown by the players.
+    pub fn set_metadata(&self, title: &str) {
+        if self.sender.send(Msg::Metadata(title.to_owned())).is_err() {
+            eprintln!("Icecast thread is gone, metadata is not sent");
+        }
+    }
+
+    /// Frames dropped because the server couldn't keep up or was unavailable.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+impl Drop for IcecastSink {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Msg::Stop);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                eprintln!("Icecast thread panicked");
+            }
+        }
+    }
+}
+
+impl Worker {
+    fn run(mut self) {
+        let mut delay = self.settings.reconnect_min;
+        loop {
+            match self.connect() {
+                Ok(stream) => {
+                    println!(
+                        "Streaming to Icecast {}:{}{}",
+                        self.settings.host, self.settings.port, self.settings.mount
+                    );
+                    delay = self.settings.reconnect_min;
+                    if let Some(title) = self.title.clone() {
+                        self.log_metadata_err(self.send_metadata(&title));
+                    }
+                    match self.stream(stream) {
+                        Ok(()) => return,
+                        Err(e) => eprintln!("Icecast connection lost: {}", e),
+                    }
+                }
+                Err(e) => eprintln!("Error connecting to Icecast: {}", e),
+            }
+
+            if !self.wait(delay) {
+                return;
+            }
+            delay = cmp::min(delay * 2, self.settings.reconnect_max);
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, Error> {
+        let s = &self.settings;
+        let mut stream = self.open_connection()?;
+
+        let method = match s.method {
+            Method::Put => "PUT",
+            Method::Source => "SOURCE",
+        };
+        let version = match s.method {
+            Method::Put => "HTTP/1.1",
+            Method::Source => "HTTP/1.0",
+        };
+        let mut request = format!(
+            "{} {} {}\r\n\
+             Host: {}:{}\r\n\
+             Authorization: Basic {}\r\n\
+             User-Agent: {}\r\n\
+             Content-Type: audio/aac\r\n\
+             Ice-Public: {}\r\n",
+            method,
+            s.mount,
+            version,
+            s.host,
+            s.port,
+            base64::encode(&format!("{}:{}", s.user, s.password)),
+            USER_AGENT,
+            if s.public { 1 } else { 0 },
+        );
+        for (header, value) in &[
+            ("Ice-Name", &s.name),
+            ("Ice-Description", &s.description),
+            ("Ice-Genre", &s.genre),
+        ] {
+            if let Some(value) = value {
+                request += &format!("{}: {}\r\n", header, value);
+            }
+        }
+        if let Some(bit_rate) = s.bit_rate {
+            request += &format!("Ice-Bitrate: {}\r\n", bit_rate / 1000);
+        }
+        if s.method == Method::Put {
+            request += "Expect: 100-continue\r\n";
+        }
+        request += "\r\n";
+
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| IoError::new("sending Icecast handshake", e))?;
+
+        let status = read_status(&mut stream)?;
+        // PUT is answered with 100 Continue, SOURCE with 200 OK.
+        if status != 100 && status != 200 {
+            return Err(status_error("Icecast handshake", status));
+        }
+        Ok(stream)
+    }
+
+    /// Sends queued frames until stopped. An error means the connection is lost.
+    fn stream(&mut self, mut stream: TcpStream) -> Result<(), Error> {
+        loop {
+            match self.receiver.recv() {
+                Ok(Msg::Data(frame)) => stream
+                    .write_all(&frame)
+                    .map_err(|e| IoError::new("sending to Icecast", e))?,
+                Ok(Msg::Metadata(title)) => {
+                    self.log_metadata_err(self.send_metadata(&title));
+                    self.title = Some(title);
+                }
+                Ok(Msg::Stop) | Err(_) => return Ok(()),
+            }
+        }
+    }
+
+    /// Waits before reconnecting, dropping stale frames. Returns `false` if stopped.
+    fn wait(&mut self, delay: Duration) -> bool {
+        let deadline = Instant::now() + delay;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.receiver.recv_timeout(timeout) {
+                Ok(Msg::Data(_)) => (),
+                Ok(Msg::Metadata(title)) => self.title = Some(title),
+                Ok(Msg::Stop) | Err(channel::RecvTimeoutError::Disconnected) => return false,
+                Err(channel::RecvTimeoutError::Timeout) => return true,
+            }
+        }
+    }
+
+    /// Metadata goes through the admin interface, on a separate connection.
+    fn send_metadata(&self, title: &str) -> Result<(), Error> {
+        let s = &self.settings;
+        let mut stream = self.open_connection()?;
+
+        let request = format!(
+            "GET /admin/metadata?mount={}&mode=updinfo&song={} HTTP/1.0\r\n\
+             Host: {}:{}\r\n\
+             Authorization: Basic {}\r\n\
+             User-Agent: {}\r\n\
+             \r\n",
+            url_encode(&s.mount),
+            url_encode(title),
+            s.host,
+            s.port,
+            base64::encode(&format!("{}:{}", s.user, s.password)),
+            USER_AGENT,
+        );
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| IoError::new("sending Icecast metadata", e))?;
+
+        let status = read_status(&mut stream)?;
+        if status != 200 {
+            return Err(status_error("Icecast metadata update", status));
+        }
+        Ok(())
+    }
+
+    fn log_metadata_err(&self, res: Result<(), Error>) {
+        if let Err(e) = res {
+            eprintln!("Error updating Icecast metadata: {}", e);
+        }
+    }
+
+    fn open_connection(&self) -> Result<TcpStream, Error> {
+        let s = &self.settings;
+        let addrs = (s.host.as_str(), s.port)
+            .to_socket_addrs()
+            .map_err(|e| IoError::new(format!("resolving {}", s.host), e))?;
+
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream
+                        .set_write_timeout(Some(WRITE_TIMEOUT))
+                        .map_err(|e| IoError::new("setting Icecast write timeout", e))?;
+                    stream
+                        .set_read_timeout(Some(WRITE_TIMEOUT))
+                        .map_err(|e| IoError::new("setting Icecast read timeout", e))?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = e,
+            }
+        }
+
+        Err(IoError::new(format!("connecting to {}:{}", s.host, s.port), last_err).into())
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_owned(),
+            port: 8000,
+            mount: "/stream".to_owned(),
+            user: "source".to_owned(),
+            password: "hackme".to_owned(),
+            method: Method::Put,
+            name: None,
+            description: None,
+            genre: None,
+            public: false,
+            bit_rate: None,
+            reconnect_min: Duration::from_secs(1),
+            reconnect_max: Duration::from_secs(60),
+            queue_len: 256,
+        }
+    }
+}
+
+/// Reads the response headers and returns the status code.
+fn read_status(stream: &mut TcpStream) -> Result<u32, Error> {
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") && response.len() < MAX_RESPONSE_LEN {
+        match stream.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => response.push(byte[0]),
+            Err(e) => return Err(IoError::new("reading Icecast response", e).into()),
+        }
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or("");
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| {
+            IoError::new(
+                "reading Icecast response",
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected answer '{}'", status_line),
+                ),
+            )
+            .into()
+        })
+}
+
+fn status_error(context: &'static str, status: u32) -> Error {
+    let kind = match status {
+        401 | 403 => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+    };
+    IoError::new(
+        context,
+        io::Error::new(kind, format!("server answered {}", status)),
+    )
+    .into()
+}
+
+fn url_encode(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                res.push(b as char)
+            }
+            _ => res += &format!("%{:02X}", b),
+        }
+    }
+    res
+}
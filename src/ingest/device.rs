@@ -0,0 +1,219 @@
+use super::{read_planar, Source, READ_TIMEOUT};
+use crate::alsa;
+use crate::channel;
+use crate::error::Error;
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The first reopen is tried this long after the disconnect, each failure doubles it.
+const FIRST_REOPEN_DELAY: Duration = Duration::from_millis(500);
+const MAX_REOPEN_DELAY: Duration = Duration::from_secs(10);
+/// Events nobody takes are dropped past this many.
+const EVENT_QUEUE_LEN: usize = 16;
+
+/// What happened to the device, see `DeviceSource::events`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceEvent {
+    /// Silence is returned from now on.
+    Disconnected,
+    /// The device is captured again.
+    Reconnected,
+    /// The device came back with other params, it's closed and the reopening goes on.
+    ParamsChanged(alsa::Params),
+}
+
+/// An alsa capture device that survives being unplugged. While it's away, silence is
+/// returned in real time, so that the stream and its clients go on, and the device of
+/// the same name is reopened with a growing delay.
+///
+/// With `Access::NonInterleaved` the channels are read apart and interleaved into the
+/// caller's buffer, as sources return interleaved frames.
+pub struct DeviceSource {
+    name: String,
+    /// The ones the device was first opened with, it has to come back with the same.
+    params: alsa::Params,
+    config: alsa::Config,
+    pcm: Option<alsa::SndPcm>,
+    next_reopen: Instant,
+    reopen_delay: Duration,
+    /// A buffer per channel for `Access::NonInterleaved`.
+    planes: Vec<Vec<u8>>,
+    events: Option<channel::Sender<DeviceEvent>>,
+}
+
+impl DeviceSource {
+    /// Fails if the device can't be opened now, the reopening is only for losing it later.
+    pub fn open(name: String, params: alsa::Params, config: alsa::Config) -> Result<Self, Error> {
+        let pcm =
+            alsa::SndPcm::open_with_config(name.clone(), alsa::Stream::Capture, params, config)?;
+        Ok(Self {
+            name,
+            params: pcm.get_params(),
+            config,
+            pcm: Some(pcm),
+            next_reopen: Instant::now(),
+            reopen_delay: FIRST_REOPEN_DELAY,
+            planes: Vec::new(),
+            events: None,
+        })
+    }
+
+    /// The disconnects and reopens from now on. A new call replaces the previous receiver.
+    pub fn events(&mut self) -> channel::Receiver<DeviceEvent> {
+        let (sender, receiver) = channel::bounded(EVENT_QUEUE_LEN);
+        self.events = Some(sender);
+        receiver
+    }
+
+    /// `None` while the device is away.
+    pub fn pcm(&self) -> Option<&alsa::SndPcm> {
+        self.pcm.as_ref()
+    }
+
+    fn reopen(&mut self) {
+        if Instant::now() < self.next_reopen {
+            return;
+        }
+
+        let res = alsa::SndPcm::open_with_config(
+            self.name.clone(),
+            alsa::Stream::Capture,
+            self.params,
+            self.config,
+        );
+        match res {
+            Ok(pcm) if pcm.get_params() == self.params => {
+                println!("Capture device '{}' is back", self.name);
+                self.pcm = Some(pcm);
+                self.reopen_delay = FIRST_REOPEN_DELAY;
+                self.notify(DeviceEvent::Reconnected);
+            }
+            Ok(pcm) => {
+                // The stream can't change its params, so this is as good as still away.
+                eprintln!(
+                    "Capture device '{}' came back with {:?} instead of {:?}, retrying in {:?}",
+                    self.name,
+                    pcm.get_params(),
+                    self.params,
+                    self.reopen_delay
+                );
+                self.notify(DeviceEvent::ParamsChanged(pcm.get_params()));
+                self.retry_later();
+            }
+            Err(e) => {
+                eprintln!(
+                    "Error reopening capture device '{}', retrying in {:?}: {}",
+                    self.name, self.reopen_delay, e
+                );
+                self.retry_later();
+            }
+        }
+    }
+
+    fn retry_later(&mut self) {
+        self.next_reopen = Instant::now() + self.reopen_delay;
+        self.reopen_delay = cmp::min(self.reopen_delay * 2, MAX_REOPEN_DELAY);
+    }
+
+    fn disconnected(&mut self) {
+        eprintln!("Capture device '{}' is disconnected", self.name);
+        self.pcm = None;
+        self.next_reopen = Instant::now() + self.reopen_delay;
+        self.notify(DeviceEvent::Disconnected);
+    }
+
+    fn notify(&self, event: DeviceEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.try_send(event);
+        }
+    }
+
+    /// Like `read`, but with `Access::Mmap` the frames are passed to `consume` straight from
+    /// the ring buffer, `buf` only holds the silence then. `consume` isn't called when
+    /// nothing was read.
+    pub fn read_with<F>(&mut self, buf: &mut [u8], mut consume: F) -> Result<(), Error>
+    where
+        F: FnMut(&[u8]) -> Result<(), Error>,
+    {
+        if self.config.access != alsa::Access::Mmap {
+            let read = self.read(buf)?;
+            return if read > 0 {
+                consume(&buf[..read])
+            } else {
+                Ok(())
+            };
+        }
+
+        if self.pcm.is_none() {
+            self.reopen();
+        }
+        if let Some(pcm) = &mut self.pcm {
+            let max_frames = buf.len() / self.params.bytes_per_frame();
+            let res = pcm.mmap_begin(max_frames).and_then(|area| {
+                consume(area.data())?;
+                let frames = area.frames();
+                area.commit(frames)
+            });
+            match res {
+                Err(ref e) if e.is_device_disconnected() => self.disconnected(),
+                res => return res,
+            }
+        }
+        let len = self.silence(buf);
+        consume(&buf[..len])
+    }
+
+    /// Fills `buf` with the silence of up to `READ_TIMEOUT`, taking as long as it lasts.
+    fn silence(&self, buf: &mut [u8]) -> usize {
+        let frame_size = self.params.bytes_per_frame();
+        let max_frames = READ_TIMEOUT.as_millis() as usize * self.params.rate as usize / 1000;
+        let frames = cmp::min(buf.len() / frame_size, max_frames);
+
+        let len = frames * frame_size;
+        for b in &mut buf[..len] {
+            *b = self.params.format.silence_byte();
+        }
+        thread::sleep(Duration::from_micros(
+            frames as u64 * 1_000_000 / u64::from(self.params.rate),
+        ));
+        len
+    }
+}
+
+impl Source for DeviceSource {
+    fn params(&self) -> alsa::Params {
+        self.params
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.pcm.is_none() {
+            self.reopen();
+        }
+        let pcm = match &self.pcm {
+            Some(pcm) => pcm,
+            None => return Ok(self.silence(buf)),
+        };
+
+        let res = if self.config.access == alsa::Access::NonInterleaved {
+            read_planar(pcm, &mut self.planes, buf)
+        } else {
+            pcm.read_interleaved(buf)
+        };
+        match res {
+            Ok(read) => Ok(read),
+            Err(ref e) if e.is_device_disconnected() => {
+                self.disconnected();
+                Ok(self.silence(buf))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        match &self.pcm {
+            Some(pcm) => pcm.stop(),
+            None => Ok(()),
+        }
+    }
+}
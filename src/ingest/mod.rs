@@ -0,0 +1,137 @@
+//! Audio sources feeding the resample → encode → `NetServer` pipeline.
+
+mod device;
+mod pcm;
+mod rtp;
+
+pub use device::{DeviceEvent, DeviceSource};
+pub use pcm::{TcpPcmSource, UdpPcmSource};
+pub use rtp::{RtpPayload, RtpSource};
+
+use crate::alsa;
+use crate::error::Error;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Sources return from `read` at least this often, so that the caller could check for exit.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub trait Source: Send {
+    /// Format of the PCM returned by `read`.
+    fn params(&self) -> alsa::Params;
+
+    /// Reads whole frames of interleaved PCM. Returns 0 when nothing has arrived for a while.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+
+    fn stop(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Where to take the audio from, parsed from `scheme://address`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    Alsa,
+    /// Raw PCM, the sender connects to the address.
+    TcpPcm(SocketAddr),
+    /// Raw PCM in datagrams.
+    UdpPcm(SocketAddr),
+    Rtp(SocketAddr, RtpPayload),
+}
+
+impl Input {
+    /// Opens a network source. `params` describes the incoming audio, for RTP only the rate
+    /// and channels are used, as the payload type defines the format. `aac_config` is
+    /// the AudioSpecificConfig of AAC over RTP, see `RtpSource::bind`.
+    pub fn open_network(
+        &self,
+        params: alsa::Params,
+        aac_config: Option<&[u8]>,
+    ) -> Result<Option<Box<dyn Source>>, Error> {
+        let source: Box<dyn Source> = match *self {
+            Input::Alsa => return Ok(None),
+            Input::TcpPcm(addr) => Box::new(TcpPcmSource::listen(addr, params)?),
+            Input::UdpPcm(addr) => Box::new(UdpPcmSource::bind(addr, params)?),
+            Input::Rtp(addr, payload) => Box::new(RtpSource::bind(
+                addr,
+                payload,
+                params.rate,
+                params.channels,
+                aac_config,
+            )?),
+        };
+        Ok(Some(source))
+    }
+}
+
+impl FromStr for Input {
+    type Err = String;
+
+    /// `alsa`, `tcp://addr`, `udp://addr`, `rtp-l16://addr`, `rtp-l24://addr`, and with
+    /// the `fdk-aac` feature `rtp-aac://addr`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "alsa" {
+            return Ok(Input::Alsa);
+        }
+
+        let mut parts = s.splitn(2, "://");
+        let scheme = parts.next().unwrap_or("");
+        let addr = match parts.next() {
+            Some(addr) => addr
+                .parse::<SocketAddr>()
+                .map_err(|e| format!("{}: {}", addr, e))?,
+            None => return Err(format!("'{}' is not in the form of scheme://address", s)),
+        };
+
+        match scheme {
+            "tcp" => Ok(Input::TcpPcm(addr)),
+            "udp" => Ok(Input::UdpPcm(addr)),
+            "rtp-l16" => Ok(Input::Rtp(addr, RtpPayload::L16)),
+            "rtp-l24" => Ok(Input::Rtp(addr, RtpPayload::L24)),
+            #[cfg(feature = "fdk-aac")]
+            "rtp-aac" => Ok(Input::Rtp(addr, RtpPayload::Aac)),
+            _ => Err(format!("Unknown input scheme '{}'", scheme)),
+        }
+    }
+}
+
+impl Source for alsa::SndPcm {
+    fn params(&self) -> alsa::Params {
+        self.get_params()
+    }
+
+    /// The planes of a non-interleaved PCM are allocated on each read,
+    /// `DeviceSource` keeps them.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.get_access() == alsa::Access::NonInterleaved {
+            read_planar(self, &mut Vec::new(), buf)
+        } else {
+            self.read_interleaved(buf)
+        }
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        alsa::SndPcm::stop(self)
+    }
+}
+
+/// Reads as many frames as `buf` holds into `planes` and interleaves them into `buf`.
+fn read_planar(
+    pcm: &alsa::SndPcm,
+    planes: &mut Vec<Vec<u8>>,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let params = pcm.get_params();
+    let plane_len = buf.len() / params.bytes_per_frame() * params.format.bytes_per_sample();
+    planes.resize(params.channels as usize, Vec::new());
+    for plane in planes.iter_mut() {
+        plane.resize(plane_len, 0);
+    }
+
+    let mut channels: Vec<&mut [u8]> = planes.iter_mut().map(|p| &mut p[..]).collect();
+    let frames = pcm.read_noninterleaved(&mut channels)?;
+    let sample_len = frames * params.format.bytes_per_sample();
+    let channels: Vec<&[u8]> = planes.iter().map(|p| &p[..sample_len]).collect();
+    Ok(params.format.interleave(&channels, buf))
+}
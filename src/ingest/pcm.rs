@@ -0,0 +1,173 @@
+use super::{Source, READ_TIMEOUT};
+use crate::alsa;
+use crate::error::{Error, IoError};
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::thread;
+
+/// Raw interleaved PCM sent over a TCP connection. One sender at a time,
+/// after it disconnects the next one is accepted.
+pub struct TcpPcmSource {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    params: alsa::Params,
+    partial: PartialFrame,
+}
+
+/// Raw interleaved PCM, one chunk of whole frames per datagram.
+pub struct UdpPcmSource {
+    socket: UdpSocket,
+    params: alsa::Params,
+    buf: Vec<u8>,
+}
+
+/// Keeps the tail of a read that didn't make a whole frame until the rest arrives.
+struct PartialFrame {
+    bytes: Vec<u8>,
+    frame_size: usize,
+}
+
+impl TcpPcmSource {
+    pub fn listen(addr: SocketAddr, params: alsa::Params) -> Result<Self, Error> {
+        let listener =
+            TcpListener::bind(addr).map_err(|e| IoError::new(format!("binding to {}", addr), e))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| IoError::new("making the listener non-blocking", e))?;
+
+        Ok(Self {
+            listener,
+            stream: None,
+            params,
+            partial: PartialFrame::new(params.bytes_per_frame()),
+        })
+    }
+
+    fn accept(&mut self) -> Result<(), Error> {
+        let (stream, addr) = match self.listener.accept() {
+            Ok(v) => v,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(READ_TIMEOUT);
+                return Ok(());
+            }
+            Err(e) => return Err(IoError::new("accepting a PCM sender", e).into()),
+        };
+
+        stream
+            .set_nonblocking(false)
+            .map_err(|e| IoError::new("making the PCM stream blocking", e))?;
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(|e| IoError::new("setting PCM stream timeout", e))?;
+        println!("PCM sender connected: {}", addr);
+
+        self.stream = Some(stream);
+        self.partial.clear();
+        Ok(())
+    }
+}
+
+impl Source for TcpPcmSource {
+    fn params(&self) -> alsa::Params {
+        self.params
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                self.accept()?;
+                return Ok(0);
+            }
+        };
+
+        let start = self.partial.take_into(buf);
+        match stream.read(&mut buf[start..]) {
+            Ok(0) => {
+                println!("PCM sender disconnected");
+                self.stream = None;
+                Ok(0)
+            }
+            Ok(n) => Ok(self.partial.keep_tail(buf, start + n)),
+            Err(ref e) if is_timeout(e) => Ok(self.partial.keep_tail(buf, start)),
+            Err(e) => {
+                eprintln!("Error reading PCM stream: {}", e);
+                self.stream = None;
+                Ok(0)
+            }
+        }
+    }
+}
+
+impl UdpPcmSource {
+    pub fn bind(addr: SocketAddr, params: alsa::Params) -> Result<Self, Error> {
+        let socket =
+            UdpSocket::bind(addr).map_err(|e| IoError::new(format!("binding to {}", addr), e))?;
+        socket
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(|e| IoError::new("setting socket timeout", e))?;
+
+        Ok(Self {
+            socket,
+            params,
+            buf: vec![0; 64 * 1024],
+        })
+    }
+}
+
+impl Source for UdpPcmSource {
+    fn params(&self) -> alsa::Params {
+        self.params
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let n = match self.socket.recv(&mut self.buf) {
+            Ok(n) => n,
+            Err(ref e) if is_timeout(e) => return Ok(0),
+            Err(e) => return Err(IoError::new("receiving PCM", e).into()),
+        };
+
+        let frame_size = self.params.bytes_per_frame();
+        let len = n.min(buf.len()) / frame_size * frame_size;
+        if len < n {
+            eprintln!("Dropped {} bytes of a PCM datagram", n - len);
+        }
+        buf[..len].copy_from_slice(&self.buf[..len]);
+        Ok(len)
+    }
+}
+
+impl PartialFrame {
+    fn new(frame_size: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(frame_size),
+            frame_size,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.bytes.clear();
+    }
+
+    /// Moves the kept bytes to the start of `buf`, returns their count.
+    fn take_into(&mut self, buf: &mut [u8]) -> usize {
+        let len = self.bytes.len();
+        buf[..len].copy_from_slice(&self.bytes);
+        self.bytes.clear();
+        len
+    }
+
+    /// Keeps the incomplete frame at the end of `buf[..len]`, returns the length of whole frames.
+    fn keep_tail(&mut self, buf: &[u8], len: usize) -> usize {
+        let whole = len / self.frame_size * self.frame_size;
+        self.bytes.extend_from_slice(&buf[whole..len]);
+        whole
+    }
+}
+
+pub(super) fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
@@ -0,0 +1,263 @@
+use super::pcm::is_timeout;
+use super::{Source, READ_TIMEOUT};
+#[cfg(feature = "fdk-aac")]
+use crate::adts;
+use crate::alsa;
+#[cfg(feature = "fdk-aac")]
+use crate::codec::AacDecoder;
+use crate::error::{Error, IoError};
+use std::convert::TryInto;
+#[cfg(feature = "fdk-aac")]
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+const RTP_VERSION: u8 = 2;
+const RTP_HEADER_LEN: usize = 12;
+/// Gaps longer than this many packets are not filled with silence, the sender probably restarted.
+const MAX_CONCEALED_PKTS: u16 = 8;
+
+/// RTP payload formats the source understands. The payload type number is not checked,
+/// as it's dynamic for all of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RtpPayload {
+    /// RFC 3551 L16, big-endian 16 bit.
+    L16,
+    /// RFC 3190 L24, big-endian 24 bit.
+    L24,
+    /// RFC 3640 mpeg4-generic in the AAC-hbr mode.
+    #[cfg(feature = "fdk-aac")]
+    Aac,
+}
+
+/// Receives an RTP stream on a UDP socket, unicast or multicast.
+pub struct RtpSource {
+    socket: UdpSocket,
+    payload: RtpPayload,
+    params: alsa::Params,
+    #[cfg(feature = "fdk-aac")]
+    decoder: Option<AacDecoder>,
+    next_seq: Option<u16>,
+    /// PCM of the last packet, gaps are concealed with as much silence.
+    last_pcm_len: usize,
+    pcm: Vec<u8>,
+    pcm_read: usize,
+    buf: Vec<u8>,
+}
+
+impl RtpPayload {
+    /// Format of the PCM produced from the payload.
+    fn pcm_format(self) -> alsa::Format {
+        match self {
+            RtpPayload::L16 => alsa::Format::S16Le,
+            #[cfg(feature = "fdk-aac")]
+            RtpPayload::Aac => alsa::Format::S16Le,
+            RtpPayload::L24 => alsa::Format::FloatLe,
+        }
+    }
+}
+
+impl RtpSource {
+    /// `aac_config` is the AudioSpecificConfig of an AAC stream, from the `config=` of its SDP,
+    /// it must describe `rate` and `channels`. Without it AAC-LC of those is assumed.
+    #[cfg_attr(not(feature = "fdk-aac"), allow(unused_variables))]
+    pub fn bind(
+        addr: SocketAddr,
+        payload: RtpPayload,
+        rate: u32,
+        channels: u32,
+        aac_config: Option<&[u8]>,
+    ) -> Result<Self, Error> {
+        let socket =
+            UdpSocket::bind(addr).map_err(|e| IoError::new(format!("binding to {}", addr), e))?;
+        socket
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(|e| IoError::new("setting socket timeout", e))?;
+
+        let params = alsa::Params {
+            format: payload.pcm_format(),
+            channels,
+            rate,
+        };
+
+        #[cfg(feature = "fdk-aac")]
+        let decoder = match payload {
+            RtpPayload::Aac => {
+                let default_config = adts::audio_specific_config(rate, channels);
+                let config = aac_config
+                    .or_else(|| default_config.as_ref().map(|c| &c[..]))
+                    .ok_or_else(|| {
+                        IoError::new(
+                            "creating AAC decoder",
+                            io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!("AAC has no config for {} Hz {} channels", rate, channels),
+                            ),
+                        )
+                    })?;
+                Some(AacDecoder::new(config)?)
+            }
+            RtpPayload::L16 | RtpPayload::L24 => None,
+        };
+
+        Ok(Self {
+            socket,
+            payload,
+            params,
+            #[cfg(feature = "fdk-aac")]
+            decoder,
+            next_seq: None,
+            last_pcm_len: 0,
+            pcm: Vec::new(),
+            pcm_read: 0,
+            buf: vec![0; 64 * 1024],
+        })
+    }
+
+    /// Receives one packet and appends its PCM. Returns `false` on timeout.
+    fn recv_pkt(&mut self) -> Result<bool, Error> {
+        let n = match self.socket.recv(&mut self.buf) {
+            Ok(n) => n,
+            Err(ref e) if is_timeout(e) => return Ok(false),
+            Err(e) => return Err(IoError::new("receiving RTP", e).into()),
+        };
+
+        let (seq, payload) = match parse_rtp(&self.buf[..n]) {
+            Some(v) => v,
+            None => {
+                eprintln!("Malformed RTP packet of {} bytes", n);
+                return Ok(true);
+            }
+        };
+
+        if let Some(expected) = self.next_seq {
+            let diff = seq.wrapping_sub(expected) as i16;
+            if diff < 0 {
+                // Late or duplicate, its place is already taken.
+                return Ok(true);
+            }
+            if diff > 0 {
+                eprintln!("Lost {} RTP packets before #{}", diff, seq);
+                if diff as u16 <= MAX_CONCEALED_PKTS {
+                    let silence = self.params.format.silence_byte();
+                    let len = self.pcm.len() + diff as usize * self.last_pcm_len;
+                    self.pcm.resize(len, silence);
+                }
+            }
+        }
+        self.next_seq = Some(seq.wrapping_add(1));
+
+        let start = self.pcm.len();
+        match self.payload {
+            RtpPayload::L16 => {
+                for sample in payload.chunks_exact(2) {
+                    self.pcm.extend_from_slice(&[sample[1], sample[0]]);
+                }
+            }
+            RtpPayload::L24 => {
+                for sample in payload.chunks_exact(3) {
+                    let v = i32::from_be_bytes([sample[0], sample[1], sample[2], 0]) >> 8;
+                    let v = v as f32 / (1 << 23) as f32;
+                    self.pcm.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+            #[cfg(feature = "fdk-aac")]
+            RtpPayload::Aac => {
+                let decoder = self.decoder.as_mut().unwrap();
+                let aus = match aac_access_units(payload) {
+                    Some(aus) => aus,
+                    None => {
+                        eprintln!("Malformed AAC RTP payload #{}", seq);
+                        return Ok(true);
+                    }
+                };
+                for au in aus {
+                    for sample in decoder.decode(au)? {
+                        self.pcm.extend_from_slice(&sample.to_le_bytes());
+                    }
+                }
+            }
+        }
+
+        if self.pcm.len() > start {
+            self.last_pcm_len = self.pcm.len() - start;
+        }
+        Ok(true)
+    }
+}
+
+impl Source for RtpSource {
+    fn params(&self) -> alsa::Params {
+        self.params
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.pcm_read == self.pcm.len() {
+            self.pcm.clear();
+            self.pcm_read = 0;
+            if !self.recv_pkt()? {
+                return Ok(0);
+            }
+        }
+
+        let frame_size = self.params.bytes_per_frame();
+        let available = self.pcm.len() - self.pcm_read;
+        let len = available.min(buf.len()) / frame_size * frame_size;
+        buf[..len].copy_from_slice(&self.pcm[self.pcm_read..self.pcm_read + len]);
+        self.pcm_read += len;
+        if len == 0 {
+            // Not even a frame fits, or the packet had no audio.
+            self.pcm_read = self.pcm.len();
+        }
+        Ok(len)
+    }
+}
+
+/// Returns the sequence number and the payload.
+fn parse_rtp(pkt: &[u8]) -> Option<(u16, &[u8])> {
+    if pkt.len() < RTP_HEADER_LEN || pkt[0] >> 6 != RTP_VERSION {
+        return None;
+    }
+    let has_padding = pkt[0] & 0x20 != 0;
+    let has_extension = pkt[0] & 0x10 != 0;
+    let csrc_count = (pkt[0] & 0x0f) as usize;
+    let seq = u16::from_be_bytes(pkt[2..4].try_into().unwrap());
+
+    let mut start = RTP_HEADER_LEN + csrc_count * 4;
+    if has_extension {
+        let words = u16::from_be_bytes(pkt.get(start + 2..start + 4)?.try_into().unwrap());
+        start += 4 + words as usize * 4;
+    }
+    let mut end = pkt.len();
+    if has_padding {
+        end = end.checked_sub(*pkt.last()? as usize)?;
+    }
+    if start > end {
+        return None;
+    }
+
+    Some((seq, &pkt[start..end]))
+}
+
+/// Splits an AAC-hbr payload into access units: 16 bit AU headers of 13 bit size
+/// and 3 bit index, preceded by their total length in bits.
+#[cfg(feature = "fdk-aac")]
+fn aac_access_units(payload: &[u8]) -> Option<Vec<&[u8]>> {
+    let headers_bits = u16::from_be_bytes(payload.get(0..2)?.try_into().unwrap()) as usize;
+    // AU headers are 16 bits each in the AAC-hbr mode, so the length is whole bytes.
+    let headers_len = headers_bits / 8;
+    let headers = payload.get(2..2 + headers_len)?;
+    let mut data = &payload[2 + headers_len..];
+
+    let mut aus = Vec::new();
+    for header in headers.chunks_exact(2) {
+        let size = (u16::from_be_bytes([header[0], header[1]]) >> 3) as usize;
+        if size > data.len() {
+            return None;
+        }
+        let (au, rest) = data.split_at(size);
+        aus.push(au);
+        data = rest;
+    }
+
+    Some(aus)
+}
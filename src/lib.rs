@@ -1,10 +1,22 @@
 extern crate crossbeam_channel as channel;
 
+pub mod adts;
+pub mod aes67;
 pub mod alsa;
 pub mod audio_saver;
+#[cfg(any(feature = "fdk-aac", feature = "opus"))]
+pub mod codec;
 pub mod error;
 pub mod exit_listener;
+pub mod hls;
+mod http;
+pub mod icecast;
+pub mod ingest;
+#[cfg(feature = "fdk-aac")]
+pub mod net_client;
 pub mod net_server;
 pub mod thread_buffer;
+#[cfg(feature = "opus")]
+pub mod webrtc;
 
 use stream_audio_ffmpeg as ffmpeg;
@@ -0,0 +1,71 @@
+use super::Event;
+use crate::adts;
+use crate::alsa;
+use crate::codec::AacDecoder;
+use crate::error::{Error, IoError};
+use std::io;
+
+/// Turns the stream events into PCM, filling silence and lost packets with digital silence.
+pub struct StreamDecoder {
+    decoder: AacDecoder,
+    params: alsa::Params,
+    /// Size of the last decoded packet, a lost packet is replaced with as much silence.
+    last_decoded_len: usize,
+}
+
+impl StreamDecoder {
+    /// Decodes the AAC-LC of the rate and channels of `params` to PCM of `params`.
+    pub fn new(params: alsa::Params) -> Result<Self, Error> {
+        let config =
+            adts::audio_specific_config(params.rate, params.channels).ok_or_else(|| {
+                IoError::new(
+                    "creating AAC decoder",
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("AAC has no config for {:?}", params),
+                    ),
+                )
+            })?;
+        let decoder = AacDecoder::new(&config)?;
+
+        Ok(Self {
+            decoder,
+            params,
+            last_decoded_len: 0,
+        })
+    }
+
+    /// Appends the PCM of the event to `out`. Events without audio are ignored.
+    pub fn decode(&mut self, event: &Event, out: &mut Vec<u8>) -> Result<(), Error> {
+        match event {
+            Event::Audio { payload, .. } => {
+                let start = out.len();
+                let samples = self.decoder.decode(payload)?;
+                self.params.format.extend_from_s16(samples, out);
+                if out.len() > start {
+                    self.last_decoded_len = out.len() - start;
+                }
+            }
+            Event::Silence { frames, .. } => {
+                let len = *frames as usize * self.params.bytes_per_frame();
+                self.push_silence(len, out);
+            }
+            Event::Lost { .. } => {
+                let len = self.last_decoded_len;
+                self.push_silence(len, out);
+            }
+            Event::Bye { .. } | Event::Timeout => (),
+        }
+
+        Ok(())
+    }
+
+    pub fn get_params(&self) -> alsa::Params {
+        self.params
+    }
+
+    fn push_silence(&self, len: usize, out: &mut Vec<u8>) {
+        let silence = self.params.format.silence_byte();
+        out.resize(out.len() + len, silence);
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+/// Decouples bursty network arrival from the steady pace of a playback device.
+///
+/// Playback starts once `target` bytes are buffered. On an underrun the buffer fills up to
+/// `target` again, playing silence meanwhile. Above `max` bytes the oldest audio is dropped.
+pub struct JitterBuffer {
+    que: Mutex<JitterQueue>,
+    cvar: Condvar,
+}
+
+struct JitterQueue {
+    data: VecDeque<u8>,
+    target: usize,
+    max: usize,
+    is_buffering: bool,
+    is_closed: bool,
+    underruns: u64,
+}
+
+impl JitterBuffer {
+    /// `target` and `max` should be multiples of the frame size.
+    pub fn new(target: usize, max: usize) -> Self {
+        Self {
+            que: Mutex::new(JitterQueue {
+                data: VecDeque::with_capacity(max),
+                target,
+                max,
+                is_buffering: true,
+                is_closed: false,
+                underruns: 0,
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, pcm: &[u8]) {
+        let mut que = self.que.lock().unwrap();
+        que.data.extend(pcm);
+
+        if que.data.len() > que.max {
+            let excess = que.data.len() - que.max;
+            que.data.drain(..excess);
+        }
+        if que.is_buffering && que.data.len() >= que.target {
+            que.is_buffering = false;
+        }
+
+        self.cvar.notify_one();
+    }
+
+    /// Fills `out` with audio, or with `silence` bytes while buffering.
+    /// Waits until the playback starts for the first time.
+    /// Returns `false` once the buffer is closed.
+    pub fn pop(&self, out: &mut [u8], silence: u8) -> bool {
+        let mut que = self.que.lock().unwrap();
+        while que.is_buffering && que.underruns == 0 && !que.is_closed {
+            que = self.cvar.wait(que).unwrap();
+        }
+        if que.is_closed {
+            return false;
+        }
+
+        if !que.is_buffering && que.data.len() < out.len() {
+            que.is_buffering = true;
+            que.underruns += 1;
+        }
+        if que.is_buffering {
+            for b in out.iter_mut() {
+                *b = silence;
+            }
+            return true;
+        }
+
+        let len = out.len();
+        for (dst, src) in out.iter_mut().zip(que.data.drain(..len)) {
+            *dst = src;
+        }
+        true
+    }
+
+    /// Wakes up and stops the consumer.
+    pub fn close(&self) {
+        self.que.lock().unwrap().is_closed = true;
+        self.cvar.notify_all();
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.que.lock().unwrap().underruns
+    }
+}
@@ -0,0 +1,250 @@
+mod decoder;
+mod jitter_buffer;
+mod reorder;
+
+pub use decoder::StreamDecoder;
+pub use jitter_buffer::JitterBuffer;
+pub use reorder::{ReorderBuffer, Slot};
+
+use crate::error::{Error, IoError};
+use crate::net_server::pkt;
+use crate::net_server::ByeReason;
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub server: SocketAddr,
+    /// How many packets may overtake a missing one before it's considered lost.
+    pub reorder_depth: usize,
+    /// `info` is resent this many times, if the server doesn't answer.
+    pub handshake_attempts: u32,
+    /// How long to wait for a packet before returning `Event::Timeout`.
+    pub recv_timeout: Duration,
+}
+
+/// What the server sent, in the sequence order.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Audio {
+        seq: u32,
+        payload: Vec<u8>,
+    },
+    Silence {
+        seq: u32,
+        frames: u32,
+    },
+    Lost {
+        seq: u32,
+    },
+    Bye {
+        reason: Option<ByeReason>,
+        reconnect_after: Option<Duration>,
+    },
+    Timeout,
+}
+
+/// Receives the stream of a `NetServer`.
+pub struct NetClient {
+    socket: UdpSocket,
+    server: SocketAddr,
+    info: Vec<u8>,
+    reorder: ReorderBuffer<Payload>,
+    ready: VecDeque<Event>,
+    buf: Vec<u8>,
+    mic_seq: u32,
+}
+
+enum Payload {
+    Audio(Vec<u8>),
+    Silence(u32),
+}
+
+impl Event {
+    /// Rebuilds the server packet carrying the event, keeping the sequence number.
+    /// Only audio and silence can be rebuilt.
+    pub fn to_pkt(&self) -> Option<Vec<u8>> {
+        match self {
+            Event::Audio { seq, payload } => Some(pkt::audio_pkt(*seq, payload)),
+            Event::Silence { seq, frames } => Some(pkt::silence_pkt(*seq, *frames)),
+            Event::Lost { .. } | Event::Bye { .. } | Event::Timeout => None,
+        }
+    }
+}
+
+impl NetClient {
+    /// Asks the server for `info` and starts listening.
+    pub fn connect(settings: Settings) -> Result<Self, Error> {
+        let bind_addr = if settings.server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket =
+            UdpSocket::bind(bind_addr).map_err(|e| IoError::new("creating a client socket", e))?;
+        socket
+            .connect(settings.server)
+            .map_err(|e| IoError::new(format!("connecting to {}", settings.server), e))?;
+        socket
+            .set_read_timeout(Some(settings.recv_timeout))
+            .map_err(|e| IoError::new("setting socket timeout", e))?;
+
+        let mut res = Self {
+            socket,
+            server: settings.server,
+            info: Vec::new(),
+            reorder: ReorderBuffer::new(settings.reorder_depth),
+            ready: VecDeque::new(),
+            buf: vec![0; 64 * 1024],
+            mic_seq: 0,
+        };
+
+        res.info = res.request_info(settings.handshake_attempts)?;
+        res.send(b"start")?;
+
+        Ok(res)
+    }
+
+    /// The server's answer to `info`.
+    pub fn info(&self) -> &[u8] {
+        &self.info
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Asks the server to play the microphone audio sent with `send_mic`.
+    /// The server doesn't answer, a wrong key shows in its log only. After a pause
+    /// longer than the server's timeout the key has to be sent again.
+    pub fn start_mic(&self, key: &str) -> Result<(), Error> {
+        let mut request = pkt::MIC_REQUEST.to_vec();
+        request.push(b' ');
+        request.extend_from_slice(key.as_bytes());
+        self.send(&request)
+    }
+
+    /// Sends an AAC frame of the microphone, `start_mic` must be called first.
+    pub fn send_mic(&mut self, aac: &[u8]) -> Result<(), Error> {
+        self.mic_seq = pkt::next_seq(self.mic_seq);
+        self.send(&pkt::mic_pkt(self.mic_seq, aac))
+    }
+
+    /// Waits for the next event. Duplicates are dropped and packets are reordered.
+    pub fn recv(&mut self) -> Result<Event, Error> {
+        loop {
+            if let Some(event) = self.ready.pop_front() {
+                return Ok(event);
+            }
+
+            let n = match self.socket.recv(&mut self.buf) {
+                Ok(n) => n,
+                Err(ref e) if is_timeout(e) => return Ok(Event::Timeout),
+                Err(e) => return Err(IoError::new("receiving from server", e).into()),
+            };
+
+            let event = match pkt::parse(&self.buf[..n]) {
+                Some(pkt::Pkt::Audio { seq, payload }) => {
+                    self.reorder.push(seq, Payload::Audio(payload.to_vec()));
+                    None
+                }
+                Some(pkt::Pkt::Silence { seq, frames }) => {
+                    self.reorder.push(seq, Payload::Silence(frames));
+                    None
+                }
+                Some(pkt::Pkt::Heartbeat { last_seq }) => {
+                    for slot in self.reorder.flush_until(last_seq) {
+                        self.ready.push_back(slot_to_event(slot));
+                    }
+                    None
+                }
+                Some(pkt::Pkt::Bye {
+                    reason,
+                    reconnect_after,
+                }) => Some(Event::Bye {
+                    reason,
+                    reconnect_after,
+                }),
+                // A late answer to one of the repeated requests.
+                Some(pkt::Pkt::Info { .. }) => None,
+                None => {
+                    eprintln!("Unknown packet from server of {} bytes", n);
+                    None
+                }
+            };
+
+            while let Some(slot) = self.reorder.pop() {
+                self.ready.push_back(slot_to_event(slot));
+            }
+            if let Some(event) = event {
+                self.ready.push_back(event);
+            }
+        }
+    }
+
+    fn request_info(&mut self, attempts: u32) -> Result<Vec<u8>, Error> {
+        for _ in 0..attempts {
+            self.send(pkt::TAGGED_INFO_REQUEST)?;
+
+            // Packets of an earlier session may still arrive before the answer.
+            loop {
+                let n = match self.socket.recv(&mut self.buf) {
+                    Ok(n) => n,
+                    Err(ref e) if is_timeout(e) => break,
+                    Err(e) => return Err(IoError::new("receiving info from server", e).into()),
+                };
+                if let Some(pkt::Pkt::Info { info }) = pkt::parse(&self.buf[..n]) {
+                    return Ok(info.to_vec());
+                }
+            }
+        }
+
+        Err(IoError::new(
+            format!("waiting for info from {}", self.server),
+            io::ErrorKind::TimedOut.into(),
+        )
+        .into())
+    }
+
+    fn send(&self, request: &[u8]) -> Result<(), Error> {
+        self.socket
+            .send(request)
+            .map_err(|e| IoError::new(format!("sending request to {}", self.server), e))?;
+        Ok(())
+    }
+}
+impl Drop for NetClient {
+    fn drop(&mut self) {
+        if let Err(e) = self.send(b"stop") {
+            eprintln!("Error stopping the stream: {}", e);
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            server: "127.0.0.1:25204".parse().unwrap(),
+            reorder_depth: 8,
+            handshake_attempts: 5,
+            recv_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+fn slot_to_event((seq, slot): (u32, Slot<Payload>)) -> Event {
+    match slot {
+        Slot::Received(Payload::Audio(payload)) => Event::Audio { seq, payload },
+        Slot::Received(Payload::Silence(frames)) => Event::Silence { seq, frames },
+        Slot::Lost => Event::Lost { seq },
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
@@ -0,0 +1,250 @@
+use crate::net_server::pkt;
+use std::collections::BTreeMap;
+
+/// Distance in reorder depths beyond which the sequence is resynchronized.
+const RESYNC_DEPTHS: i64 = 4;
+
+/// What takes a place in the sequence.
+#[derive(Debug, PartialEq)]
+pub enum Slot<T> {
+    Received(T),
+    Lost,
+}
+
+/// Puts packets back into the sequence order. A missing packet is given up on,
+/// once `depth` packets after it have arrived.
+///
+/// A packet further than `RESYNC_DEPTHS` depths from the expected one, either way,
+/// means the sender restarted or the stream was cut off for long: the sequence
+/// starts again from it.
+pub struct ReorderBuffer<T> {
+    depth: usize,
+    /// The sequence number of the next packet to be released.
+    expected: Option<u32>,
+    /// Position of `expected` in the sequence with the wrap arounds unrolled.
+    expected_pos: u64,
+    held: BTreeMap<u64, (u32, T)>,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            expected: None,
+            expected_pos: 0,
+            held: BTreeMap::new(),
+        }
+    }
+
+    /// Duplicates and packets that came after their place was given up on are dropped.
+    /// On a resync the held packets are dropped too.
+    pub fn push(&mut self, seq: u32, item: T) {
+        let expected = *self.expected.get_or_insert(seq);
+
+        let mut distance = distance(expected, seq);
+        if distance.abs() > self.resync_distance() {
+            self.held.clear();
+            self.expected = Some(seq);
+            distance = 0;
+        }
+        if distance < 0 {
+            return;
+        }
+        let pos = self.expected_pos + distance as u64;
+        self.held.entry(pos).or_insert((seq, item));
+    }
+
+    /// Returns the next slot in the sequence, if it's already known.
+    pub fn pop(&mut self) -> Option<(u32, Slot<T>)> {
+        let expected = self.expected?;
+
+        if let Some((seq, item)) = self.held.remove(&self.expected_pos) {
+            self.advance(seq);
+            return Some((seq, Slot::Received(item)));
+        }
+
+        if self.held.len() > self.depth {
+            self.advance(expected);
+            return Some((expected, Slot::Lost));
+        }
+
+        None
+    }
+
+    /// Gives up waiting: everything up to and including `last_seq` is released,
+    /// the missing packets as lost.
+    ///
+    /// If `last_seq` is too far ahead to fill the gap, the held packets are released
+    /// and the sequence goes on after `last_seq`.
+    pub fn flush_until(&mut self, last_seq: u32) -> Vec<(u32, Slot<T>)> {
+        let mut res = Vec::new();
+
+        if let Some(expected) = self.expected {
+            if distance(expected, last_seq) > self.resync_distance() {
+                let held = std::mem::take(&mut self.held);
+                res.extend(
+                    held.into_iter()
+                        .map(|(_, (seq, item))| (seq, Slot::Received(item))),
+                );
+                self.expected = Some(pkt::next_seq(last_seq));
+                self.expected_pos += 1;
+                return res;
+            }
+        }
+
+        while let Some(expected) = self.expected {
+            if distance(expected, last_seq) < 0 {
+                break;
+            }
+
+            match self.held.remove(&self.expected_pos) {
+                Some((seq, item)) => res.push((seq, Slot::Received(item))),
+                None => res.push((expected, Slot::Lost)),
+            }
+            self.advance(expected);
+        }
+
+        res
+    }
+
+    fn resync_distance(&self) -> i64 {
+        (self.depth.max(1) as i64) * RESYNC_DEPTHS
+    }
+
+    fn advance(&mut self, seq: u32) {
+        self.expected = Some(pkt::next_seq(seq));
+        self.expected_pos += 1;
+    }
+}
+
+/// Signed distance from `from` to `to`, taking the wrap around into account.
+fn distance(from: u32, to: u32) -> i64 {
+    let mut res = i64::from(to.wrapping_sub(from) as i32);
+    // The sequence number reserved for control packets is skipped on the wrap around.
+    if res > 0 && to < from {
+        res -= 1;
+    } else if res < 0 && to > from {
+        res += 1;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pop_all(reorder: &mut ReorderBuffer<u32>) -> Vec<(u32, Slot<u32>)> {
+        let mut res = Vec::new();
+        while let Some(slot) = reorder.pop() {
+            res.push(slot);
+        }
+        res
+    }
+
+    fn push_all(reorder: &mut ReorderBuffer<u32>, seqs: &[u32]) {
+        for &seq in seqs {
+            reorder.push(seq, seq);
+        }
+    }
+
+    #[test]
+    fn reorders() {
+        let mut reorder = ReorderBuffer::new(4);
+        push_all(&mut reorder, &[1, 3, 2, 4]);
+
+        let seqs: Vec<_> = pop_all(&mut reorder).into_iter().map(|(s, _)| s).collect();
+        assert_eq!(seqs, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn gives_up_after_depth() {
+        let mut reorder = ReorderBuffer::new(2);
+        push_all(&mut reorder, &[1, 3, 4]);
+        assert_eq!(pop_all(&mut reorder), vec![(1, Slot::Received(1))]);
+
+        reorder.push(5, 5);
+        assert_eq!(
+            pop_all(&mut reorder),
+            vec![
+                (2, Slot::Lost),
+                (3, Slot::Received(3)),
+                (4, Slot::Received(4)),
+                (5, Slot::Received(5)),
+            ]
+        );
+
+        reorder.push(2, 2);
+        assert_eq!(pop_all(&mut reorder), vec![]);
+    }
+
+    #[test]
+    fn wraps_around_skipping_control_seq() {
+        let mut reorder = ReorderBuffer::new(2);
+        push_all(&mut reorder, &[u32::MAX - 1, 1, u32::MAX]);
+
+        assert_eq!(
+            pop_all(&mut reorder),
+            vec![
+                (u32::MAX - 1, Slot::Received(u32::MAX - 1)),
+                (u32::MAX, Slot::Received(u32::MAX)),
+                (1, Slot::Received(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn resyncs_on_forward_jump() {
+        let mut reorder = ReorderBuffer::new(2);
+        reorder.push(1, 1);
+        pop_all(&mut reorder);
+
+        push_all(&mut reorder, &[1_000_000, 1_000_001]);
+        assert_eq!(
+            pop_all(&mut reorder),
+            vec![
+                (1_000_000, Slot::Received(1_000_000)),
+                (1_000_001, Slot::Received(1_000_001)),
+            ]
+        );
+    }
+
+    #[test]
+    fn resyncs_on_sender_restart() {
+        let mut reorder = ReorderBuffer::new(2);
+        push_all(&mut reorder, &[500, 501]);
+        pop_all(&mut reorder);
+
+        push_all(&mut reorder, &[1, 2]);
+        assert_eq!(
+            pop_all(&mut reorder),
+            vec![(1, Slot::Received(1)), (2, Slot::Received(2))]
+        );
+    }
+
+    #[test]
+    fn flushes_missing_as_lost() {
+        let mut reorder = ReorderBuffer::new(4);
+        push_all(&mut reorder, &[1, 3]);
+        pop_all(&mut reorder);
+
+        assert_eq!(
+            reorder.flush_until(4),
+            vec![(2, Slot::Lost), (3, Slot::Received(3)), (4, Slot::Lost)]
+        );
+    }
+
+    #[test]
+    fn flush_far_ahead_doesnt_fill_the_gap() {
+        let mut reorder = ReorderBuffer::new(2);
+        push_all(&mut reorder, &[1, 3]);
+        pop_all(&mut reorder);
+
+        assert_eq!(reorder.flush_until(1_000_000), vec![(3, Slot::Received(3))]);
+
+        reorder.push(1_000_001, 1_000_001);
+        assert_eq!(
+            pop_all(&mut reorder),
+            vec![(1_000_001, Slot::Received(1_000_001))]
+        );
+    }
+}
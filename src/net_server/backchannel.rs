@@ -0,0 +1,290 @@
+//! Microphone audio sent back by one authorized client and played on a local device.
+//!
+//! The client authorizes its address with the `mic <key>` request, then sends
+//! `pkt::mic_pkt`s of AAC frames to the same socket. The packets are reordered, decoded
+//! and go through a jitter buffer of their own to a separate playback device.
+//!
+//! Meant for a LAN only: the key goes in clear text and the authorization is bound to
+//! the source address, anybody who can see the traffic or spoof the address can use it.
+//! An address that gets the key wrong too often is ignored for a while.
+
+use crate::alsa;
+use crate::channel;
+use crate::error::{Error, IoError};
+use crate::net_client::{Event, JitterBuffer, ReorderBuffer, Slot, StreamDecoder};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Frames written to the device at once.
+const PLAYBACK_CHUNK_FRAMES: usize = 256;
+/// Speech packets are small and frequent, a short wait for the late ones is enough.
+const REORDER_DEPTH: usize = 4;
+/// Packets waiting for the decoder, newer ones are dropped beyond that.
+const QUEUE_LEN: usize = 64;
+/// Wrong keys an address may send within `FAILED_ATTEMPTS_WINDOW` before it's locked out.
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+const FAILED_ATTEMPTS_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Shared secret the client authorizes with.
+    pub key: String,
+    /// Alsa playback device. It must not be the one the stream is captured from,
+    /// or the client would hear itself.
+    pub device: String,
+    /// Format of the device, the audio is decoded to it.
+    pub params: alsa::Params,
+    /// Audio buffered before the playback starts, and again after an underrun.
+    pub latency: Duration,
+    /// Linear gain. Below 1 by default, so that a microphone near the speakers doesn't howl.
+    pub gain: f32,
+    /// The client has to authorize again after this long without a packet.
+    pub timeout: Duration,
+}
+
+pub(super) struct Backchannel {
+    key: String,
+    timeout: Duration,
+    /// The authorized client and when its last packet arrived.
+    client: Option<(SocketAddr, Instant)>,
+    /// Wrong keys by address and when the first of them arrived.
+    failed_attempts: HashMap<IpAddr, (u32, Instant)>,
+    sender: Option<channel::Sender<Msg>>,
+    decoder_thread: Option<JoinHandle<()>>,
+    player_thread: Option<JoinHandle<()>>,
+}
+
+enum Msg {
+    /// A new client starts its own sequence.
+    NewClient,
+    Audio(u32, Vec<u8>),
+}
+
+impl Backchannel {
+    pub fn new(settings: Settings) -> Result<Self, Error> {
+        let player = alsa::SndPcm::open(
+            settings.device.clone(),
+            alsa::Stream::Playback,
+            settings.params,
+        )?;
+        println!("Microphone player settings: {}", player.dump_settings()?);
+        let params = player.get_params();
+
+        let decoder = StreamDecoder::new(params)?;
+
+        let target = (settings.latency.as_millis() as usize * params.rate as usize / 1000)
+            * params.bytes_per_frame();
+        let jitter_buffer = Arc::new(JitterBuffer::new(target, target * 4));
+
+        let player_thread = {
+            let jitter_buffer = jitter_buffer.clone();
+            thread::Builder::new()
+                .name("Microphone player".to_owned())
+                .spawn(move || play(jitter_buffer, player))
+                .map_err(|e| IoError::new("spawning microphone player thread", e))?
+        };
+
+        let (sender, receiver) = channel::bounded(QUEUE_LEN);
+        let gain = settings.gain;
+        let decoder_thread = thread::Builder::new()
+            .name("Microphone decoder".to_owned())
+            .spawn(move || decode(receiver, decoder, gain, jitter_buffer))
+            .map_err(|e| IoError::new("spawning microphone decoder thread", e))?;
+
+        Ok(Self {
+            key: settings.key,
+            timeout: settings.timeout,
+            client: None,
+            failed_attempts: HashMap::new(),
+            sender: Some(sender),
+            decoder_thread: Some(decoder_thread),
+            player_thread: Some(player_thread),
+        })
+    }
+
+    /// Handles `mic <key>`, the key is what follows the space.
+    pub fn authorize(&mut self, key: &[u8], addr: SocketAddr) {
+        self.failed_attempts
+            .retain(|_, (_, first)| first.elapsed() < FAILED_ATTEMPTS_WINDOW);
+        let failed = self.failed_attempts.get(&addr.ip()).map_or(0, |(n, _)| *n);
+        if failed >= MAX_FAILED_ATTEMPTS {
+            return;
+        }
+
+        if !key_matches(key, self.key.as_bytes()) {
+            let (failed, _) = self
+                .failed_attempts
+                .entry(addr.ip())
+                .or_insert((0, Instant::now()));
+            *failed += 1;
+            if *failed == MAX_FAILED_ATTEMPTS {
+                eprintln!("Too many wrong microphone keys from {}, ignoring it", addr);
+            } else {
+                eprintln!("Wrong microphone key from {}", addr);
+            }
+            return;
+        }
+
+        match self.client {
+            Some((client, _)) if client == addr => (),
+            _ => {
+                eprintln!("Playing the microphone of {}", addr);
+                self.send(Msg::NewClient);
+            }
+        }
+        self.client = Some((addr, Instant::now()));
+    }
+
+    /// Packets from anybody but the authorized client are dropped.
+    pub fn receive(&mut self, addr: SocketAddr, seq: u32, payload: &[u8]) {
+        let last_pkt = match &mut self.client {
+            Some((client, last_pkt)) if *client == addr => last_pkt,
+            _ => {
+                eprintln!("Microphone packet from unauthorized {}", addr);
+                return;
+            }
+        };
+        if last_pkt.elapsed() > self.timeout {
+            eprintln!(
+                "Microphone of {} timed out, it has to authorize again",
+                addr
+            );
+            self.client = None;
+            return;
+        }
+
+        *last_pkt = Instant::now();
+        self.send(Msg::Audio(seq, payload.to_vec()));
+    }
+
+    fn send(&self, msg: Msg) {
+        if let Some(sender) = &self.sender {
+            if let Err(channel::TrySendError::Full(_)) = sender.try_send(msg) {
+                eprintln!("Microphone packet dropped, the decoder is behind");
+            }
+        }
+    }
+}
+impl Drop for Backchannel {
+    fn drop(&mut self) {
+        // Disconnecting the channel stops the decoder, which stops the player.
+        self.sender = None;
+        for thread in self
+            .decoder_thread
+            .take()
+            .into_iter()
+            .chain(self.player_thread.take())
+        {
+            if thread.join().is_err() {
+                eprintln!("Microphone thread panicked");
+            }
+        }
+    }
+}
+
+/// Takes as long whichever byte differs, only the length of the key can be timed.
+fn key_matches(key: &[u8], expected: &[u8]) -> bool {
+    key.len() == expected.len() && openssl::memcmp::eq(key, expected)
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            key: String::new(),
+            device: "default".to_owned(),
+            params: alsa::Params {
+                format: alsa::Format::FloatLe,
+                channels: 2,
+                rate: 44100,
+            },
+            latency: Duration::from_millis(100),
+            gain: 0.5,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+fn decode(
+    receiver: channel::Receiver<Msg>,
+    mut decoder: StreamDecoder,
+    gain: f32,
+    jitter_buffer: Arc<JitterBuffer>,
+) {
+    let format = decoder.get_params().format;
+    let mut reorder = ReorderBuffer::new(REORDER_DEPTH);
+    let mut pcm = Vec::new();
+
+    for msg in receiver.iter() {
+        match msg {
+            Msg::NewClient => reorder = ReorderBuffer::new(REORDER_DEPTH),
+            Msg::Audio(seq, payload) => reorder.push(seq, payload),
+        }
+
+        while let Some((seq, slot)) = reorder.pop() {
+            let event = match slot {
+                Slot::Received(payload) => Event::Audio { seq, payload },
+                Slot::Lost => Event::Lost { seq },
+            };
+            pcm.clear();
+            if let Err(e) = decoder.decode(&event, &mut pcm) {
+                eprintln!("Error decoding microphone audio: {}", e);
+                continue;
+            }
+            apply_gain(format, gain, &mut pcm);
+            jitter_buffer.push(&pcm);
+        }
+    }
+
+    jitter_buffer.close();
+}
+
+fn play(jitter_buffer: Arc<JitterBuffer>, player: alsa::SndPcm) {
+    let params = player.get_params();
+    let silence = params.format.silence_byte();
+    let mut buffer = vec![0; PLAYBACK_CHUNK_FRAMES * params.bytes_per_frame()];
+
+    while jitter_buffer.pop(&mut buffer, silence) {
+        if let Err(e) = player.write_interleaved(&buffer) {
+            eprintln!("Error playing microphone audio: {}", e);
+            return;
+        }
+    }
+    println!("Microphone underruns: {}", jitter_buffer.underruns());
+}
+
+fn apply_gain(format: alsa::Format, gain: f32, pcm: &mut [u8]) {
+    if (gain - 1.0).abs() < f32::EPSILON {
+        return;
+    }
+
+    match format {
+        alsa::Format::U8 => {
+            for b in pcm.iter_mut() {
+                let v = (f32::from(*b) - 128.0) * gain + 128.0;
+                *b = v.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        alsa::Format::S16Le => {
+            for sample in pcm.chunks_exact_mut(2) {
+                let v = f32::from(i16::from_le_bytes([sample[0], sample[1]])) * gain;
+                // Float to int casts saturate.
+                sample.copy_from_slice(&(v as i16).to_le_bytes());
+            }
+        }
+        alsa::Format::FloatLe => {
+            for sample in pcm.chunks_exact_mut(4) {
+                let v = f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) * gain;
+                sample.copy_from_slice(&v.to_le_bytes());
+            }
+        }
+        format => {
+            for sample in pcm.chunks_exact_mut(format.bytes_per_sample()) {
+                let v = format.sample_to_f32(sample) * gain;
+                format.sample_from_f32(v, sample);
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Capture file format. All integers are big-endian.
+//!
+//! Header: `b"SACAP" | version: u8`, the version is `1`.
+//!
+//! Followed by records until the end of the file:
+//! `sent_at_us: u64 | ip_len: u8 | ip: [u8; ip_len] | port: u16 | len: u32 | pkt: [u8; len]`,
+//! where `sent_at_us` is microseconds since the UNIX epoch, `ip_len` is 4 for IPv4 and 16 for
+//! IPv6 destinations and `pkt` is the datagram exactly as it was sent.
+
+use crate::error::*;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8] = b"SACAP";
+const VERSION: u8 = 1;
+
+/// A packet read from a capture file.
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// Time since the UNIX epoch.
+    pub sent_at: Duration,
+    pub dest: SocketAddr,
+    pub pkt: Vec<u8>,
+}
+
+pub struct CaptureWriter {
+    out: BufWriter<fs::File>,
+    fname: String,
+}
+
+pub struct CaptureReader {
+    input: BufReader<fs::File>,
+    fname: String,
+}
+
+impl CaptureWriter {
+    pub fn create(fname: String) -> Result<Self, Error> {
+        let file = match fs::File::create(&fname) {
+            Ok(file) => file,
+            Err(e) => return Err(FileError::create(fname, e).into()),
+        };
+
+        let mut res = Self {
+            out: BufWriter::new(file),
+            fname,
+        };
+        res.write_all(MAGIC)?;
+        res.write_all(&[VERSION])?;
+        Ok(res)
+    }
+
+    pub fn write(&mut self, pkt: &[u8], dest: &SocketAddr) -> Result<(), Error> {
+        let sent_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let sent_at_us = sent_at.as_secs() * 1_000_000 + u64::from(sent_at.subsec_micros());
+
+        self.write_all(&sent_at_us.to_be_bytes())?;
+        match dest.ip() {
+            IpAddr::V4(ip) => {
+                self.write_all(&[4])?;
+                self.write_all(&ip.octets())?;
+            }
+            IpAddr::V6(ip) => {
+                self.write_all(&[16])?;
+                self.write_all(&ip.octets())?;
+            }
+        }
+        self.write_all(&dest.port().to_be_bytes())?;
+        self.write_all(&(pkt.len() as u32).to_be_bytes())?;
+        self.write_all(pkt)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        match self.out.flush() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(FileError::create(self.fname.clone(), e).into()),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        match self.out.write_all(buf) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(FileError::create(self.fname.clone(), e).into()),
+        }
+    }
+}
+
+impl CaptureReader {
+    pub fn open(fname: String) -> Result<Self, Error> {
+        let file = match fs::File::open(&fname) {
+            Ok(file) => file,
+            Err(e) => return Err(FileError::create(fname, e).into()),
+        };
+
+        let mut res = Self {
+            input: BufReader::new(file),
+            fname,
+        };
+
+        let mut header = [0; 6];
+        res.read_exact(&mut header)?;
+        if &header[..5] != MAGIC || header[5] != VERSION {
+            return Err(res.invalid_data("not a capture file or unsupported version"));
+        }
+
+        Ok(res)
+    }
+
+    /// Returns `None` at the end of the file.
+    pub fn read_record(&mut self) -> Result<Option<Record>, Error> {
+        let mut sent_at_us = [0; 8];
+        match self.input.read_exact(&mut sent_at_us) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(FileError::create(self.fname.clone(), e).into()),
+        }
+        let sent_at = Duration::from_micros(u64::from_be_bytes(sent_at_us));
+
+        let mut ip_len = [0; 1];
+        self.read_exact(&mut ip_len)?;
+        let ip = match ip_len[0] {
+            4 => {
+                let mut ip = [0; 4];
+                self.read_exact(&mut ip)?;
+                IpAddr::V4(Ipv4Addr::from(ip))
+            }
+            16 => {
+                let mut ip = [0; 16];
+                self.read_exact(&mut ip)?;
+                IpAddr::V6(Ipv6Addr::from(ip))
+            }
+            _ => return Err(self.invalid_data("wrong address length")),
+        };
+
+        let mut port = [0; 2];
+        self.read_exact(&mut port)?;
+        let mut len = [0; 4];
+        self.read_exact(&mut len)?;
+
+        let mut pkt = vec![0; u32::from_be_bytes(len) as usize];
+        self.read_exact(&mut pkt)?;
+
+        Ok(Some(Record {
+            sent_at,
+            dest: SocketAddr::new(ip, u16::from_be_bytes(port)),
+            pkt,
+        }))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        match self.input.read_exact(buf) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(FileError::create(self.fname.clone(), e).into()),
+        }
+    }
+
+    fn invalid_data(&self, msg: &'static str) -> Error {
+        FileError::create(
+            self.fname.clone(),
+            io::Error::new(io::ErrorKind::InvalidData, msg),
+        )
+        .into()
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
@@ -0,0 +1,176 @@
+//! Discontinuous transmission: encoded digital silence is replaced by silence markers.
+//!
+//! The encoder gives out a frame some time after it took its PCM in, so whether a packet
+//! is silent can't be told by the PCM given with it. The packets are counted instead, and
+//! one is only replaced when it starts later than the silence did by more than the encoder
+//! can hold frames back. The markers count the frames of the packets they replace.
+
+/// Frames in an AAC packet.
+const AAC_FRAME_SAMPLES: u64 = 1024;
+/// The encoder's packets start less than this many frames after their PCM: a frame of
+/// priming and one of lookahead, with room to spare.
+const ENCODER_DELAY_FRAMES: u64 = 4 * AAC_FRAME_SAMPLES;
+/// Markers are sent this many times per second of silence.
+const MARKERS_PER_SEC: u32 = 10;
+
+pub struct Dtx {
+    /// Frames of silence a marker is sent for.
+    marker_frames: u32,
+    /// Frames given to the encoder.
+    pcm_frames: u64,
+    /// Frames of the packets the encoder gave out.
+    encoded_frames: u64,
+    /// Where the silence the encoder is given started.
+    silence_start: Option<u64>,
+    /// Frames of the replaced packets not marked yet.
+    unmarked: u32,
+}
+
+/// What to do with an encoded packet.
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    /// Send it to the clients.
+    Audio,
+    /// Drop it, sending a marker of that many frames first if there are some.
+    Silence(Option<u32>),
+}
+
+impl Dtx {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            marker_frames: rate / MARKERS_PER_SEC,
+            pcm_frames: 0,
+            encoded_frames: 0,
+            silence_start: None,
+            unmarked: 0,
+        }
+    }
+
+    /// Takes note of the PCM before it's given to the encoder. When the silence ends,
+    /// returns the frames to mark before the packets that follow.
+    pub fn pcm(&mut self, silent: bool, frames: u32) -> Option<u32> {
+        let start = self.pcm_frames;
+        self.pcm_frames += u64::from(frames);
+        if silent {
+            self.silence_start.get_or_insert(start);
+            return None;
+        }
+        self.silence_start = None;
+        self.take_unmarked()
+    }
+
+    /// Decides on the next packet the encoder gave out.
+    pub fn encoded(&mut self) -> Packet {
+        let start = self.encoded_frames;
+        self.encoded_frames += AAC_FRAME_SAMPLES;
+        match self.silence_start {
+            Some(silence_start) if start >= silence_start + ENCODER_DELAY_FRAMES => (),
+            _ => return Packet::Audio,
+        }
+
+        self.unmarked += AAC_FRAME_SAMPLES as u32;
+        if self.unmarked >= self.marker_frames {
+            Packet::Silence(self.take_unmarked())
+        } else {
+            Packet::Silence(None)
+        }
+    }
+
+    fn take_unmarked(&mut self) -> Option<u32> {
+        match std::mem::take(&mut self.unmarked) {
+            0 => None,
+            frames => Some(frames),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    const RATE: u32 = 48000;
+    /// The delay of the simulated encoder.
+    const DELAY: usize = 2048;
+
+    /// What the clients got: the loudness of the audio packets' frames, and the silence.
+    #[derive(Debug, PartialEq)]
+    enum Sent {
+        Audio(Vec<u8>),
+        Silence(u32),
+    }
+
+    /// Runs the slices through an encoder that gives out a packet of the frames it took
+    /// `DELAY` frames ago, a frame being its loudness.
+    fn run(slices: &[(u8, usize)]) -> Vec<Sent> {
+        let mut dtx = Dtx::new(RATE);
+        let mut encoder: VecDeque<u8> = vec![0; DELAY].into();
+        let mut sent = Vec::new();
+        for &(loudness, frames) in slices {
+            if let Some(frames) = dtx.pcm(loudness == 0, frames as u32) {
+                sent.push(Sent::Silence(frames));
+            }
+            encoder.extend(vec![loudness; frames]);
+            while encoder.len() >= DELAY + AAC_FRAME_SAMPLES as usize {
+                let pkt: Vec<u8> = encoder.drain(..AAC_FRAME_SAMPLES as usize).collect();
+                match dtx.encoded() {
+                    Packet::Audio => sent.push(Sent::Audio(pkt)),
+                    Packet::Silence(Some(frames)) => sent.push(Sent::Silence(frames)),
+                    Packet::Silence(None) => (),
+                }
+            }
+        }
+        sent
+    }
+
+    fn frames(sent: &[Sent]) -> Vec<u8> {
+        let mut res = Vec::new();
+        for s in sent {
+            match s {
+                Sent::Audio(pkt) => res.extend_from_slice(pkt),
+                Sent::Silence(frames) => res.resize(res.len() + *frames as usize, 0),
+            }
+        }
+        res
+    }
+
+    /// The frames of the slices as the encoder gives them out, `len` of them.
+    fn delayed(slices: &[(u8, usize)], len: usize) -> Vec<u8> {
+        let mut res = vec![0; DELAY];
+        for &(loudness, frames) in slices {
+            res.extend(vec![loudness; frames]);
+        }
+        res.resize(len, 0);
+        res
+    }
+
+    #[test]
+    fn audio_before_silence_is_delivered() {
+        let slices = [(0, 1000), (1, 3000), (0, 48000), (2, 3000), (0, 10000)];
+        let sent = run(&slices);
+        let frames = frames(&sent);
+        assert_eq!(frames, delayed(&slices, frames.len()));
+        assert!(sent
+            .iter()
+            .any(|s| matches!(s, Sent::Silence(frames) if *frames >= RATE / MARKERS_PER_SEC)));
+    }
+
+    #[test]
+    fn short_silence_is_sent_as_audio() {
+        let sent = run(&[(1, 4096), (0, 2048), (1, 8192)]);
+        assert!(sent.iter().all(|s| matches!(s, Sent::Audio(_))));
+    }
+
+    #[test]
+    fn silence_is_marked_before_resumed_audio() {
+        let slices = [(1, 4096), (0, 9000), (1, 4096)];
+        let sent = run(&slices);
+        let frames = frames(&sent);
+        assert_eq!(frames, delayed(&slices, frames.len()));
+
+        let marker = sent.iter().position(|s| matches!(s, Sent::Silence(_)));
+        assert!(sent[marker.unwrap() + 1..]
+            .iter()
+            .all(|s| matches!(s, Sent::Audio(_))));
+    }
+}
@@ -0,0 +1,196 @@
+//! Simulates a bad network in front of the sockets: loss, delay, jitter, duplication and
+//! reordering. Only meant for testing clients, e.g. over loopback.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub loss: Loss,
+    /// Constant delay added to every packet.
+    pub delay: Duration,
+    /// Every packet is additionally delayed by a random value from `0..jitter`.
+    pub jitter: Duration,
+    /// Probability of a packet being sent twice.
+    pub duplicate: f64,
+    /// Probability of a packet being held back by `reorder_delay`,
+    /// so that the following packets overtake it.
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+    /// Makes the impairment reproducible.
+    pub seed: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Loss {
+    None,
+    /// Every packet is lost with the given probability.
+    Random(f64),
+    /// Bursty loss: a two state Markov chain, every state with its own loss probability.
+    GilbertElliott {
+        good_to_bad: f64,
+        bad_to_good: f64,
+        loss_in_good: f64,
+        loss_in_bad: f64,
+    },
+}
+
+pub struct Impairment {
+    settings: Settings,
+    rng: StdRng,
+    is_bad_state: bool,
+    held: BinaryHeap<HeldPkt>,
+    pushed_cnt: u64,
+}
+
+/// A packet waiting for its time to be sent.
+pub struct HeldPkt {
+    pub due: Instant,
+    pub socket: usize,
+    pub dest: SocketAddr,
+    pub pkt: Vec<u8>,
+    order: u64,
+}
+
+impl Impairment {
+    pub fn new(settings: Settings) -> Self {
+        let rng = match settings.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Self {
+            settings,
+            rng,
+            is_bad_state: false,
+            held: BinaryHeap::new(),
+            pushed_cnt: 0,
+        }
+    }
+
+    /// Decides the fate of a packet that should be sent now through `socket` to `dest`.
+    pub fn push(&mut self, socket: usize, dest: SocketAddr, pkt: &[u8], now: Instant) {
+        if self.is_lost() {
+            return;
+        }
+
+        let copies = if self.chance(self.settings.duplicate) {
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let mut due = now + self.settings.delay;
+            if self.settings.jitter > Duration::from_secs(0) {
+                due += self.settings.jitter.mul_f64(self.rng.gen::<f64>());
+            }
+            if self.chance(self.settings.reorder) {
+                due += self.settings.reorder_delay;
+            }
+
+            self.pushed_cnt += 1;
+            self.held.push(HeldPkt {
+                due,
+                socket,
+                dest,
+                pkt: pkt.to_vec(),
+                order: self.pushed_cnt,
+            });
+        }
+    }
+
+    /// Returns the next packet which is due by `now`.
+    pub fn pop_due(&mut self, now: Instant) -> Option<HeldPkt> {
+        if self.held.peek()?.due > now {
+            return None;
+        }
+        self.held.pop()
+    }
+
+    pub fn next_due(&self) -> Option<Instant> {
+        self.held.peek().map(|p| p.due)
+    }
+
+    /// Packets waiting for their due time.
+    pub fn held_cnt(&self) -> usize {
+        self.held.len()
+    }
+
+    fn is_lost(&mut self) -> bool {
+        match self.settings.loss {
+            Loss::None => false,
+            Loss::Random(p) => self.chance(p),
+            Loss::GilbertElliott {
+                good_to_bad,
+                bad_to_good,
+                loss_in_good,
+                loss_in_bad,
+            } => {
+                let switch = if self.is_bad_state {
+                    bad_to_good
+                } else {
+                    good_to_bad
+                };
+                if self.chance(switch) {
+                    self.is_bad_state = !self.is_bad_state;
+                }
+
+                if self.is_bad_state {
+                    self.chance(loss_in_bad)
+                } else {
+                    self.chance(loss_in_good)
+                }
+            }
+        }
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        if p <= 0. {
+            false
+        } else if p >= 1. {
+            true
+        } else {
+            self.rng.gen_bool(p)
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            loss: Loss::None,
+            delay: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+            duplicate: 0.,
+            reorder: 0.,
+            reorder_delay: Duration::from_millis(20),
+            seed: None,
+        }
+    }
+}
+
+/// `BinaryHeap` is a max-heap, so the earliest packet is the greatest.
+impl Ord for HeldPkt {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        other
+            .due
+            .cmp(&self.due)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+impl PartialOrd for HeldPkt {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for HeldPkt {
+    fn eq(&self, other: &Self) -> bool {
+        self.order == other.order
+    }
+}
+impl Eq for HeldPkt {}
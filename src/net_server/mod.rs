@@ -1,36 +1,95 @@
-mod pkt;
+#[cfg(feature = "fdk-aac")]
+pub mod backchannel;
+pub mod capture;
+pub mod dtx;
+#[cfg(feature = "impairment")]
+pub mod impairment;
+pub mod pkt;
+pub mod rtsp;
+pub mod snapcast;
+mod tcp_conn;
 
 use crate::error::{Error, IoError};
 use crate::exit_listener;
 use mio;
 use mio::net::UdpSocket;
+use net2;
 use std::collections::VecDeque;
+use std::io;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+pub use pkt::ByeReason;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Every address gets its own socket. IPv6 sockets are dual-stack, so `[::]:port` also
+    /// accepts IPv4 clients and must not be combined with `0.0.0.0:port`.
+    pub addrs: Vec<SocketAddr>,
+    /// How long pending audio and goodbye messages may take to go out on shutdown.
+    pub shutdown_timeout: Duration,
+    /// Tells clients when they may try to reconnect after the server says goodbye.
+    pub reconnect_hint: Option<Duration>,
+    /// A heartbeat is sent when nothing has been sent to clients for this long. Off by
+    /// default, as clients older than the control packets would take it for audio.
+    pub heartbeat_interval: Option<Duration>,
+    /// Every outgoing packet is also written to this file, see `capture` for the format.
+    pub capture: Option<String>,
+    /// Passes outgoing packets through a simulated bad network.
+    #[cfg(feature = "impairment")]
+    pub impairment: Option<impairment::Settings>,
+    /// Also serves the stream as RTP to RTSP players.
+    pub rtsp: Option<rtsp::Settings>,
+    /// Also serves the PCM given to `send_pcm` to snapclients.
+    pub snapcast: Option<snapcast::Settings>,
+    /// Plays the microphone audio of an authorized client.
+    #[cfg(feature = "fdk-aac")]
+    pub backchannel: Option<backchannel::Settings>,
+}
 
 pub struct NetServer {
     que: Arc<Mutex<SendQueue>>,
     new_data_readiness: mio::SetReadiness,
+    local_addrs: Vec<SocketAddr>,
+    accepts_pcm: bool,
+    thread: Option<thread::JoinHandle<()>>,
 }
 
-const UDP_TOKEN: mio::Token = mio::Token(0);
 const EXIT_TOKEN: mio::Token = mio::Token(1);
 const SEND_DATA_TOKEN: mio::Token = mio::Token(2);
+/// Socket `i` is registered with the token `FIRST_SOCKET_TOKEN + i`.
+const FIRST_SOCKET_TOKEN: usize = 100;
+/// The RTSP listener and connections are registered starting with this token.
+const FIRST_RTSP_TOKEN: usize = 1 << 20;
+/// The Snapcast listener and connections are registered starting with this token.
+const FIRST_SNAPCAST_TOKEN: usize = 2 << 20;
 
 impl NetServer {
-    pub fn new(addr: SocketAddr, stopper: exit_listener::SignalEvent) -> Result<Self, Error> {
-        let socket = UdpSocket::bind(&addr).map_err(|e| IoError::new("creating a socket", e))?;
-
+    pub fn new(settings: Settings, stopper: exit_listener::SignalEvent) -> Result<Self, Error> {
         let poll = mio::Poll::new().map_err(|e| IoError::new("creating mio::Poll", e))?;
 
-        poll.register(
-            &socket,
-            UDP_TOKEN,
-            mio::Ready::readable(),
-            mio::PollOpt::level(),
-        )
-        .map_err(|e| IoError::new(format!("Registering UdpSocket {} to poll", addr), e))?;
+        let mut sockets = Vec::with_capacity(settings.addrs.len());
+        let mut local_addrs = Vec::with_capacity(settings.addrs.len());
+        for (idx, addr) in settings.addrs.iter().enumerate() {
+            let socket = bind_socket(addr)?;
+
+            poll.register(
+                &socket,
+                mio::Token(FIRST_SOCKET_TOKEN + idx),
+                mio::Ready::readable(),
+                mio::PollOpt::level(),
+            )
+            .map_err(|e| IoError::new(format!("Registering UdpSocket {} to poll", addr), e))?;
+
+            let local_addr = socket
+                .local_addr()
+                .map_err(|e| IoError::new(format!("getting local address of {}", addr), e))?;
+
+            sockets.push(socket);
+            local_addrs.push(local_addr);
+        }
 
         poll.register(
             &stopper,
@@ -40,6 +99,11 @@ impl NetServer {
         )
         .map_err(|e| IoError::new("Registering SignalEvent to poll", e))?;
 
+        let capture = match &settings.capture {
+            Some(fname) => Some(capture::CaptureWriter::create(fname.clone())?),
+            None => None,
+        };
+
         let (registration, set_readiness) = mio::Registration::new2();
         let que = Arc::new(Mutex::new(SendQueue::new(registration)));
 
@@ -51,23 +115,71 @@ impl NetServer {
         )
         .map_err(|e| IoError::new("Registering SendQueue to poll", e))?;
 
-        let res = Self {
-            que: que.clone(),
-            new_data_readiness: set_readiness,
+        #[cfg(feature = "impairment")]
+        let impairment = settings.impairment.clone().map(impairment::Impairment::new);
+
+        let rtsp = match &settings.rtsp {
+            Some(rtsp_settings) => Some(rtsp::RtspServer::bind(
+                rtsp_settings.clone(),
+                &poll,
+                FIRST_RTSP_TOKEN,
+            )?),
+            None => None,
+        };
+
+        let snapcast = match &settings.snapcast {
+            Some(snapcast_settings) => Some(snapcast::SnapcastServer::bind(
+                snapcast_settings.clone(),
+                &poll,
+                FIRST_SNAPCAST_TOKEN,
+            )?),
+            None => None,
+        };
+        let accepts_pcm = snapcast.is_some();
+
+        #[cfg(feature = "fdk-aac")]
+        let backchannel = match &settings.backchannel {
+            Some(backchannel_settings) => {
+                Some(backchannel::Backchannel::new(backchannel_settings.clone())?)
+            }
+            None => None,
         };
 
         let poll_loop = PollLoop {
             poll,
-            socket,
+            sockets,
             stopper,
             clients: Vec::new(),
-            que,
+            que: que.clone(),
             pkt_gen: pkt::NetworkPktGenerator::new(),
+            settings,
+            last_sent: Instant::now(),
+            capture,
+            #[cfg(feature = "impairment")]
+            impairment,
+            rtsp,
+            snapcast,
+            #[cfg(feature = "fdk-aac")]
+            backchannel,
         };
 
-        thread::spawn(move || poll_loop.poll_loop());
+        let thread = thread::Builder::new()
+            .name("NetServer".to_owned())
+            .spawn(move || poll_loop.poll_loop())
+            .map_err(|e| IoError::new("spawning NetServer poll thread", e))?;
+
+        Ok(Self {
+            que,
+            new_data_readiness: set_readiness,
+            local_addrs,
+            accepts_pcm,
+            thread: Some(thread),
+        })
+    }
 
-        Ok(res)
+    /// Addresses the sockets are actually bound to, in the order of `Settings::addrs`.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
     }
 
     pub fn send_to_all(&self, buf: &[u8]) -> Result<(), Error> {
@@ -82,8 +194,84 @@ impl NetServer {
         };
 
         block.extend_from_slice(buf);
-        que.to_send.push_back(block);
+        que.to_send.push_back(QueuedPkt::Audio(block));
+
+        self.notify_new_data()
+    }
+
+    /// Raw PCM in the format of `snapcast::Settings::params`, for the protocols that
+    /// don't take the encoded stream. Ignored if none of them is enabled.
+    pub fn send_pcm(&self, pcm: &[u8]) -> Result<(), Error> {
+        if !self.accepts_pcm || pcm.is_empty() {
+            return Ok(());
+        }
+
+        self.que
+            .lock()
+            .unwrap()
+            .to_send
+            .push_back(QueuedPkt::Pcm(pcm.to_vec()));
+
+        self.notify_new_data()
+    }
+
+    /// Sends packets built by another server as they are, keeping their sequence numbers.
+    pub fn forward_to_all(&self, pkt: &[u8]) -> Result<(), Error> {
+        if pkt.is_empty() {
+            return Ok(());
+        }
+
+        self.que
+            .lock()
+            .unwrap()
+            .to_send
+            .push_back(QueuedPkt::Forwarded(pkt.to_vec()));
+
+        self.notify_new_data()
+    }
+
+    /// Replaces the answer to the `info` request.
+    pub fn set_info(&self, info: &[u8]) {
+        self.que.lock().unwrap().info = info.to_vec();
+    }
+
+    /// Sends a compact marker instead of `frames` frames of digital silence.
+    pub fn send_silence(&self, frames: u32) -> Result<(), Error> {
+        if frames == 0 {
+            return Ok(());
+        }
+
+        self.que
+            .lock()
+            .unwrap()
+            .to_send
+            .push_back(QueuedPkt::Silence(frames));
+
+        self.notify_new_data()
+    }
+
+    /// Flushes queued data, says goodbye to every client and waits for the poll thread.
+    /// Bounded by `Settings::shutdown_timeout`.
+    pub fn close(&mut self, reason: ByeReason) {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return,
+        };
+
+        self.que.lock().unwrap().close_reason = Some(reason);
+        let res = self
+            .new_data_readiness
+            .set_readiness(mio::Ready::readable());
+        if let Err(e) = res {
+            eprintln!("Error sending close signal to NetServer poll: {}", e);
+        }
+
+        if thread.join().is_err() {
+            eprintln!("NetServer poll thread panicked");
+        }
+    }
 
+    fn notify_new_data(&self) -> Result<(), Error> {
         self.new_data_readiness
             .set_readiness(mio::Ready::readable())
             .map_err(|e| IoError::new("sending signal to Poll of a new data block", e))?;
@@ -91,111 +279,419 @@ impl NetServer {
         Ok(())
     }
 }
+impl Drop for NetServer {
+    fn drop(&mut self) {
+        self.close(ByeReason::Shutdown);
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            addrs: vec!["0.0.0.0:25204".parse().unwrap()],
+            shutdown_timeout: Duration::from_millis(500),
+            reconnect_hint: None,
+            heartbeat_interval: None,
+            capture: None,
+            #[cfg(feature = "impairment")]
+            impairment: None,
+            rtsp: None,
+            snapcast: None,
+            #[cfg(feature = "fdk-aac")]
+            backchannel: None,
+        }
+    }
+}
 
 struct PollLoop {
     poll: mio::Poll,
-    socket: UdpSocket,
+    sockets: Vec<UdpSocket>,
     stopper: exit_listener::SignalEvent,
-    clients: Vec<SocketAddr>,
+    clients: Vec<Client>,
     que: Arc<Mutex<SendQueue>>,
     pkt_gen: pkt::NetworkPktGenerator,
+    settings: Settings,
+    last_sent: Instant,
+    capture: Option<capture::CaptureWriter>,
+    #[cfg(feature = "impairment")]
+    impairment: Option<impairment::Impairment>,
+    rtsp: Option<rtsp::RtspServer>,
+    snapcast: Option<snapcast::SnapcastServer>,
+    #[cfg(feature = "fdk-aac")]
+    backchannel: Option<backchannel::Backchannel>,
+}
+
+/// A listening client and the socket its requests arrived on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Client {
+    addr: SocketAddr,
+    socket: usize,
+}
+
+enum QueuedPkt {
+    Audio(Vec<u8>),
+    Silence(u32),
+    Forwarded(Vec<u8>),
+    Pcm(Vec<u8>),
 }
 
 struct SendQueue {
-    to_send: VecDeque<Vec<u8>>,
+    to_send: VecDeque<QueuedPkt>,
     free: Vec<Vec<u8>>,
+    info: Vec<u8>,
+    close_reason: Option<ByeReason>,
     registration: mio::Registration,
 }
 
 impl PollLoop {
     fn poll_loop(mut self) {
-        let mut buf = vec![0; 1024];
+        // Large enough for the microphone AAC frames.
+        let mut buf = vec![0; 2048];
         let mut events = mio::Events::with_capacity(1024);
         loop {
-            self.poll.poll(&mut events, None).unwrap();
+            self.poll.poll(&mut events, self.poll_timeout()).unwrap();
             for event in &events {
                 match event.token() {
-                    UDP_TOKEN => {
-                        let res = self.socket.recv_from(buf.as_mut_slice());
-
-                        match res {
-                            Ok((n, back_addr)) => self.new_connection(&buf[..n], back_addr),
-                            Err(e) => self.read_err(e),
-                        };
-                    }
                     EXIT_TOKEN => {
                         if self.stopper.has_signal() {
+                            self.shutdown(ByeReason::Shutdown);
                             return;
                         }
                     }
-                    SEND_DATA_TOKEN => self.send_new_data(),
+                    SEND_DATA_TOKEN => {
+                        let close_reason = self.que.lock().unwrap().close_reason;
+                        match close_reason {
+                            Some(reason) => {
+                                self.shutdown(reason);
+                                return;
+                            }
+                            None => self.send_new_data(None),
+                        }
+                    }
+                    mio::Token(t) if t >= FIRST_SNAPCAST_TOKEN => {
+                        if let Some(snapcast) = &mut self.snapcast {
+                            snapcast.ready(&self.poll, event.token());
+                        }
+                    }
+                    mio::Token(t) if t >= FIRST_RTSP_TOKEN => {
+                        if let Some(rtsp) = &mut self.rtsp {
+                            rtsp.ready(&self.poll, event.token(), event.readiness());
+                        }
+                    }
+                    mio::Token(t) if t >= FIRST_SOCKET_TOKEN => {
+                        let socket = t - FIRST_SOCKET_TOKEN;
+                        let res = self.sockets[socket].recv_from(buf.as_mut_slice());
+
+                        match res {
+                            Ok((n, back_addr)) => {
+                                let client = Client {
+                                    addr: back_addr,
+                                    socket,
+                                };
+                                self.new_connection(&buf[..n], client)
+                            }
+                            Err(e) => self.read_err(e),
+                        };
+                    }
                     _ => {}
                 }
             }
+
+            #[cfg(feature = "impairment")]
+            self.send_due_impaired();
+
+            self.send_heartbeat_if_idle();
+
+            if let Some(rtsp) = &mut self.rtsp {
+                rtsp.expire_sessions();
+            }
         }
     }
 
-    fn new_connection(&mut self, buf: &[u8], addr: SocketAddr) {
+    fn new_connection(&mut self, buf: &[u8], client: Client) {
         match buf {
-            b"info" => self.send_info(&addr),
-            b"start" => self.add_new_client(addr),
-            b"stop" => self.remove_client(&addr),
-            _ => {
+            b"info" => self.send_info(&client, false),
+            pkt::TAGGED_INFO_REQUEST => self.send_info(&client, true),
+            b"start" => self.add_new_client(client),
+            b"stop" => self.remove_client(&client.addr),
+            #[cfg(feature = "fdk-aac")]
+            _ => self.mic_pkt(buf, client),
+            #[cfg(not(feature = "fdk-aac"))]
+            _ => eprintln!("Unknown request: {:?}", buf),
+        }
+    }
+
+    #[cfg(feature = "fdk-aac")]
+    fn mic_pkt(&mut self, buf: &[u8], client: Client) {
+        let backchannel = match &mut self.backchannel {
+            Some(backchannel) => backchannel,
+            None => {
                 eprintln!("Unknown request: {:?}", buf);
+                return;
             }
+        };
+
+        if buf.len() > pkt::MIC_REQUEST.len()
+            && buf.starts_with(pkt::MIC_REQUEST)
+            && buf[pkt::MIC_REQUEST.len()] == b' '
+        {
+            backchannel.authorize(&buf[pkt::MIC_REQUEST.len() + 1..], client.addr);
+        } else if let Some((seq, payload)) = pkt::parse_mic(buf) {
+            backchannel.receive(client.addr, seq, payload);
+        } else {
+            eprintln!("Unknown request: {:?}", buf);
         }
     }
 
-    fn send_new_data(&mut self) {
-        let mut que = self.que.lock().unwrap();
-
-        while let Some(mut block) = que.to_send.pop_front() {
-            self.pkt_gen.wrap_in_pkt(&mut block);
+    fn send_new_data(&mut self, deadline: Option<Instant>) {
+        let que = self.que.clone();
+        let mut que = que.lock().unwrap();
+
+        while let Some(queued) = que.to_send.pop_front() {
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    eprintln!(
+                        "Shutdown deadline reached, dropping {} blocks",
+                        que.to_send.len() + 1
+                    );
+                    que.to_send.clear();
+                    return;
+                }
+            }
 
-            let mut clients_to_remove = Vec::new();
+            match queued {
+                QueuedPkt::Audio(mut block) => {
+                    if let Some(rtsp) = &mut self.rtsp {
+                        rtsp.send_aac(&block);
+                    }
+                    self.pkt_gen.wrap_in_pkt(&mut block);
+                    self.send_to_clients(&block, deadline);
 
-            for _ in 0..2 {
-                for (idx, addr) in self.clients.iter().enumerate() {
-                    let res = self.socket.send_to(&block, &addr);
-                    if let Err(e) = res {
-                        eprintln!("Error sending data block to {}. {}", addr, e);
-                        clients_to_remove.push(idx);
+                    block.clear();
+                    que.free.push(block);
+                }
+                QueuedPkt::Silence(frames) => {
+                    if let Some(rtsp) = &mut self.rtsp {
+                        rtsp.skip_frames(frames);
+                    }
+                    let pkt = self.pkt_gen.silence_pkt(frames);
+                    self.send_to_clients(&pkt, deadline);
+                }
+                QueuedPkt::Forwarded(pkt) => {
+                    if let Some(rtsp) = &mut self.rtsp {
+                        match pkt::parse(&pkt) {
+                            Some(pkt::Pkt::Audio { payload, .. }) => rtsp.send_aac(payload),
+                            Some(pkt::Pkt::Silence { frames, .. }) => rtsp.skip_frames(frames),
+                            _ => (),
+                        }
+                    }
+                    self.pkt_gen.forwarded(&pkt);
+                    self.send_to_clients(&pkt, deadline);
+                }
+                QueuedPkt::Pcm(pcm) => {
+                    if let Some(snapcast) = &mut self.snapcast {
+                        snapcast.send_pcm(&pcm);
                     }
                 }
             }
+        }
+    }
+
+    /// Sends the packet twice to every client, dropping the clients it can't be sent to.
+    fn send_to_clients(&mut self, pkt: &[u8], deadline: Option<Instant>) {
+        let mut clients_to_remove = Vec::new();
+
+        for _ in 0..2 {
+            for idx in 0..self.clients.len() {
+                let client = self.clients[idx];
+                let res = self.send_path(client, pkt, deadline);
+                if let Err(e) = res {
+                    eprintln!("Error sending data block to {}. {}", client.addr, e);
+                    clients_to_remove.push(client.addr);
+                }
+            }
+        }
+
+        if !clients_to_remove.is_empty() {
+            self.clients
+                .retain(|c| !clients_to_remove.contains(&c.addr));
+        }
+
+        self.last_sent = Instant::now();
+    }
+
+    /// Every packet to a client goes through here. The impairment simulator, when enabled,
+    /// takes the packet and decides itself when it reaches the socket.
+    fn send_path(
+        &mut self,
+        client: Client,
+        pkt: &[u8],
+        deadline: Option<Instant>,
+    ) -> io::Result<()> {
+        #[cfg(feature = "impairment")]
+        {
+            if let Some(impairment) = &mut self.impairment {
+                impairment.push(client.socket, client.addr, pkt, Instant::now());
+                self.send_due_impaired();
+                return Ok(());
+            }
+        }
+
+        self.send_now(client.socket, &client.addr, pkt, deadline)
+    }
+
+    fn send_now(
+        &mut self,
+        socket: usize,
+        addr: &SocketAddr,
+        pkt: &[u8],
+        deadline: Option<Instant>,
+    ) -> io::Result<()> {
+        send_before(&self.sockets[socket], pkt, addr, deadline)?;
+        capture_pkt(&mut self.capture, pkt, addr);
+        Ok(())
+    }
+
+    #[cfg(feature = "impairment")]
+    fn send_due_impaired(&mut self) {
+        let now = Instant::now();
+        loop {
+            let held = match &mut self.impairment {
+                Some(impairment) => impairment.pop_due(now),
+                None => return,
+            };
+            let held = match held {
+                Some(held) => held,
+                None => return,
+            };
+
+            let res = self.send_now(held.socket, &held.dest, &held.pkt, None);
+            if let Err(e) = res {
+                eprintln!("Error sending impaired packet to {}. {}", held.dest, e);
+            }
+        }
+    }
 
-            for to_remove in clients_to_remove {
-                self.clients.remove(to_remove);
+    /// Waits for the packets the simulator still holds to become due, until the deadline.
+    #[cfg(feature = "impairment")]
+    fn drain_impaired(&mut self, deadline: Instant) {
+        loop {
+            self.send_due_impaired();
+
+            let impairment = match &self.impairment {
+                Some(impairment) => impairment,
+                None => return,
+            };
+            let due = match impairment.next_due() {
+                Some(due) => due,
+                None => return,
+            };
+            if due > deadline {
+                eprintln!(
+                    "Shutdown deadline reached, dropping {} impaired packets",
+                    impairment.held_cnt()
+                );
+                return;
             }
 
-            block.clear();
-            que.free.push(block);
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    /// Wakes the loop up for the next heartbeat, RTSP session expiry or impaired packet.
+    fn poll_timeout(&self) -> Option<Duration> {
+        let heartbeat = self
+            .settings
+            .heartbeat_interval
+            .map(|interval| self.last_sent + interval);
+        let session_expiry = self.rtsp.as_ref().and_then(|rtsp| rtsp.next_expiry());
+
+        [heartbeat, session_expiry, self.next_impaired_due()]
+            .iter()
+            .flatten()
+            .min()
+            .map(|due| {
+                due.checked_duration_since(Instant::now())
+                    .unwrap_or_default()
+            })
+    }
+
+    #[cfg(feature = "impairment")]
+    fn next_impaired_due(&self) -> Option<Instant> {
+        self.impairment.as_ref().and_then(|i| i.next_due())
+    }
+
+    #[cfg(not(feature = "impairment"))]
+    fn next_impaired_due(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Lets clients tell a silent server from a dead one.
+    fn send_heartbeat_if_idle(&mut self) {
+        let interval = match self.settings.heartbeat_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        if self.last_sent.elapsed() < interval {
+            return;
+        }
+
+        if self.clients.is_empty() {
+            self.last_sent = Instant::now();
+        } else {
+            let pkt = self.pkt_gen.heartbeat_pkt();
+            self.send_to_clients(&pkt, None);
+        }
+    }
+
+    /// Flushes the pending audio and says goodbye to every client before `shutdown_timeout` ends.
+    fn shutdown(&mut self, reason: ByeReason) {
+        let deadline = Instant::now() + self.settings.shutdown_timeout;
+        self.send_new_data(Some(deadline));
+
+        let bye = pkt::bye_pkt(reason, self.settings.reconnect_hint);
+        self.send_to_clients(&bye, Some(deadline));
+
+        #[cfg(feature = "impairment")]
+        self.drain_impaired(deadline);
+
+        if let Some(capture) = &mut self.capture {
+            if let Err(e) = capture.flush() {
+                eprintln!("Error flushing packet capture: {}", e);
+            }
         }
+
+        eprintln!("Said goodbye to {} clients", self.clients.len());
     }
 
-    fn send_info(&self, addr: &SocketAddr) {
-        let res = self.socket.send_to(b"Hi, how are you?", &addr);
+    fn send_info(&mut self, client: &Client, tagged: bool) {
+        let mut info = self.que.lock().unwrap().info.clone();
+        if tagged {
+            info = pkt::info_pkt(&info);
+        }
+        let res = self.send_path(*client, &info, None);
         if let Err(e) = res {
-            eprintln!("Error sending: info to {}. {}", addr, e);
+            eprintln!("Error sending: info to {}. {}", client.addr, e);
         }
     }
 
-    fn add_new_client(&mut self, addr: SocketAddr) {
-        let idx = self.clients.iter().position(|&r| r == addr);
+    fn add_new_client(&mut self, client: Client) {
+        let idx = self.clients.iter().position(|r| r.addr == client.addr);
         match idx {
-            Some(_) => {
-                eprintln!("Client {} is already listening", addr);
+            Some(idx) => {
+                eprintln!("Client {} is already listening", client.addr);
+                self.clients[idx].socket = client.socket;
             }
             None => {
-                eprintln!("New client listening: {}", addr);
-                self.clients.push(addr);
+                eprintln!("New client listening: {}", client.addr);
+                self.clients.push(client);
             }
         }
     }
 
     fn remove_client(&mut self, addr: &SocketAddr) {
         eprintln!("{} client disconnected", addr);
-        self.clients.retain(|&r| r != *addr);
+        self.clients.retain(|r| r.addr != *addr);
     }
 
     fn read_err(&self, e: std::io::Error) {
@@ -208,11 +704,64 @@ impl PollLoop {
     }
 }
 
+/// Binds a non-blocking UDP socket. IPv6 sockets are bound with `IPV6_V6ONLY` off.
+fn bind_socket(addr: &SocketAddr) -> Result<UdpSocket, Error> {
+    let ctx = || format!("creating a socket {}", addr);
+
+    let builder = match addr {
+        SocketAddr::V4(_) => net2::UdpBuilder::new_v4(),
+        SocketAddr::V6(_) => net2::UdpBuilder::new_v6().and_then(|b| {
+            b.only_v6(false)?;
+            Ok(b)
+        }),
+    }
+    .map_err(|e| IoError::new(ctx(), e))?;
+
+    let socket = builder.bind(addr).map_err(|e| IoError::new(ctx(), e))?;
+    let socket = UdpSocket::from_socket(socket).map_err(|e| IoError::new(ctx(), e))?;
+    Ok(socket)
+}
+
+/// Writes a sent packet to the capture file, if any. Stops capturing on the first error.
+fn capture_pkt(capture: &mut Option<capture::CaptureWriter>, pkt: &[u8], dest: &SocketAddr) {
+    if let Some(writer) = capture {
+        if let Err(e) = writer.write(pkt, dest) {
+            eprintln!("Error capturing packets, capture is stopped: {}", e);
+            *capture = None;
+        }
+    }
+}
+
+/// Sends a datagram, retrying on a full socket buffer until the deadline passes.
+/// Without a deadline it gives up after the first `WouldBlock`.
+fn send_before(
+    socket: &UdpSocket,
+    buf: &[u8],
+    addr: &SocketAddr,
+    deadline: Option<Instant>,
+) -> io::Result<usize> {
+    loop {
+        match socket.send_to(buf, addr) {
+            Err(e) => match deadline {
+                Some(deadline)
+                    if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline =>
+                {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                _ => return Err(e),
+            },
+            res => return res,
+        }
+    }
+}
+
 impl SendQueue {
     fn new(registration: mio::Registration) -> Self {
         Self {
             to_send: VecDeque::new(),
             free: Vec::new(),
+            info: b"Hi, how are you?".to_vec(),
+            close_reason: None,
             registration,
         }
     }
@@ -1,16 +1,233 @@
+//! Every packet starts with a big-endian `u32` sequence number followed by the payload.
+//! Audio packets never use the sequence number `0`, it marks control packets instead:
+//! `0u32 | type: u8 | body`.
+//!
+//! Clients send text requests, except the microphone audio which is a control packet too.
+//! The answer to the `info` request is the raw info, as older clients expect. The answer
+//! to `info tagged` is a control packet instead, so that a late one can't be taken for audio.
+
+use std::convert::TryInto;
+use std::time::Duration;
 use std::u32;
 
+const CONTROL_SEQ: u32 = 0;
+
+const PKT_BYE: u8 = 1;
+const PKT_HEARTBEAT: u8 = 2;
+const PKT_SILENCE: u8 = 3;
+const PKT_INFO: u8 = 4;
+const PKT_MIC: u8 = 5;
+
+/// Asks for the info as an `info_pkt`.
+pub const TAGGED_INFO_REQUEST: &[u8] = b"info tagged";
+
+/// Followed by a space and the key, authorizes the client to send `mic_pkt`s.
+pub const MIC_REQUEST: &[u8] = b"mic";
+
+/// Why the server is telling clients goodbye.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByeReason {
+    Shutdown,
+    Restart,
+}
+
+/// A packet received from the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pkt<'a> {
+    Audio {
+        seq: u32,
+        payload: &'a [u8],
+    },
+    Silence {
+        seq: u32,
+        frames: u32,
+    },
+    Heartbeat {
+        last_seq: u32,
+    },
+    Bye {
+        reason: Option<ByeReason>,
+        reconnect_after: Option<Duration>,
+    },
+    Info {
+        info: &'a [u8],
+    },
+}
+
 pub struct NetworkPktGenerator {
     cnt: u32,
 }
 
+impl Default for NetworkPktGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NetworkPktGenerator {
     pub fn new() -> Self {
         Self { cnt: 0 }
     }
 
     pub fn wrap_in_pkt(&mut self, buf: &mut Vec<u8>) {
-        self.cnt = self.cnt.overflowing_add(1).0;
-        buf.splice(0..0, self.cnt.to_be_bytes().iter().cloned());
+        let seq = self.next_seq();
+        buf.splice(0..0, seq.to_be_bytes().iter().cloned());
+    }
+
+    pub fn silence_pkt(&mut self, frames: u32) -> Vec<u8> {
+        let seq = self.next_seq();
+        silence_pkt(seq, frames)
+    }
+
+    /// Continues the numbering from a packet that was forwarded as is.
+    pub fn forwarded(&mut self, pkt: &[u8]) {
+        match parse(pkt) {
+            Some(Pkt::Audio { seq, .. }) | Some(Pkt::Silence { seq, .. }) => self.cnt = seq,
+            _ => (),
+        }
+    }
+
+    /// Body: `last_seq: u32`, the sequence number of the last packet sent,
+    /// so that clients could detect losses during the pause.
+    pub fn heartbeat_pkt(&self) -> Vec<u8> {
+        let mut res = control_pkt(PKT_HEARTBEAT);
+        res.extend_from_slice(&self.cnt.to_be_bytes());
+        res
+    }
+
+    fn next_seq(&mut self) -> u32 {
+        self.cnt = next_seq(self.cnt);
+        self.cnt
+    }
+}
+
+pub fn audio_pkt(seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(payload.len() + 4);
+    res.extend_from_slice(&seq.to_be_bytes());
+    res.extend_from_slice(payload);
+    res
+}
+
+/// Body: `seq: u32 | frames: u32`. Takes the place of an audio packet carrying `frames`
+/// frames of digital silence, so it consumes a sequence number.
+pub fn silence_pkt(seq: u32, frames: u32) -> Vec<u8> {
+    let mut res = control_pkt(PKT_SILENCE);
+    res.extend_from_slice(&seq.to_be_bytes());
+    res.extend_from_slice(&frames.to_be_bytes());
+    res
+}
+
+/// Body: `reason: u8 | reconnect_after_ms: u32`. Zero `reconnect_after_ms` means no hint.
+pub fn bye_pkt(reason: ByeReason, reconnect_after: Option<Duration>) -> Vec<u8> {
+    let reconnect_ms = reconnect_after.map_or(0, |d| {
+        let ms = d.as_millis();
+        if ms > u32::MAX as u128 {
+            u32::MAX
+        } else {
+            ms as u32
+        }
+    });
+
+    let mut res = control_pkt(PKT_BYE);
+    res.push(reason.to_wire());
+    res.extend_from_slice(&reconnect_ms.to_be_bytes());
+    res
+}
+
+/// Body: the info set on the server, the answer to `TAGGED_INFO_REQUEST`.
+pub fn info_pkt(info: &[u8]) -> Vec<u8> {
+    let mut res = control_pkt(PKT_INFO);
+    res.extend_from_slice(info);
+    res
+}
+
+/// Body: `seq: u32 | AAC frame`. Microphone audio from a client, numbered on its own.
+pub fn mic_pkt(seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut res = control_pkt(PKT_MIC);
+    res.extend_from_slice(&seq.to_be_bytes());
+    res.extend_from_slice(payload);
+    res
+}
+
+/// The sequence number and the payload of a `mic_pkt`.
+pub fn parse_mic(buf: &[u8]) -> Option<(u32, &[u8])> {
+    if read_u32(buf, 0)? != CONTROL_SEQ || *buf.get(4)? != PKT_MIC {
+        return None;
+    }
+    Some((read_u32(buf, 5)?, &buf[9..]))
+}
+
+/// Returns `None` for malformed or unknown packets.
+pub fn parse(buf: &[u8]) -> Option<Pkt<'_>> {
+    let seq = read_u32(buf, 0)?;
+    if seq != CONTROL_SEQ {
+        return Some(Pkt::Audio {
+            seq,
+            payload: &buf[4..],
+        });
+    }
+
+    match *buf.get(4)? {
+        PKT_BYE => {
+            let reason = ByeReason::from_wire(*buf.get(5)?);
+            let reconnect_ms = read_u32(buf, 6)?;
+            let reconnect_after = if reconnect_ms == 0 {
+                None
+            } else {
+                Some(Duration::from_millis(u64::from(reconnect_ms)))
+            };
+            Some(Pkt::Bye {
+                reason,
+                reconnect_after,
+            })
+        }
+        PKT_HEARTBEAT => Some(Pkt::Heartbeat {
+            last_seq: read_u32(buf, 5)?,
+        }),
+        PKT_SILENCE => Some(Pkt::Silence {
+            seq: read_u32(buf, 5)?,
+            frames: read_u32(buf, 9)?,
+        }),
+        PKT_INFO => Some(Pkt::Info { info: &buf[5..] }),
+        _ => None,
+    }
+}
+
+/// The sequence number following `seq`, skipping the one reserved for control packets.
+pub fn next_seq(seq: u32) -> u32 {
+    let res = seq.wrapping_add(1);
+    if res == CONTROL_SEQ {
+        res + 1
+    } else {
+        res
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn control_pkt(pkt_type: u8) -> Vec<u8> {
+    let mut res = Vec::with_capacity(16);
+    res.extend_from_slice(&CONTROL_SEQ.to_be_bytes());
+    res.push(pkt_type);
+    res
+}
+
+impl ByeReason {
+    fn to_wire(self) -> u8 {
+        match self {
+            ByeReason::Shutdown => 1,
+            ByeReason::Restart => 2,
+        }
+    }
+
+    fn from_wire(reason: u8) -> Option<Self> {
+        match reason {
+            1 => Some(ByeReason::Shutdown),
+            2 => Some(ByeReason::Restart),
+            _ => None,
+        }
     }
 }
@@ -0,0 +1,631 @@
+//! RTSP control for players that expect `rtsp://host/stream`. Every playing session gets
+//! the AAC frames as RTP (RFC 3640, AAC-hbr), over UDP or interleaved in the RTSP connection.
+
+use super::tcp_conn::TcpConns;
+use crate::adts;
+use crate::error::{Error, IoError};
+use mio;
+use mio::net::UdpSocket;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SERVER_NAME: &str = "stream-audio";
+const RTP_VERSION: u8 = 2;
+const RTP_PAYLOAD_TYPE: u8 = 96;
+/// Samples in an AAC-LC frame, the RTP timestamp advances by this much per packet.
+const AAC_FRAME_SAMPLES: u32 = 1024;
+/// The AU size field is 13 bits.
+const MAX_AU_LEN: usize = (1 << 13) - 1;
+const MAX_REQUEST_LEN: usize = 16 * 1024;
+/// A connection with this much unsent output doesn't read, it's closed.
+const MAX_PENDING_OUTPUT: usize = 1024 * 1024;
+/// Tries to find an even port for RTP with a free one above it for RTCP.
+const RTP_BIND_ATTEMPTS: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub addr: SocketAddr,
+    /// The stream is served at `rtsp://host:port{path}`.
+    pub path: String,
+    /// Encoder params, announced in the SDP.
+    pub rate: u32,
+    pub channels: u32,
+    pub bit_rate: u32,
+    /// Sessions without any request for this long are torn down.
+    pub session_timeout: Duration,
+}
+
+/// Lives in the `NetServer` poll loop, see `TcpConns` for the tokens it takes.
+pub(super) struct RtspServer {
+    settings: Settings,
+    conns: TcpConns,
+    rtp_socket: UdpSocket,
+    /// Only holds the advertised port, the players' reports aren't read.
+    _rtcp_socket: UdpSocket,
+    rtp_port: u16,
+    sessions: Vec<Session>,
+    next_session_id: u32,
+    ssrc: u32,
+    seq: u16,
+    timestamp: u32,
+    /// Hex AudioSpecificConfig for the SDP.
+    config: String,
+}
+
+struct Session {
+    id: String,
+    conn: usize,
+    transport: Transport,
+    is_playing: bool,
+    last_seen: Instant,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transport {
+    Udp { rtp: SocketAddr, rtcp_port: u16 },
+    Interleaved { rtp_channel: u8, rtcp_channel: u8 },
+}
+
+struct Request<'a> {
+    method: &'a str,
+    url: &'a str,
+    headers: Vec<(&'a str, &'a str)>,
+}
+
+impl RtspServer {
+    pub(super) fn bind(
+        settings: Settings,
+        poll: &mio::Poll,
+        first_token: usize,
+    ) -> Result<Self, Error> {
+        let config = match adts::audio_specific_config(settings.rate, settings.channels) {
+            Some(config) => format!("{:02X}{:02X}", config[0], config[1]),
+            None => {
+                return Err(IoError::new(
+                    "starting RTSP server",
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "AAC can't describe {} Hz with {} channels",
+                            settings.rate, settings.channels
+                        ),
+                    ),
+                )
+                .into())
+            }
+        };
+
+        let conns = TcpConns::bind("RTSP", &settings.addr, poll, first_token)?;
+
+        let (rtp_socket, rtcp_socket, rtp_port) = bind_rtp_pair(settings.addr.ip())?;
+
+        // Not a secret, only has to differ between runs.
+        let ssrc = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() ^ d.as_secs() as u32)
+            .unwrap_or_default();
+
+        Ok(Self {
+            settings,
+            conns,
+            rtp_socket,
+            _rtcp_socket: rtcp_socket,
+            rtp_port,
+            sessions: Vec::new(),
+            next_session_id: 1,
+            ssrc,
+            seq: 0,
+            timestamp: 0,
+            config,
+        })
+    }
+
+    pub(super) fn ready(&mut self, poll: &mio::Poll, token: mio::Token, readiness: mio::Ready) {
+        let id = match self.conns.ready(poll, token) {
+            Some(id) => id,
+            None => return,
+        };
+        let res = self.conn_ready(id, readiness);
+        if let Err(e) = res {
+            if e.kind() != io::ErrorKind::UnexpectedEof {
+                eprintln!("RTSP connection error: {}", e);
+            }
+            self.close_conn(id);
+        }
+    }
+
+    /// Sends an AAC frame to the playing sessions.
+    pub(super) fn send_aac(&mut self, aac: &[u8]) {
+        if aac.len() > MAX_AU_LEN {
+            eprintln!("AAC frame of {} bytes doesn't fit RTP", aac.len());
+            return;
+        }
+
+        if self.sessions.iter().any(|s| s.is_playing) {
+            let pkt = self.rtp_pkt(aac);
+            self.send_rtp(&pkt);
+        }
+
+        self.seq = self.seq.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(AAC_FRAME_SAMPLES);
+    }
+
+    /// Moves the timestamp over the frames that weren't encoded.
+    pub(super) fn skip_frames(&mut self, frames: u32) {
+        self.timestamp = self.timestamp.wrapping_add(frames);
+    }
+
+    /// Tears down the sessions that haven't been heard from for `session_timeout`.
+    pub(super) fn expire_sessions(&mut self) {
+        let timeout = self.settings.session_timeout;
+        self.sessions.retain(|s| {
+            let is_alive = s.last_seen.elapsed() < timeout;
+            if !is_alive {
+                eprintln!("RTSP session {} timed out", s.id);
+            }
+            is_alive
+        });
+    }
+
+    /// When the next session would time out without a request.
+    pub(super) fn next_expiry(&self) -> Option<Instant> {
+        let timeout = self.settings.session_timeout;
+        self.sessions.iter().map(|s| s.last_seen + timeout).min()
+    }
+
+    fn conn_ready(&mut self, id: usize, readiness: mio::Ready) -> io::Result<()> {
+        if readiness.is_readable() {
+            match self.conns.get_mut(id) {
+                Some(conn) => conn.read_available()?,
+                None => return Ok(()),
+            }
+
+            for s in self.sessions.iter_mut().filter(|s| s.conn == id) {
+                s.last_seen = Instant::now();
+            }
+
+            while let Some(head) = take_request(&mut self.conns.get_mut(id).unwrap().input) {
+                let response = self.handle_request(id, &head);
+                let conn = self.conns.get_mut(id).unwrap();
+                conn.output.extend_from_slice(response.as_bytes());
+            }
+
+            if self.conns.get(id).unwrap().input.len() > MAX_REQUEST_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "RTSP request is too long",
+                ));
+            }
+        }
+
+        match self.conns.get_mut(id) {
+            Some(conn) => conn.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close_conn(&mut self, id: usize) {
+        self.conns.close(id);
+        self.sessions.retain(|s| s.conn != id);
+    }
+
+    fn handle_request(&mut self, conn: usize, head: &str) -> String {
+        let req = match Request::parse(head) {
+            Some(req) => req,
+            None => return response("400 Bad Request", "0", &[], ""),
+        };
+        let cseq = req.header("CSeq").unwrap_or("0");
+
+        let session_id = req
+            .header("Session")
+            .map(|v| v.split(';').next().unwrap_or("").trim());
+        if let Some(session_id) = session_id {
+            let session = self.sessions.iter_mut().find(|s| s.id == session_id);
+            match session {
+                Some(session) => session.last_seen = Instant::now(),
+                None => return response("454 Session Not Found", cseq, &[], ""),
+            }
+        }
+
+        match req.method {
+            "OPTIONS" => response(
+                "200 OK",
+                cseq,
+                &[(
+                    "Public",
+                    "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN, GET_PARAMETER".to_owned(),
+                )],
+                "",
+            ),
+            "DESCRIBE" => self.describe(conn, &req, cseq),
+            "SETUP" => self.setup(conn, &req, cseq, session_id),
+            "PLAY" => self.play(&req, cseq, session_id),
+            "TEARDOWN" => {
+                if let Some(session_id) = session_id {
+                    eprintln!("RTSP session {} is torn down", session_id);
+                    self.sessions.retain(|s| s.id != session_id);
+                }
+                response("200 OK", cseq, &[], "")
+            }
+            "GET_PARAMETER" | "SET_PARAMETER" => response("200 OK", cseq, &[], ""),
+            _ => response("501 Not Implemented", cseq, &[], ""),
+        }
+    }
+
+    fn describe(&self, conn: usize, req: &Request, cseq: &str) -> String {
+        if url_path(req.url).trim_end_matches('/') != self.settings.path {
+            return response("404 Not Found", cseq, &[], "");
+        }
+
+        let local_addr = match self.conns.get(conn).unwrap().stream.local_addr() {
+            Ok(addr) => addr,
+            Err(_) => self.settings.addr,
+        };
+        let addr_type = if local_addr.is_ipv4() { "IP4" } else { "IP6" };
+
+        let sdp = format!(
+            "v=0\r\n\
+             o=- {ssrc} 1 IN {addr_type} {ip}\r\n\
+             s=Stream Audio\r\n\
+             c=IN {addr_type} {any}\r\n\
+             t=0 0\r\n\
+             a=control:*\r\n\
+             m=audio 0 RTP/AVP {pt}\r\n\
+             b=AS:{kbps}\r\n\
+             a=rtpmap:{pt} mpeg4-generic/{rate}/{channels}\r\n\
+             a=fmtp:{pt} streamtype=5;profile-level-id=15;mode=AAC-hbr;config={config};\
+             sizelength=13;indexlength=3;indexdeltalength=3\r\n\
+             a=control:trackID=0\r\n",
+            ssrc = self.ssrc,
+            addr_type = addr_type,
+            ip = local_addr.ip(),
+            any = if local_addr.is_ipv4() {
+                "0.0.0.0"
+            } else {
+                "::"
+            },
+            pt = RTP_PAYLOAD_TYPE,
+            kbps = self.settings.bit_rate / 1000,
+            rate = self.settings.rate,
+            channels = self.settings.channels,
+            config = self.config,
+        );
+
+        let base = if req.url.ends_with('/') {
+            req.url.to_owned()
+        } else {
+            format!("{}/", req.url)
+        };
+        response(
+            "200 OK",
+            cseq,
+            &[
+                ("Content-Base", base),
+                ("Content-Type", "application/sdp".to_owned()),
+            ],
+            &sdp,
+        )
+    }
+
+    fn setup(
+        &mut self,
+        conn: usize,
+        req: &Request,
+        cseq: &str,
+        session_id: Option<&str>,
+    ) -> String {
+        let path = url_path(req.url);
+        if !path.starts_with(&self.settings.path) {
+            return response("404 Not Found", cseq, &[], "");
+        }
+
+        let peer = self.conns.get(conn).unwrap().peer;
+        let transport = match req
+            .header("Transport")
+            .and_then(|t| parse_transport(t, peer))
+        {
+            Some(transport) => transport,
+            None => return response("461 Unsupported Transport", cseq, &[], ""),
+        };
+
+        let id = match session_id {
+            Some(id) => {
+                let session = self.sessions.iter_mut().find(|s| s.id == id).unwrap();
+                session.transport = transport;
+                id.to_owned()
+            }
+            None => {
+                let id = format!("{:08X}{:08X}", self.ssrc, self.next_session_id);
+                self.next_session_id += 1;
+                self.sessions.push(Session {
+                    id: id.clone(),
+                    conn,
+                    transport,
+                    is_playing: false,
+                    last_seen: Instant::now(),
+                });
+                id
+            }
+        };
+
+        let transport = match transport {
+            Transport::Udp { rtp, rtcp_port } => format!(
+                "RTP/AVP;unicast;client_port={}-{};server_port={}-{};ssrc={:08X}",
+                rtp.port(),
+                rtcp_port,
+                self.rtp_port,
+                self.rtp_port + 1,
+                self.ssrc
+            ),
+            Transport::Interleaved {
+                rtp_channel,
+                rtcp_channel,
+            } => format!(
+                "RTP/AVP/TCP;unicast;interleaved={}-{};ssrc={:08X}",
+                rtp_channel, rtcp_channel, self.ssrc
+            ),
+        };
+
+        response(
+            "200 OK",
+            cseq,
+            &[
+                ("Transport", transport),
+                (
+                    "Session",
+                    format!("{};timeout={}", id, self.settings.session_timeout.as_secs()),
+                ),
+            ],
+            "",
+        )
+    }
+
+    fn play(&mut self, req: &Request, cseq: &str, session_id: Option<&str>) -> String {
+        let session = match session_id {
+            Some(id) => self.sessions.iter_mut().find(|s| s.id == id).unwrap(),
+            None => return response("454 Session Not Found", cseq, &[], ""),
+        };
+
+        if !session.is_playing {
+            eprintln!("RTSP session {} is playing", session.id);
+            session.is_playing = true;
+        }
+
+        response(
+            "200 OK",
+            cseq,
+            &[
+                ("Session", session.id.clone()),
+                ("Range", "npt=0.000-".to_owned()),
+                (
+                    "RTP-Info",
+                    format!(
+                        "url={};seq={};rtptime={}",
+                        req.url, self.seq, self.timestamp
+                    ),
+                ),
+            ],
+            "",
+        )
+    }
+
+    fn rtp_pkt(&self, aac: &[u8]) -> Vec<u8> {
+        let mut pkt = Vec::with_capacity(16 + aac.len());
+        pkt.push(RTP_VERSION << 6);
+        // Every packet carries a whole frame, so the marker is always set.
+        pkt.push(0x80 | RTP_PAYLOAD_TYPE);
+        pkt.extend_from_slice(&self.seq.to_be_bytes());
+        pkt.extend_from_slice(&self.timestamp.to_be_bytes());
+        pkt.extend_from_slice(&self.ssrc.to_be_bytes());
+        // One 16 bit AU header: 13 bits of size and 3 bits of index.
+        pkt.extend_from_slice(&16u16.to_be_bytes());
+        pkt.extend_from_slice(&((aac.len() as u16) << 3).to_be_bytes());
+        pkt.extend_from_slice(aac);
+        pkt
+    }
+
+    fn send_rtp(&mut self, pkt: &[u8]) {
+        let mut conns_to_close = Vec::new();
+
+        for session in self.sessions.iter().filter(|s| s.is_playing) {
+            match session.transport {
+                Transport::Udp { rtp, .. } => {
+                    if let Err(e) = self.rtp_socket.send_to(pkt, &rtp) {
+                        if e.kind() != io::ErrorKind::WouldBlock {
+                            eprintln!("Error sending RTP to {}: {}", rtp, e);
+                        }
+                    }
+                }
+                Transport::Interleaved { rtp_channel, .. } => {
+                    let conn = match self.conns.get_mut(session.conn) {
+                        Some(conn) => conn,
+                        None => continue,
+                    };
+                    conn.output.push(b'$');
+                    conn.output.push(rtp_channel);
+                    conn.output
+                        .extend_from_slice(&(pkt.len() as u16).to_be_bytes());
+                    conn.output.extend_from_slice(pkt);
+
+                    let res = conn.flush();
+                    if res.is_err() || conn.output.len() > MAX_PENDING_OUTPUT {
+                        conns_to_close.push(session.conn);
+                    }
+                }
+            }
+        }
+
+        for id in conns_to_close {
+            self.close_conn(id);
+        }
+    }
+}
+
+impl<'a> Request<'a> {
+    fn parse(head: &'a str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?;
+        let url = request_line.next()?;
+
+        let headers = lines
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                let name = parts.next()?.trim();
+                let value = parts.next()?.trim();
+                Some((name, value))
+            })
+            .collect();
+
+        Some(Self {
+            method,
+            url,
+            headers,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    }
+}
+
+fn response(status: &str, cseq: &str, headers: &[(&str, String)], body: &str) -> String {
+    let mut res = format!(
+        "RTSP/1.0 {}\r\nCSeq: {}\r\nServer: {}\r\n",
+        status, cseq, SERVER_NAME
+    );
+    for (name, value) in headers {
+        res += &format!("{}: {}\r\n", name, value);
+    }
+    if !body.is_empty() {
+        res += &format!("Content-Length: {}\r\n", body.len());
+    }
+    res += "\r\n";
+    res += body;
+    res
+}
+
+/// `rtsp://host:port/stream/trackID=0` → `/stream/trackID=0`
+fn url_path(url: &str) -> &str {
+    let without_scheme = match url.find("://") {
+        Some(idx) => &url[idx + 3..],
+        None => url,
+    };
+    match without_scheme.find('/') {
+        Some(idx) => &without_scheme[idx..],
+        None => "/",
+    }
+}
+
+/// Picks the first unicast transport we support from the client's list.
+fn parse_transport(value: &str, peer: SocketAddr) -> Option<Transport> {
+    for spec in value.split(',') {
+        let mut params = spec.split(';').map(|p| p.trim());
+        let protocol = params.next()?;
+        let params: Vec<&str> = params.collect();
+        if params.contains(&"multicast") {
+            continue;
+        }
+
+        let range = |name: &str| {
+            params
+                .iter()
+                .find_map(|p| p.strip_prefix(name))
+                .and_then(parse_range)
+        };
+
+        match protocol {
+            "RTP/AVP" | "RTP/AVP/UDP" => {
+                if let Some((rtp, rtcp)) = range("client_port=") {
+                    return Some(Transport::Udp {
+                        rtp: SocketAddr::new(peer.ip(), rtp),
+                        rtcp_port: rtcp,
+                    });
+                }
+            }
+            "RTP/AVP/TCP" => {
+                let (rtp, rtcp) = range("interleaved=").unwrap_or((0, 1));
+                if rtp <= u16::from(u8::MAX) && rtcp <= u16::from(u8::MAX) {
+                    return Some(Transport::Interleaved {
+                        rtp_channel: rtp as u8,
+                        rtcp_channel: rtcp as u8,
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// `5000-5001` or `5000`, a single value implies the next one.
+fn parse_range(v: &str) -> Option<(u16, u16)> {
+    let mut parts = v.splitn(2, '-');
+    let first = parts.next()?.parse::<u16>().ok()?;
+    let second = match parts.next() {
+        Some(second) => second.parse().ok()?,
+        None => first.wrapping_add(1),
+    };
+    Some((first, second))
+}
+
+/// Binds the RTP socket to an even port and the RTCP one to the port above it,
+/// as RFC 3550 asks.
+fn bind_rtp_pair(ip: IpAddr) -> Result<(UdpSocket, UdpSocket, u16), Error> {
+    for _ in 0..RTP_BIND_ATTEMPTS {
+        let rtp_socket = UdpSocket::bind(&SocketAddr::new(ip, 0))
+            .map_err(|e| IoError::new("binding RTP socket", e))?;
+        let rtp_port = rtp_socket
+            .local_addr()
+            .map_err(|e| IoError::new("getting RTP socket address", e))?
+            .port();
+        if rtp_port & 1 == 1 {
+            continue;
+        }
+
+        if let Ok(rtcp_socket) = UdpSocket::bind(&SocketAddr::new(ip, rtp_port + 1)) {
+            return Ok((rtp_socket, rtcp_socket, rtp_port));
+        }
+    }
+
+    Err(IoError::new(
+        "binding RTP and RTCP sockets",
+        io::Error::new(io::ErrorKind::AddrInUse, "no free pair of ports"),
+    )
+    .into())
+}
+
+/// Removes the next complete request from `input`, skipping interleaved RTCP from the client.
+fn take_request(input: &mut Vec<u8>) -> Option<String> {
+    loop {
+        if input.first() == Some(&b'$') {
+            if input.len() < 4 {
+                return None;
+            }
+            let len = 4 + u16::from_be_bytes([input[2], input[3]]) as usize;
+            if input.len() < len {
+                return None;
+            }
+            input.drain(..len);
+            continue;
+        }
+
+        let head_len = input.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+        let head = String::from_utf8_lossy(&input[..head_len]).into_owned();
+        let body_len = Request::parse(&head)
+            .and_then(|req| req.header("Content-Length"))
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        if input.len() < head_len + body_len {
+            return None;
+        }
+
+        input.drain(..head_len + body_len);
+        return Some(head);
+    }
+}
@@ -0,0 +1,360 @@
+//! The Snapcast binary protocol, so that stock snapclients can play the stream in sync.
+//!
+//! Every message is a 26 byte little-endian base header followed by the payload:
+//! `type u16 | id u16 | refers_to u16 | sent tv | received tv | size u32`,
+//! where `tv` is `sec i32 | usec i32` of the sender's clock.
+//!
+//! snapclient can't decode AAC, so it gets the PCM the encoder is fed with, as 16 bit
+//! `pcm` with a WAV codec header of the same rate and channels as the encoder.
+
+use super::tcp_conn::TcpConns;
+use crate::alsa;
+use crate::error::Error;
+use mio;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const BASE_HEADER_LEN: usize = 26;
+const MSG_CODEC_HEADER: u16 = 1;
+const MSG_WIRE_CHUNK: u16 = 2;
+const MSG_SERVER_SETTINGS: u16 = 3;
+const MSG_TIME: u16 = 4;
+const MSG_HELLO: u16 = 5;
+const MSG_CLIENT_INFO: u16 = 7;
+const MAX_MSG_LEN: usize = 64 * 1024;
+/// A client with this much unsent audio doesn't keep up, it's disconnected.
+const MAX_PENDING_OUTPUT: usize = 1024 * 1024;
+const BITS_PER_SAMPLE: u16 = 16;
+/// Chunk timestamps are resynced with the clock when they drift further than this.
+const MAX_TIMESTAMP_DRIFT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// snapclient connects to port 1704 by default.
+    pub addr: SocketAddr,
+    /// Format of the PCM given to `NetServer::send_pcm`.
+    pub params: alsa::Params,
+    /// How far behind the capture clients play, it absorbs the network jitter.
+    pub buffer: Duration,
+}
+
+/// Lives in the `NetServer` poll loop, see `TcpConns` for the tokens it takes.
+pub(super) struct SnapcastServer {
+    settings: Settings,
+    conns: TcpConns,
+    /// Connections that said hello, they get the audio.
+    clients: Vec<usize>,
+    next_msg_id: u16,
+    /// Server time the next chunk starts at.
+    next_chunk_at: Option<Duration>,
+    pcm: Vec<u8>,
+}
+
+/// `sec | usec` as in the Snapcast messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Tv {
+    sec: i32,
+    usec: i32,
+}
+
+struct BaseHeader {
+    msg_type: u16,
+    id: u16,
+    sent: Tv,
+    size: usize,
+}
+
+impl SnapcastServer {
+    pub(super) fn bind(
+        settings: Settings,
+        poll: &mio::Poll,
+        first_token: usize,
+    ) -> Result<Self, Error> {
+        let conns = TcpConns::bind("Snapcast", &settings.addr, poll, first_token)?;
+
+        Ok(Self {
+            settings,
+            conns,
+            clients: Vec::new(),
+            next_msg_id: 1,
+            next_chunk_at: None,
+            pcm: Vec::new(),
+        })
+    }
+
+    pub(super) fn ready(&mut self, poll: &mio::Poll, token: mio::Token) {
+        let id = match self.conns.ready(poll, token) {
+            Some(id) => id,
+            None => return,
+        };
+
+        if let Err(e) = self.conn_ready(id) {
+            if e.kind() != io::ErrorKind::UnexpectedEof {
+                eprintln!("Snapcast connection error: {}", e);
+            }
+            self.close_conn(id);
+        }
+    }
+
+    /// Sends the PCM to the clients that said hello, timestamped with the server clock.
+    pub(super) fn send_pcm(&mut self, pcm: &[u8]) {
+        let params = self.settings.params;
+        let frames = pcm.len() / params.bytes_per_frame();
+        let duration = Duration::from_micros(frames as u64 * 1_000_000 / u64::from(params.rate));
+
+        // The chunk was captured just now, so it started `duration` ago.
+        let now = monotonic_now();
+        let start = now.checked_sub(duration).unwrap_or_default();
+        let chunk_at = match self.next_chunk_at {
+            Some(next) if next.max(start) - next.min(start) <= MAX_TIMESTAMP_DRIFT => next,
+            _ => start,
+        };
+        self.next_chunk_at = Some(chunk_at + duration);
+
+        if self.clients.is_empty() {
+            return;
+        }
+
+        self.pcm.clear();
+        to_s16(params.format, pcm, &mut self.pcm);
+
+        let mut payload = Vec::with_capacity(12 + self.pcm.len());
+        let timestamp = Tv::from(chunk_at);
+        payload.extend_from_slice(&timestamp.sec.to_le_bytes());
+        payload.extend_from_slice(&timestamp.usec.to_le_bytes());
+        payload.extend_from_slice(&(self.pcm.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&self.pcm);
+
+        let msg = self.message(MSG_WIRE_CHUNK, 0, Tv::default(), &payload);
+        let mut conns_to_close = Vec::new();
+        for &id in &self.clients {
+            if let Some(conn) = self.conns.get_mut(id) {
+                conn.output.extend_from_slice(&msg);
+                let res = conn.flush();
+                if res.is_err() || conn.output.len() > MAX_PENDING_OUTPUT {
+                    conns_to_close.push(id);
+                }
+            }
+        }
+        for id in conns_to_close {
+            self.close_conn(id);
+        }
+    }
+
+    fn conn_ready(&mut self, id: usize) -> io::Result<()> {
+        let received = Tv::from(monotonic_now());
+        match self.conns.get_mut(id) {
+            Some(conn) => conn.read_available()?,
+            None => return Ok(()),
+        }
+
+        while let Some((header, payload)) =
+            take_message(&mut self.conns.get_mut(id).unwrap().input)?
+        {
+            let reply = match header.msg_type {
+                MSG_HELLO => Some(self.hello(id, &payload)),
+                MSG_TIME => {
+                    // The latency is of the client → server trip, the client works out the rest.
+                    let latency = received - header.sent;
+                    let mut payload = Vec::with_capacity(8);
+                    payload.extend_from_slice(&latency.sec.to_le_bytes());
+                    payload.extend_from_slice(&latency.usec.to_le_bytes());
+                    Some(self.message(MSG_TIME, header.id, received, &payload))
+                }
+                MSG_CLIENT_INFO => None,
+                t => {
+                    eprintln!("Unexpected Snapcast message type {}", t);
+                    None
+                }
+            };
+
+            if let Some(reply) = reply {
+                self.conns
+                    .get_mut(id)
+                    .unwrap()
+                    .output
+                    .extend_from_slice(&reply);
+            }
+        }
+
+        self.conns.get_mut(id).unwrap().flush()
+    }
+
+    /// Answers with the settings and the codec header, then the client gets the audio.
+    fn hello(&mut self, id: usize, payload: &[u8]) -> Vec<u8> {
+        let peer = self.conns.get(id).unwrap().peer;
+        eprintln!(
+            "Snapcast client {} says hello: {}",
+            peer,
+            String::from_utf8_lossy(payload.get(4..).unwrap_or_default())
+        );
+
+        let settings = format!(
+            r#"{{"bufferMs":{},"latency":0,"muted":false,"volume":100}}"#,
+            self.settings.buffer.as_millis()
+        );
+        let mut msg = self.message(
+            MSG_SERVER_SETTINGS,
+            0,
+            Tv::default(),
+            &length_prefixed(settings.as_bytes()),
+        );
+
+        let mut codec_header = length_prefixed(b"pcm");
+        codec_header.extend_from_slice(&length_prefixed(&self.wav_header()));
+        msg.extend_from_slice(&self.message(MSG_CODEC_HEADER, 0, Tv::default(), &codec_header));
+
+        if !self.clients.contains(&id) {
+            self.clients.push(id);
+        }
+        msg
+    }
+
+    fn close_conn(&mut self, id: usize) {
+        self.conns.close(id);
+        self.clients.retain(|&c| c != id);
+    }
+
+    fn message(&mut self, msg_type: u16, refers_to: u16, received: Tv, payload: &[u8]) -> Vec<u8> {
+        let id = self.next_msg_id;
+        self.next_msg_id = self.next_msg_id.wrapping_add(1);
+        let sent = Tv::from(monotonic_now());
+
+        let mut msg = Vec::with_capacity(BASE_HEADER_LEN + payload.len());
+        msg.extend_from_slice(&msg_type.to_le_bytes());
+        msg.extend_from_slice(&id.to_le_bytes());
+        msg.extend_from_slice(&refers_to.to_le_bytes());
+        for tv in &[sent, received] {
+            msg.extend_from_slice(&tv.sec.to_le_bytes());
+            msg.extend_from_slice(&tv.usec.to_le_bytes());
+        }
+        msg.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        msg.extend_from_slice(payload);
+        msg
+    }
+
+    /// RIFF header of a 16 bit PCM stream of unknown length.
+    fn wav_header(&self) -> Vec<u8> {
+        let channels = self.settings.params.channels as u16;
+        let rate = self.settings.params.rate;
+        let block_align = channels * BITS_PER_SAMPLE / 8;
+
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&36u32.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&rate.to_le_bytes());
+        header.extend_from_slice(&(rate * u32::from(block_align)).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header
+    }
+}
+
+impl From<Duration> for Tv {
+    fn from(d: Duration) -> Self {
+        Self {
+            sec: d.as_secs() as i32,
+            usec: d.subsec_micros() as i32,
+        }
+    }
+}
+
+impl std::ops::Sub for Tv {
+    type Output = Tv;
+
+    fn sub(self, other: Tv) -> Tv {
+        let usec = (i64::from(self.sec) - i64::from(other.sec)) * 1_000_000 + i64::from(self.usec)
+            - i64::from(other.usec);
+        Tv {
+            sec: usec.div_euclid(1_000_000) as i32,
+            usec: usec.rem_euclid(1_000_000) as i32,
+        }
+    }
+}
+
+/// Removes the next complete message from `input`.
+fn take_message(input: &mut Vec<u8>) -> io::Result<Option<(BaseHeader, Vec<u8>)>> {
+    if input.len() < BASE_HEADER_LEN {
+        return Ok(None);
+    }
+
+    let u16_at = |i: usize| u16::from_le_bytes([input[i], input[i + 1]]);
+    let u32_at =
+        |i: usize| u32::from_le_bytes([input[i], input[i + 1], input[i + 2], input[i + 3]]);
+    let header = BaseHeader {
+        msg_type: u16_at(0),
+        id: u16_at(2),
+        sent: Tv {
+            sec: u32_at(6) as i32,
+            usec: u32_at(10) as i32,
+        },
+        size: u32_at(22) as usize,
+    };
+
+    if header.size > MAX_MSG_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Snapcast message of {} bytes is too long", header.size),
+        ));
+    }
+    if input.len() < BASE_HEADER_LEN + header.size {
+        return Ok(None);
+    }
+
+    let payload = input[BASE_HEADER_LEN..BASE_HEADER_LEN + header.size].to_vec();
+    input.drain(..BASE_HEADER_LEN + header.size);
+    Ok(Some((header, payload)))
+}
+
+fn length_prefixed(data: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(4 + data.len());
+    res.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    res.extend_from_slice(data);
+    res
+}
+
+/// Appends the samples to `out` as 16 bit little-endian.
+fn to_s16(format: alsa::Format, pcm: &[u8], out: &mut Vec<u8>) {
+    match format {
+        alsa::Format::U8 => {
+            for &s in pcm {
+                out.extend_from_slice(&((i16::from(s) - 0x80) << 8).to_le_bytes());
+            }
+        }
+        alsa::Format::S16Le => out.extend_from_slice(pcm),
+        alsa::Format::FloatLe => {
+            for s in pcm.chunks_exact(4) {
+                let v = f32::from_le_bytes([s[0], s[1], s[2], s[3]]);
+                let v = (v.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        format => {
+            let mut s16 = [0u8; 2];
+            for s in pcm.chunks_exact(format.bytes_per_sample()) {
+                alsa::Format::S16Le.sample_from_f32(format.sample_to_f32(s), &mut s16);
+                out.extend_from_slice(&s16);
+            }
+        }
+    }
+}
+
+/// Time since boot, it's what the clients sync to.
+fn monotonic_now() -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
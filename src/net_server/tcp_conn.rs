@@ -0,0 +1,147 @@
+//! Non-blocking TCP connections for the protocols served from the `NetServer` poll loop.
+
+use crate::error::{Error, IoError};
+use mio;
+use mio::net::{TcpListener, TcpStream};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+
+/// A listener registered with `first_token` and its connections with the following tokens,
+/// edge-triggered for reading and writing.
+pub(super) struct TcpConns {
+    name: &'static str,
+    listener: TcpListener,
+    first_token: usize,
+    next_id: usize,
+    conns: HashMap<usize, TcpConn>,
+}
+
+pub(super) struct TcpConn {
+    pub stream: TcpStream,
+    pub peer: SocketAddr,
+    /// Received bytes not consumed yet.
+    pub input: Vec<u8>,
+    /// Bytes the socket hasn't taken yet.
+    pub output: Vec<u8>,
+}
+
+impl TcpConns {
+    /// `name` of the protocol is for logs.
+    pub fn bind(
+        name: &'static str,
+        addr: &SocketAddr,
+        poll: &mio::Poll,
+        first_token: usize,
+    ) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr)
+            .map_err(|e| IoError::new(format!("binding {} to {}", name, addr), e))?;
+        poll.register(
+            &listener,
+            mio::Token(first_token),
+            mio::Ready::readable(),
+            mio::PollOpt::edge(),
+        )
+        .map_err(|e| IoError::new(format!("Registering {} listener to poll", name), e))?;
+
+        Ok(Self {
+            name,
+            listener,
+            first_token,
+            next_id: 0,
+            conns: HashMap::new(),
+        })
+    }
+
+    /// Accepts the pending connections if the token is the listener's, otherwise returns
+    /// the id of the connection.
+    pub fn ready(&mut self, poll: &mio::Poll, token: mio::Token) -> Option<usize> {
+        if token.0 == self.first_token {
+            self.accept(poll);
+            return None;
+        }
+        Some(token.0 - self.first_token - 1)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&TcpConn> {
+        self.conns.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut TcpConn> {
+        self.conns.get_mut(&id)
+    }
+
+    /// Closes the connection, dropping the stream removes it from the poll.
+    pub fn close(&mut self, id: usize) {
+        if let Some(conn) = self.conns.remove(&id) {
+            eprintln!("{} connection from {} closed", self.name, conn.peer);
+        }
+    }
+
+    fn accept(&mut self, poll: &mio::Poll) {
+        loop {
+            let (stream, peer) = match self.listener.accept() {
+                Ok(v) => v,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    eprintln!("Error accepting {} connection: {}", self.name, e);
+                    return;
+                }
+            };
+
+            let id = self.next_id;
+            self.next_id += 1;
+            let res = poll.register(
+                &stream,
+                mio::Token(self.first_token + 1 + id),
+                mio::Ready::readable() | mio::Ready::writable(),
+                mio::PollOpt::edge(),
+            );
+            if let Err(e) = res {
+                eprintln!("Error registering {} connection {}: {}", self.name, peer, e);
+                continue;
+            }
+
+            eprintln!("{} connection from {}", self.name, peer);
+            self.conns.insert(
+                id,
+                TcpConn {
+                    stream,
+                    peer,
+                    input: Vec::new(),
+                    output: Vec::new(),
+                },
+            );
+        }
+    }
+}
+
+impl TcpConn {
+    /// Reads everything the socket has. Fails with `UnexpectedEof` when the peer closed.
+    pub fn read_available(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.input.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Writes as much of the pending output as the socket takes.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.output.is_empty() {
+            match self.stream.write(&self.output) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.output.drain(..n);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
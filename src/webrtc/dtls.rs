@@ -0,0 +1,226 @@
+//! DTLS-SRTP (RFC 5764): the handshake runs over the ICE socket and only yields the keys
+//! of SRTP, no application data is exchanged.
+
+use super::srtp::{SrtpContext, MASTER_KEY_LEN, MASTER_SALT_LEN};
+use crate::error::{Error, IoError};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::ssl::{
+    HandshakeError, MidHandshakeSslStream, Ssl, SslContext, SslMethod, SslStream, SslVerifyMode,
+};
+use openssl::x509::{X509NameBuilder, X509};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::mem;
+
+const SRTP_PROFILE: &str = "SRTP_AES128_CM_SHA1_80";
+const SRTP_EXPORTER_LABEL: &str = "EXTRACTOR-dtls_srtp";
+/// Keeps the handshake flights under the usual path MTU.
+const MTU: u32 = 1200;
+const CERT_DAYS: u32 = 30;
+
+/// The context with a fresh self-signed certificate, and its SHA-256 fingerprint for SDP.
+pub(super) fn context() -> Result<(SslContext, String), Error> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, "stream-audio")?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(64, MsbOption::MAYBE_ZERO, false)?;
+    let serial = serial.to_asn1_integer()?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(CERT_DAYS)?;
+
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    cert.set_serial_number(&serial)?;
+    cert.set_subject_name(&name)?;
+    cert.set_issuer_name(&name)?;
+    cert.set_pubkey(&key)?;
+    cert.set_not_before(&not_before)?;
+    cert.set_not_after(&not_after)?;
+    cert.sign(&key, MessageDigest::sha256())?;
+    let cert = cert.build();
+
+    let mut ctx = SslContext::builder(SslMethod::dtls())?;
+    ctx.set_certificate(&cert)?;
+    ctx.set_private_key(&key)?;
+    ctx.check_private_key()?;
+    ctx.set_tlsext_use_srtp(SRTP_PROFILE)?;
+    // Browsers use self-signed certificates too, the fingerprint from the offer is
+    // compared once the handshake is done.
+    ctx.set_verify_callback(
+        SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        |_, _| true,
+    );
+
+    let fingerprint = hex_fingerprint(&cert.digest(MessageDigest::sha256())?);
+    Ok((ctx.build(), fingerprint))
+}
+
+/// Server side of the handshake with one peer, driven by the received datagrams.
+pub(super) struct Dtls {
+    state: State,
+    /// Hash function name and the value from the offer.
+    remote_fingerprint: (String, String),
+}
+
+enum State {
+    New(Ssl),
+    Handshaking(MidHandshakeSslStream<Datagrams>),
+    Done(SslStream<Datagrams>),
+    Failed,
+}
+
+/// Datagrams in and out of OpenSSL, each write is a whole datagram.
+struct Datagrams {
+    incoming: VecDeque<Vec<u8>>,
+    outgoing: Vec<Vec<u8>>,
+}
+
+impl Dtls {
+    pub fn accept(ctx: &SslContext, remote_fingerprint: (String, String)) -> Result<Self, Error> {
+        let mut ssl = Ssl::new(ctx)?;
+        ssl.set_mtu(MTU)?;
+        Ok(Self {
+            state: State::New(ssl),
+            remote_fingerprint,
+        })
+    }
+
+    /// Handles a received datagram, adding the ones to send back to `out`.
+    /// Returns the SRTP context once the handshake completes.
+    pub fn receive(
+        &mut self,
+        pkt: &[u8],
+        out: &mut Vec<Vec<u8>>,
+    ) -> Result<Option<SrtpContext>, Error> {
+        let res = match mem::replace(&mut self.state, State::Failed) {
+            State::New(ssl) => ssl.accept(Datagrams::new(pkt)),
+            State::Handshaking(mut mid) => {
+                mid.get_mut().incoming.push_back(pkt.to_vec());
+                mid.handshake()
+            }
+            State::Done(mut stream) => {
+                // A retransmitted flight, OpenSSL answers it while reading.
+                stream.get_mut().incoming.push_back(pkt.to_vec());
+                let mut buf = [0u8; 1500];
+                let _ = stream.ssl_read(&mut buf);
+                out.append(&mut stream.get_mut().outgoing);
+                self.state = State::Done(stream);
+                return Ok(None);
+            }
+            State::Failed => return Ok(None),
+        };
+
+        match res {
+            Ok(mut stream) => {
+                out.append(&mut stream.get_mut().outgoing);
+                let srtp = self.srtp_context(&stream)?;
+                self.state = State::Done(stream);
+                Ok(Some(srtp))
+            }
+            Err(HandshakeError::WouldBlock(mut mid)) => {
+                out.append(&mut mid.get_mut().outgoing);
+                self.state = State::Handshaking(mid);
+                Ok(None)
+            }
+            Err(HandshakeError::Failure(mut mid)) => {
+                // Likely an alert telling why.
+                out.append(&mut mid.get_mut().outgoing);
+                Err(handshake_error(mid.error().to_string()))
+            }
+            Err(HandshakeError::SetupFailure(e)) => Err(e.into()),
+        }
+    }
+
+    fn srtp_context(&self, stream: &SslStream<Datagrams>) -> Result<SrtpContext, Error> {
+        let ssl = stream.ssl();
+        if ssl.selected_srtp_profile().is_none() {
+            return Err(handshake_error(format!(
+                "{} wasn't negotiated",
+                SRTP_PROFILE
+            )));
+        }
+
+        let (hash, expected) = &self.remote_fingerprint;
+        let digest = MessageDigest::from_name(&hash.replace('-', ""))
+            .ok_or_else(|| handshake_error(format!("unknown fingerprint hash {}", hash)))?;
+        let cert = ssl
+            .peer_certificate()
+            .ok_or_else(|| handshake_error("no peer certificate".to_owned()))?;
+        let actual = hex_fingerprint(&cert.digest(digest)?);
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(handshake_error(format!(
+                "the peer certificate fingerprint {} isn't the offered {}",
+                actual, expected
+            )));
+        }
+
+        // Client key, server key, client salt, server salt. We send as the server.
+        let mut material = [0u8; 2 * (MASTER_KEY_LEN + MASTER_SALT_LEN)];
+        ssl.export_keying_material(&mut material, SRTP_EXPORTER_LABEL, None)?;
+        let key = &material[MASTER_KEY_LEN..2 * MASTER_KEY_LEN];
+        let salt = &material[2 * MASTER_KEY_LEN + MASTER_SALT_LEN..];
+        SrtpContext::new(key, salt)
+    }
+}
+
+impl Datagrams {
+    fn new(first: &[u8]) -> Self {
+        let mut incoming = VecDeque::new();
+        incoming.push_back(first.to_vec());
+        Self {
+            incoming,
+            outgoing: Vec::new(),
+        }
+    }
+}
+
+impl Read for Datagrams {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.incoming.pop_front() {
+            Some(datagram) => {
+                let len = datagram.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram[..len]);
+                Ok(len)
+            }
+            None => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl Write for Datagrams {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Upper case hex bytes separated with colons, as SDP has them.
+fn hex_fingerprint(digest: &[u8]) -> String {
+    digest
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn handshake_error(msg: String) -> Error {
+    IoError::new(
+        "DTLS handshake",
+        io::Error::new(io::ErrorKind::InvalidData, msg),
+    )
+    .into()
+}
@@ -0,0 +1,481 @@
+//! WebRTC audio for browsers on the LAN. The page served over HTTP posts its SDP offer,
+//! the answer gives the host candidates of the media socket only, as an ICE-lite agent,
+//! so no STUN or TURN server is involved. The audio goes as Opus over DTLS-SRTP.
+//!
+//! Nothing the browser sends besides ICE checks and the DTLS handshake is read,
+//! RTCP reports are ignored.
+
+mod dtls;
+mod sdp;
+mod srtp;
+mod stun;
+
+use crate::alsa;
+use crate::codec::OpusEncoder;
+use crate::error::{Error, IoError};
+use crate::ffmpeg;
+use crate::http;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::ssl::SslContext;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// WebRTC Opus has a 48 kHz clock, and it is the rate we encode with.
+const OPUS_RATE: u32 = 48000;
+/// 20 ms frames, what browsers expect by default.
+const OPUS_FRAME_SAMPLES: u32 = OPUS_RATE / 50;
+const RTP_VERSION: u8 = 2;
+const RTP_HEADER_LEN: usize = 12;
+const RTP_MARKER: u8 = 0x80;
+/// The media socket wakes up this often to expire peers and check for the stop.
+const MEDIA_READ_TIMEOUT: Duration = Duration::from_millis(200);
+const MAX_DATAGRAM_LEN: usize = 1500;
+const ICE_UFRAG_LEN: usize = 8;
+const ICE_PWD_LEN: usize = 24;
+/// Posts its offer to `/offer` and plays the answered stream.
+const PAGE: &str = include_str!("page.html");
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Address of the page and the signalling endpoint.
+    pub http_addr: SocketAddr,
+    /// UDP address of the media. With an unspecified IP every interface is a candidate.
+    pub media_addr: SocketAddr,
+    /// Opus bit rate.
+    pub bit_rate: u32,
+    /// A peer is dropped when it stops sending ICE checks for this long.
+    pub consent_timeout: Duration,
+}
+
+/// Encodes the captured audio to Opus and sends it to every connected browser.
+pub struct WebRtcServer {
+    socket: UdpSocket,
+    shared: Arc<Shared>,
+    resampler: Option<ffmpeg::Resampler>,
+    encoder: OpusEncoder,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+/// What the signalling and the media threads share.
+struct Shared {
+    ctx: SslContext,
+    fingerprint: String,
+    candidates: Vec<SocketAddr>,
+    consent_timeout: Duration,
+    /// By our ICE username fragment.
+    peers: Mutex<HashMap<String, Peer>>,
+}
+
+struct Peer {
+    local_pwd: String,
+    remote_ufrag: String,
+    payload_type: u8,
+    /// Where the checks come from, the nominated one once the browser has chosen.
+    addr: Option<SocketAddr>,
+    last_check: Instant,
+    dtls: dtls::Dtls,
+    /// Set once the DTLS handshake is done, only then media is sent.
+    srtp: Option<srtp::SrtpContext>,
+    ssrc: u32,
+    seq: u16,
+    timestamp: u32,
+    sent_any: bool,
+}
+
+impl WebRtcServer {
+    /// `params` is the format of the PCM given to `send_pcm`.
+    pub fn new(settings: Settings, params: alsa::Params) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(settings.media_addr).map_err(|e| {
+            IoError::new(
+                format!("binding WebRTC media to {}", settings.media_addr),
+                e,
+            )
+        })?;
+        socket
+            .set_read_timeout(Some(MEDIA_READ_TIMEOUT))
+            .map_err(|e| IoError::new("setting WebRTC media socket timeout", e))?;
+        let port = socket
+            .local_addr()
+            .map_err(|e| IoError::new("getting WebRTC media address", e))?
+            .port();
+
+        let candidates: Vec<_> = host_ips(settings.media_addr.ip())?
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect();
+        if candidates.is_empty() {
+            return Err(IoError::new(
+                "starting WebRTC",
+                io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    "no interface to offer as a candidate",
+                ),
+            )
+            .into());
+        }
+
+        let listener = TcpListener::bind(settings.http_addr).map_err(|e| {
+            IoError::new(
+                format!("binding WebRTC signalling to {}", settings.http_addr),
+                e,
+            )
+        })?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| IoError::new("setting WebRTC signalling non-blocking", e))?;
+
+        let opus_params = alsa::Params {
+            rate: OPUS_RATE,
+            ..params
+        };
+        let resampler = if params != opus_params {
+            Some(ffmpeg::Resampler::new(params.into(), opus_params.into())?)
+        } else {
+            None
+        };
+        let encoder =
+            OpusEncoder::new(opus_params, settings.bit_rate, OPUS_FRAME_SAMPLES as usize)?;
+
+        let (ctx, fingerprint) = dtls::context()?;
+        let shared = Arc::new(Shared {
+            ctx,
+            fingerprint,
+            candidates,
+            consent_timeout: settings.consent_timeout,
+            peers: Mutex::new(HashMap::new()),
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let signalling_thread = {
+            let shared = shared.clone();
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("WebRTC signalling".to_owned())
+                .spawn(move || {
+                    let handler = move |req: &http::Request| shared.on_request(req);
+                    http::serve("WebRTC signalling", listener, &stop, Arc::new(handler))
+                })
+                .map_err(|e| IoError::new("spawning WebRTC signalling thread", e))?
+        };
+        let media_thread = {
+            let socket = socket
+                .try_clone()
+                .map_err(|e| IoError::new("cloning WebRTC media socket", e))?;
+            let shared = shared.clone();
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("WebRTC media".to_owned())
+                .spawn(move || shared.receive(&socket, &stop))
+                .map_err(|e| IoError::new("spawning WebRTC media thread", e))?
+        };
+
+        Ok(Self {
+            socket,
+            shared,
+            resampler,
+            encoder,
+            stop,
+            threads: vec![signalling_thread, media_thread],
+        })
+    }
+
+    /// The addresses offered to browsers.
+    pub fn candidates(&self) -> &[SocketAddr] {
+        &self.shared.candidates
+    }
+
+    pub fn send_pcm(&mut self, pcm: &[u8]) -> Result<(), Error> {
+        let pcm = match &mut self.resampler {
+            Some(resampler) => resampler.resample(pcm)?,
+            None => pcm,
+        };
+
+        self.encoder.write(pcm);
+        while let Some(frame) = self.encoder.read()? {
+            let mut peers = self.shared.peers.lock().unwrap();
+            for peer in peers.values_mut() {
+                if let Err(e) = peer.send(&self.socket, frame) {
+                    eprintln!("Error sending WebRTC audio to {:?}: {}", peer.addr, e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+impl Drop for WebRtcServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                eprintln!("WebRTC thread panicked");
+            }
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            http_addr: "0.0.0.0:8080".parse().unwrap(),
+            media_addr: "0.0.0.0:25206".parse().unwrap(),
+            bit_rate: 128000,
+            consent_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Shared {
+    fn on_request(&self, req: &http::Request) -> http::Response {
+        match (req.method.as_str(), req.path.as_str()) {
+            ("GET", "/") => http::Response::ok("text/html; charset=utf-8", PAGE),
+            ("POST", "/offer") => match self.on_offer(&req.body) {
+                Ok(answer) => http::Response::ok("application/sdp", answer),
+                Err(e) => http::Response::bad_request(&e),
+            },
+            _ => http::Response::not_found(),
+        }
+    }
+
+    fn on_offer(&self, offer: &str) -> Result<String, String> {
+        let offer = sdp::Offer::parse(offer)?;
+        let local_ufrag = random_ice_string(ICE_UFRAG_LEN).map_err(|e| e.to_string())?;
+        let local_pwd = random_ice_string(ICE_PWD_LEN).map_err(|e| e.to_string())?;
+        let mut ssrc = [0u8; 4];
+        openssl::rand::rand_bytes(&mut ssrc).map_err(|e| e.to_string())?;
+        let ssrc = u32::from_be_bytes(ssrc);
+
+        let answer = sdp::Answer {
+            ice_ufrag: &local_ufrag,
+            ice_pwd: &local_pwd,
+            fingerprint: &self.fingerprint,
+            ssrc,
+            candidates: &self.candidates,
+        }
+        .to_sdp(&offer);
+
+        let dtls =
+            dtls::Dtls::accept(&self.ctx, offer.fingerprint.clone()).map_err(|e| e.to_string())?;
+        let peer = Peer {
+            local_pwd,
+            remote_ufrag: offer.ice_ufrag,
+            payload_type: offer.opus_payload_type,
+            addr: None,
+            last_check: Instant::now(),
+            dtls,
+            srtp: None,
+            ssrc,
+            seq: ssrc as u16,
+            timestamp: ssrc.rotate_left(16),
+            sent_any: false,
+        };
+        eprintln!("WebRTC offer accepted, waiting for ICE checks");
+        self.peers.lock().unwrap().insert(local_ufrag, peer);
+        Ok(answer)
+    }
+
+    /// Handles ICE checks and DTLS until stopped.
+    fn receive(&self, socket: &UdpSocket, stop: &AtomicBool) {
+        let mut buf = [0u8; MAX_DATAGRAM_LEN];
+        while !stop.load(Ordering::SeqCst) {
+            match socket.recv_from(&mut buf) {
+                // Demultiplexed by the first byte, RFC 7983.
+                Ok((len, from)) if len > 0 => match buf[0] {
+                    0..=3 => self.on_stun(socket, &buf[..len], from),
+                    20..=63 => self.on_dtls(socket, &buf[..len], from),
+                    _ => (),
+                },
+                Ok(_) => (),
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => eprintln!("Error receiving WebRTC datagram: {}", e),
+            }
+            self.expire_peers();
+        }
+    }
+
+    fn on_stun(&self, socket: &UdpSocket, pkt: &[u8], from: SocketAddr) {
+        let req = match stun::BindingRequest::parse(pkt) {
+            Some(req) => req,
+            None => return,
+        };
+        let mut ufrags = req.username.splitn(2, ':');
+        let local_ufrag = ufrags.next().unwrap_or("");
+        let remote_ufrag = ufrags.next().unwrap_or("");
+
+        let mut peers = self.peers.lock().unwrap();
+        let peer = match peers.get_mut(local_ufrag) {
+            Some(peer) if peer.remote_ufrag == remote_ufrag => peer,
+            _ => return,
+        };
+        if !req.check_integrity(&peer.local_pwd) {
+            eprintln!("WebRTC ICE check from {} failed the integrity check", from);
+            return;
+        }
+
+        peer.last_check = Instant::now();
+        if peer.addr != Some(from) && (req.use_candidate || peer.addr.is_none()) {
+            eprintln!("WebRTC peer uses {}", from);
+            peer.addr = Some(from);
+        }
+
+        match stun::binding_success(&req.transaction_id, from, &peer.local_pwd) {
+            Ok(resp) => send_datagram(socket, &resp, from),
+            Err(e) => eprintln!("Error making ICE check response: {}", e),
+        }
+    }
+
+    fn on_dtls(&self, socket: &UdpSocket, pkt: &[u8], from: SocketAddr) {
+        let mut peers = self.peers.lock().unwrap();
+        let ufrag = match peers.iter().find(|(_, peer)| peer.addr == Some(from)) {
+            Some((ufrag, _)) => ufrag.clone(),
+            None => return,
+        };
+        let peer = peers.get_mut(&ufrag).unwrap();
+
+        let mut out = Vec::new();
+        let res = peer.dtls.receive(pkt, &mut out);
+        for datagram in &out {
+            send_datagram(socket, datagram, from);
+        }
+        match res {
+            Ok(Some(srtp)) => {
+                eprintln!("WebRTC peer {} is receiving", from);
+                peer.srtp = Some(srtp);
+            }
+            Ok(None) => (),
+            Err(e) => {
+                eprintln!("Error in WebRTC DTLS with {}: {}", from, e);
+                peers.remove(&ufrag);
+            }
+        }
+    }
+
+    fn expire_peers(&self) {
+        let timeout = self.consent_timeout;
+        self.peers.lock().unwrap().retain(|_, peer| {
+            let alive = peer.last_check.elapsed() < timeout;
+            if !alive {
+                eprintln!("WebRTC peer {:?} is gone", peer.addr);
+            }
+            alive
+        });
+    }
+}
+
+impl Peer {
+    /// Sends the Opus frame if the handshake is done.
+    fn send(&mut self, socket: &UdpSocket, frame: &[u8]) -> Result<(), Error> {
+        let (addr, srtp) = match (self.addr, &mut self.srtp) {
+            (Some(addr), Some(srtp)) => (addr, srtp),
+            _ => return Ok(()),
+        };
+
+        let mut pkt = Vec::with_capacity(RTP_HEADER_LEN + frame.len() + srtp::AUTH_TAG_LEN);
+        pkt.push(RTP_VERSION << 6);
+        // The marker starts the talkspurt.
+        let marker = if self.sent_any { 0 } else { RTP_MARKER };
+        pkt.push(marker | self.payload_type);
+        pkt.extend_from_slice(&self.seq.to_be_bytes());
+        pkt.extend_from_slice(&self.timestamp.to_be_bytes());
+        pkt.extend_from_slice(&self.ssrc.to_be_bytes());
+        pkt.extend_from_slice(frame);
+        srtp.protect(&mut pkt)?;
+
+        match socket.send_to(&pkt, addr) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                eprintln!("WebRTC packet #{} dropped, the socket is full", self.seq)
+            }
+            Err(e) => return Err(IoError::new(format!("sending to {}", addr), e).into()),
+        }
+
+        self.sent_any = true;
+        self.seq = self.seq.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(OPUS_FRAME_SAMPLES);
+        Ok(())
+    }
+}
+
+fn send_datagram(socket: &UdpSocket, datagram: &[u8], to: SocketAddr) {
+    if let Err(e) = socket.send_to(datagram, to) {
+        eprintln!("Error sending WebRTC datagram to {}: {}", to, e);
+    }
+}
+
+fn hmac_sha1(key: &[u8], parts: &[&[u8]]) -> Result<Vec<u8>, ErrorStack> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+    for part in parts {
+        signer.update(part)?;
+    }
+    signer.sign_to_vec()
+}
+
+/// Letters and digits, ICE allows `+` and `/` too but they are a pain in logs.
+fn random_ice_string(len: usize) -> Result<String, ErrorStack> {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let mut bytes = vec![0u8; len];
+    openssl::rand::rand_bytes(&mut bytes)?;
+    Ok(bytes
+        .iter()
+        .map(|b| char::from(CHARS[usize::from(*b) % CHARS.len()]))
+        .collect())
+}
+
+/// The IP itself, or the addresses of the interfaces that are up if it is unspecified.
+/// Loopback and link-local addresses are left out then.
+fn host_ips(ip: IpAddr) -> Result<Vec<IpAddr>, Error> {
+    if !ip.is_unspecified() {
+        return Ok(vec![ip]);
+    }
+
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(IoError::new("listing interfaces", io::Error::last_os_error()).into());
+    }
+
+    let mut ips = Vec::new();
+    let mut cur = addrs;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+
+        let flags = ifa.ifa_flags as libc::c_int;
+        if ifa.ifa_addr.is_null() || flags & libc::IFF_UP == 0 || flags & libc::IFF_LOOPBACK != 0 {
+            continue;
+        }
+        let found = match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_INET if ip.is_ipv4() => {
+                let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 if ip.is_ipv6() => {
+                let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                let v6 = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+                // Link-local needs a scope the SDP can't carry.
+                if v6.segments()[0] & 0xffc0 == 0xfe80 {
+                    continue;
+                }
+                IpAddr::V6(v6)
+            }
+            _ => continue,
+        };
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) };
+        eprintln!("WebRTC candidate {} on {}", found, name.to_string_lossy());
+        ips.push(found);
+    }
+
+    unsafe { libc::freeifaddrs(addrs) };
+    Ok(ips)
+}
@@ -0,0 +1,206 @@
+//! The offers of browsers asking for a single receive-only audio stream, and our answers.
+
+use std::net::SocketAddr;
+
+pub(super) struct Offer {
+    pub ice_ufrag: String,
+    /// Hash function name and the value, e.g. `sha-256` and `AB:CD:..`.
+    pub fingerprint: (String, String),
+    pub mid: String,
+    pub opus_payload_type: u8,
+}
+
+pub(super) struct Answer<'a> {
+    pub ice_ufrag: &'a str,
+    pub ice_pwd: &'a str,
+    pub fingerprint: &'a str,
+    pub ssrc: u32,
+    pub candidates: &'a [SocketAddr],
+}
+
+impl Offer {
+    /// The error says what is missing or unsupported.
+    pub fn parse(sdp: &str) -> Result<Self, String> {
+        let mut ice_ufrag = None;
+        let mut fingerprint = None;
+        let mut mid = None;
+        let mut opus_payload_type = None;
+        let mut media_count = 0;
+
+        for line in sdp.lines() {
+            let line = line.trim_end();
+            if line.starts_with("m=") {
+                media_count += 1;
+                if !line.starts_with("m=audio ") {
+                    return Err(format!("Only audio can be received, got '{}'", line));
+                }
+            } else if let Some(v) = attribute(line, "ice-ufrag") {
+                ice_ufrag = Some(v.to_owned());
+            } else if let Some(v) = attribute(line, "fingerprint") {
+                let mut parts = v.splitn(2, ' ');
+                if let (Some(hash), Some(value)) = (parts.next(), parts.next()) {
+                    fingerprint = Some((hash.to_lowercase(), value.trim().to_owned()));
+                }
+            } else if let Some(v) = attribute(line, "mid") {
+                mid = Some(v.to_owned());
+            } else if let Some(v) = attribute(line, "setup") {
+                if v == "passive" {
+                    return Err("The offerer has to be the DTLS client".to_owned());
+                }
+            } else if let Some(v) = attribute(line, "rtpmap") {
+                let mut parts = v.splitn(2, ' ');
+                if let (Some(pt), Some(encoding)) = (parts.next(), parts.next()) {
+                    if opus_payload_type.is_none()
+                        && encoding.to_lowercase().starts_with("opus/48000")
+                    {
+                        opus_payload_type = pt.parse().ok();
+                    }
+                }
+            }
+        }
+
+        if media_count != 1 {
+            return Err(format!(
+                "Exactly one audio media is supported, got {}",
+                media_count
+            ));
+        }
+        Ok(Self {
+            ice_ufrag: ice_ufrag.ok_or("No a=ice-ufrag")?,
+            fingerprint: fingerprint.ok_or("No a=fingerprint")?,
+            mid: mid.unwrap_or_else(|| "0".to_owned()),
+            opus_payload_type: opus_payload_type.ok_or("Opus isn't offered")?,
+        })
+    }
+}
+
+impl<'a> Answer<'a> {
+    /// ICE-lite with the host candidates only, DTLS server, sending only.
+    pub fn to_sdp(&self, offer: &Offer) -> String {
+        let mut sdp = format!(
+            "v=0\r\n\
+             o=- {ssrc} 2 IN IP4 127.0.0.1\r\n\
+             s=Stream Audio\r\n\
+             t=0 0\r\n\
+             a=ice-lite\r\n\
+             a=group:BUNDLE {mid}\r\n\
+             a=msid-semantic: WMS stream-audio\r\n\
+             m=audio 9 UDP/TLS/RTP/SAVPF {pt}\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=mid:{mid}\r\n\
+             a=sendonly\r\n\
+             a=rtcp-mux\r\n\
+             a=ice-ufrag:{ufrag}\r\n\
+             a=ice-pwd:{pwd}\r\n\
+             a=fingerprint:sha-256 {fingerprint}\r\n\
+             a=setup:passive\r\n\
+             a=rtpmap:{pt} opus/48000/2\r\n\
+             a=fmtp:{pt} minptime=10;useinbandfec=1;stereo=1\r\n\
+             a=msid:stream-audio audio\r\n\
+             a=ssrc:{ssrc} cname:stream-audio\r\n",
+            ssrc = self.ssrc,
+            mid = offer.mid,
+            pt = offer.opus_payload_type,
+            ufrag = self.ice_ufrag,
+            pwd = self.ice_pwd,
+            fingerprint = self.fingerprint,
+        );
+        for (i, addr) in self.candidates.iter().enumerate() {
+            // Host type preference 126, local preference by order, component 1.
+            let priority = (126 << 24) | ((65535 - i as u32) << 8) | 255;
+            sdp += &format!(
+                "a=candidate:{} 1 udp {} {} {} typ host\r\n",
+                i + 1,
+                priority,
+                addr.ip(),
+                addr.port()
+            );
+        }
+        sdp += "a=end-of-candidates\r\n";
+        sdp
+    }
+}
+
+/// The value of `a=name:value`.
+fn attribute<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let mut parts = line.splitn(2, ':');
+    let key = parts.next()?;
+    if key.len() != name.len() + 2 || !key.starts_with("a=") || !key.ends_with(name) {
+        return None;
+    }
+    parts.next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Trimmed down from what a browser offers for a receive-only audio transceiver.
+    const OFFER: &str = "v=0\r\n\
+        o=- 4611731400430051336 2 IN IP4 127.0.0.1\r\n\
+        s=-\r\n\
+        t=0 0\r\n\
+        a=group:BUNDLE 0\r\n\
+        m=audio 9 UDP/TLS/RTP/SAVPF 111 63 9\r\n\
+        c=IN IP4 0.0.0.0\r\n\
+        a=ice-ufrag:Kx3f\r\n\
+        a=ice-pwd:8HqnBmeCHpGk2NlqOFHzUA9N\r\n\
+        a=fingerprint:SHA-256 AB:CD:EF:01\r\n\
+        a=setup:actpass\r\n\
+        a=mid:0\r\n\
+        a=recvonly\r\n\
+        a=rtcp-mux\r\n\
+        a=rtpmap:111 opus/48000/2\r\n\
+        a=fmtp:111 minptime=10;useinbandfec=1\r\n\
+        a=rtpmap:63 red/48000/2\r\n\
+        a=rtpmap:9 G722/8000\r\n";
+
+    #[test]
+    fn parses_browser_offer() {
+        let offer = Offer::parse(OFFER).unwrap();
+        assert_eq!(offer.ice_ufrag, "Kx3f");
+        assert_eq!(
+            offer.fingerprint,
+            ("sha-256".to_owned(), "AB:CD:EF:01".to_owned())
+        );
+        assert_eq!(offer.mid, "0");
+        assert_eq!(offer.opus_payload_type, 111);
+    }
+
+    #[test]
+    fn rejects_video() {
+        let offer = OFFER.to_owned() + "m=video 9 UDP/TLS/RTP/SAVPF 96\r\n";
+        assert!(Offer::parse(&offer).is_err());
+    }
+
+    #[test]
+    fn rejects_passive_offerer() {
+        let offer = OFFER.replace("a=setup:actpass", "a=setup:passive");
+        assert!(Offer::parse(&offer).is_err());
+    }
+
+    #[test]
+    fn requires_opus() {
+        let offer = OFFER.replace("opus/48000/2", "PCMU/8000");
+        assert_eq!(Offer::parse(&offer).err().unwrap(), "Opus isn't offered");
+    }
+
+    #[test]
+    fn answers_with_offered_mid_and_payload_type() {
+        let offer = Offer::parse(&OFFER.replace("a=mid:0", "a=mid:audio")).unwrap();
+        let candidates = ["192.168.1.2:25206".parse().unwrap()];
+        let answer = Answer {
+            ice_ufrag: "ufrag",
+            ice_pwd: "pwd",
+            fingerprint: "01:02",
+            ssrc: 1,
+            candidates: &candidates,
+        }
+        .to_sdp(&offer);
+
+        assert!(answer.contains("a=mid:audio\r\n"));
+        assert!(answer.contains("m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n"));
+        assert!(answer.contains("a=rtpmap:111 opus/48000/2\r\n"));
+        assert!(answer.contains(" 192.168.1.2 25206 typ host\r\n"));
+    }
+}
@@ -0,0 +1,148 @@
+//! SRTP (RFC 3711) protection of the sent RTP with AES_CM_128_HMAC_SHA1_80, the profile
+//! every browser supports. Nothing is received, so there is no replay protection here.
+
+use super::{hmac_sha1, RTP_HEADER_LEN};
+use crate::error::Error;
+use openssl::symm::{encrypt, Cipher};
+
+pub(super) const MASTER_KEY_LEN: usize = 16;
+pub(super) const MASTER_SALT_LEN: usize = 14;
+pub(super) const AUTH_TAG_LEN: usize = 10;
+const AUTH_KEY_LEN: usize = 20;
+
+const LABEL_ENCRYPTION: u8 = 0;
+const LABEL_AUTH: u8 = 1;
+const LABEL_SALT: u8 = 2;
+
+/// Session keys of one direction, derived from the master key the DTLS handshake exported.
+pub(super) struct SrtpContext {
+    key: Vec<u8>,
+    salt: Vec<u8>,
+    auth_key: Vec<u8>,
+    /// Rollover counter, the high bits of the packet index.
+    roc: u32,
+    last_seq: Option<u16>,
+}
+
+impl SrtpContext {
+    pub fn new(master_key: &[u8], master_salt: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            key: derive(master_key, master_salt, LABEL_ENCRYPTION, MASTER_KEY_LEN)?,
+            salt: derive(master_key, master_salt, LABEL_SALT, MASTER_SALT_LEN)?,
+            auth_key: derive(master_key, master_salt, LABEL_AUTH, AUTH_KEY_LEN)?,
+            roc: 0,
+            last_seq: None,
+        })
+    }
+
+    /// Encrypts the payload of the packet in place and appends the authentication tag.
+    /// The packet must have the plain 12 byte header, without CSRCs or extensions.
+    pub fn protect(&mut self, pkt: &mut Vec<u8>) -> Result<(), Error> {
+        let seq = u16::from_be_bytes([pkt[2], pkt[3]]);
+        if let Some(last) = self.last_seq {
+            // Packets are protected in the sending order, so a smaller one has wrapped.
+            if seq < last {
+                self.roc = self.roc.wrapping_add(1);
+            }
+        }
+        self.last_seq = Some(seq);
+
+        // IV = (salt << 16) ^ (SSRC << 64) ^ (index << 16), the index is ROC and seq.
+        let index = (u64::from(self.roc) << 16) | u64::from(seq);
+        let mut iv = [0u8; 16];
+        iv[..MASTER_SALT_LEN].copy_from_slice(&self.salt);
+        for (i, b) in pkt[8..12].iter().enumerate() {
+            iv[4 + i] ^= b;
+        }
+        for (i, b) in index.to_be_bytes()[2..].iter().enumerate() {
+            iv[8 + i] ^= b;
+        }
+
+        let payload = encrypt(
+            Cipher::aes_128_ctr(),
+            &self.key,
+            Some(&iv),
+            &pkt[RTP_HEADER_LEN..],
+        )?;
+        pkt.truncate(RTP_HEADER_LEN);
+        pkt.extend_from_slice(&payload);
+
+        let tag = hmac_sha1(&self.auth_key, &[pkt, &self.roc.to_be_bytes()])?;
+        pkt.extend_from_slice(&tag[..AUTH_TAG_LEN]);
+        Ok(())
+    }
+}
+
+/// The AES-CM key derivation with the key derivation rate of 0.
+fn derive(master_key: &[u8], master_salt: &[u8], label: u8, len: usize) -> Result<Vec<u8>, Error> {
+    let mut iv = [0u8; 16];
+    iv[..MASTER_SALT_LEN].copy_from_slice(master_salt);
+    iv[7] ^= label;
+
+    // The key stream is the encrypted zeros.
+    let key = encrypt(Cipher::aes_128_ctr(), master_key, Some(&iv), &vec![0; len])?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// RFC 3711, B.3.
+    #[test]
+    fn derives_session_keys() {
+        let ctx = SrtpContext::new(
+            &hex("E1F97A0D3E018BE0D64FA32C06DE4139"),
+            &hex("0EC675AD498AFEEBB6960B3AABE6"),
+        )
+        .unwrap();
+
+        assert_eq!(ctx.key, hex("C61E7A93744F39EE10734AFE3FF7A087"));
+        assert_eq!(ctx.salt, hex("30CBBC08863D8C85D49DB34A9AE1"));
+        assert_eq!(
+            ctx.auth_key,
+            hex("CEBE321F6FF7716B6FD4AB49AF256A156D38BAA4")
+        );
+    }
+
+    /// RFC 3711, B.2: the key stream of SSRC 0 and index 0 encrypts the zeros.
+    #[test]
+    fn encrypts_with_aes_cm() {
+        let mut ctx = SrtpContext {
+            key: hex("2B7E151628AED2A6ABF7158809CF4F3C"),
+            salt: hex("F0F1F2F3F4F5F6F7F8F9FAFBFCFD"),
+            auth_key: vec![0; AUTH_KEY_LEN],
+            roc: 0,
+            last_seq: None,
+        };
+        let mut pkt = vec![0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        pkt.resize(RTP_HEADER_LEN + 32, 0);
+
+        ctx.protect(&mut pkt).unwrap();
+
+        assert_eq!(pkt.len(), RTP_HEADER_LEN + 32 + AUTH_TAG_LEN);
+        assert_eq!(
+            pkt[RTP_HEADER_LEN..RTP_HEADER_LEN + 32].to_vec(),
+            hex("E03EAD0935C95E80E166B16DD92B4EB4D23513162B02D0F72A43A2FE4A5F97AB")
+        );
+    }
+
+    #[test]
+    fn counts_rollovers() {
+        let mut ctx = SrtpContext::new(&[0; MASTER_KEY_LEN], &[0; MASTER_SALT_LEN]).unwrap();
+        for seq in &[0xFFFE_u16, 0xFFFF, 0, 1] {
+            let mut pkt = vec![0x80, 0];
+            pkt.extend_from_slice(&seq.to_be_bytes());
+            pkt.resize(RTP_HEADER_LEN + 4, 0);
+            ctx.protect(&mut pkt).unwrap();
+        }
+        assert_eq!(ctx.roc, 1);
+    }
+}
@@ -0,0 +1,251 @@
+//! The part of STUN (RFC 5389) an ICE-lite agent needs: answering the Binding requests
+//! authenticated with the short-term credentials from the SDP.
+
+use super::hmac_sha1;
+use crate::error::Error;
+use std::net::SocketAddr;
+
+const HEADER_LEN: usize = 20;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+
+const ATTR_USERNAME: u16 = 0x0006;
+const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ATTR_USE_CANDIDATE: u16 = 0x0025;
+const ATTR_FINGERPRINT: u16 = 0x8028;
+
+const INTEGRITY_LEN: usize = 20;
+const FINGERPRINT_XOR: u32 = 0x5354_554e;
+
+pub(super) struct BindingRequest<'a> {
+    pub transaction_id: [u8; 12],
+    /// `receiver_ufrag:sender_ufrag`.
+    pub username: &'a str,
+    /// The controlling agent nominates the pair with it.
+    pub use_candidate: bool,
+    msg: &'a [u8],
+    /// Offset of the MESSAGE-INTEGRITY attribute, checked once the password is known.
+    integrity_at: usize,
+}
+
+impl<'a> BindingRequest<'a> {
+    /// `None` if it isn't a Binding request with a username and a MESSAGE-INTEGRITY.
+    pub fn parse(msg: &'a [u8]) -> Option<Self> {
+        if msg.len() < HEADER_LEN
+            || read_u16(msg, 0) != BINDING_REQUEST
+            || usize::from(read_u16(msg, 2)) != msg.len() - HEADER_LEN
+            || read_u32(msg, 4) != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut transaction_id = [0u8; 12];
+        transaction_id.copy_from_slice(&msg[8..HEADER_LEN]);
+        let mut username = None;
+        let mut use_candidate = false;
+        let mut integrity_at = None;
+
+        let mut pos = HEADER_LEN;
+        while pos + 4 <= msg.len() {
+            let attr = read_u16(msg, pos);
+            let len = usize::from(read_u16(msg, pos + 2));
+            let value = msg.get(pos + 4..pos + 4 + len)?;
+            match attr {
+                ATTR_USERNAME => username = std::str::from_utf8(value).ok(),
+                ATTR_USE_CANDIDATE => use_candidate = true,
+                ATTR_MESSAGE_INTEGRITY if len == INTEGRITY_LEN => {
+                    integrity_at = Some(pos);
+                    // Only the fingerprint may follow, and it isn't needed.
+                    break;
+                }
+                _ => (),
+            }
+            // Attributes are padded to 4 bytes.
+            pos += 4 + ((len + 3) & !3);
+        }
+
+        Some(Self {
+            transaction_id,
+            username: username?,
+            use_candidate,
+            msg,
+            integrity_at: integrity_at?,
+        })
+    }
+
+    pub fn check_integrity(&self, password: &str) -> bool {
+        // The HMAC covers the message before the attribute, with the length as if the
+        // attribute ended it.
+        let mut header = [0u8; HEADER_LEN];
+        header.copy_from_slice(&self.msg[..HEADER_LEN]);
+        let len = (self.integrity_at + 4 + INTEGRITY_LEN - HEADER_LEN) as u16;
+        header[2..4].copy_from_slice(&len.to_be_bytes());
+
+        let expected = &self.msg[self.integrity_at + 4..self.integrity_at + 4 + INTEGRITY_LEN];
+        let body = &self.msg[HEADER_LEN..self.integrity_at];
+        match hmac_sha1(password.as_bytes(), &[&header, body]) {
+            Ok(mac) => openssl::memcmp::eq(&mac, expected),
+            Err(_) => false,
+        }
+    }
+}
+
+/// The success response telling the peer its address, signed with our password.
+pub(super) fn binding_success(
+    transaction_id: &[u8; 12],
+    from: SocketAddr,
+    password: &str,
+) -> Result<Vec<u8>, Error> {
+    let mut msg = Vec::with_capacity(HEADER_LEN + 24 + 4 + INTEGRITY_LEN + 8);
+    msg.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    msg.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    msg.extend_from_slice(transaction_id);
+
+    let port = from.port() ^ (MAGIC_COOKIE >> 16) as u16;
+    match from {
+        SocketAddr::V4(addr) => {
+            put_attr_header(&mut msg, ATTR_XOR_MAPPED_ADDRESS, 8);
+            msg.extend_from_slice(&[0, 1]);
+            msg.extend_from_slice(&port.to_be_bytes());
+            let ip = u32::from(*addr.ip()) ^ MAGIC_COOKIE;
+            msg.extend_from_slice(&ip.to_be_bytes());
+        }
+        SocketAddr::V6(addr) => {
+            put_attr_header(&mut msg, ATTR_XOR_MAPPED_ADDRESS, 20);
+            msg.extend_from_slice(&[0, 2]);
+            msg.extend_from_slice(&port.to_be_bytes());
+            let mask = msg[4..HEADER_LEN].to_vec();
+            for (b, m) in addr.ip().octets().iter().zip(mask) {
+                msg.push(b ^ m);
+            }
+        }
+    }
+
+    set_len(&mut msg, 4 + INTEGRITY_LEN);
+    let mac = hmac_sha1(password.as_bytes(), &[&msg])?;
+    put_attr_header(&mut msg, ATTR_MESSAGE_INTEGRITY, INTEGRITY_LEN);
+    msg.extend_from_slice(&mac);
+
+    set_len(&mut msg, 8);
+    let fingerprint = crc32(&msg) ^ FINGERPRINT_XOR;
+    put_attr_header(&mut msg, ATTR_FINGERPRINT, 4);
+    msg.extend_from_slice(&fingerprint.to_be_bytes());
+
+    Ok(msg)
+}
+
+fn put_attr_header(msg: &mut Vec<u8>, attr: u16, len: usize) {
+    msg.extend_from_slice(&attr.to_be_bytes());
+    msg.extend_from_slice(&(len as u16).to_be_bytes());
+}
+
+/// Sets the header length to the attributes so far and the `extra` one about to be added.
+fn set_len(msg: &mut [u8], extra: usize) {
+    let len = (msg.len() - HEADER_LEN + extra) as u16;
+    msg[2..4].copy_from_slice(&len.to_be_bytes());
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([buf[pos], buf[pos + 1]])
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+}
+
+/// CRC-32 of ISO 3309, one message per check is too few to bother with a table.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 5769, 2.1: a request with the short-term credentials of ICE.
+    const REQUEST: &[u8] = &[
+        0x00, 0x01, 0x00, 0x58, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6,
+        0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x10, 0x53, 0x54, 0x55, 0x4e, 0x20, 0x74,
+        0x65, 0x73, 0x74, 0x20, 0x63, 0x6c, 0x69, 0x65, 0x6e, 0x74, 0x00, 0x24, 0x00, 0x04, 0x6e,
+        0x00, 0x01, 0xff, 0x80, 0x29, 0x00, 0x08, 0x93, 0x2f, 0xf9, 0xb1, 0x51, 0x26, 0x3b, 0x36,
+        0x00, 0x06, 0x00, 0x09, 0x65, 0x76, 0x74, 0x6a, 0x3a, 0x68, 0x36, 0x76, 0x59, 0x20, 0x20,
+        0x20, 0x00, 0x08, 0x00, 0x14, 0x9a, 0xea, 0xa7, 0x0c, 0xbf, 0xd8, 0xcb, 0x56, 0x78, 0x1e,
+        0xf2, 0xb5, 0xb2, 0xd3, 0xf2, 0x49, 0xc1, 0xb5, 0x71, 0xa2, 0x80, 0x28, 0x00, 0x04, 0xe5,
+        0x7a, 0x3b, 0xcf,
+    ];
+    const PASSWORD: &str = "VOkJxbRl1RmTxUk/WvJxBt";
+
+    #[test]
+    fn parses_request() {
+        let req = BindingRequest::parse(REQUEST).unwrap();
+        assert_eq!(req.username, "evtj:h6vY");
+        assert_eq!(&req.transaction_id, &REQUEST[8..20]);
+        assert!(!req.use_candidate);
+    }
+
+    #[test]
+    fn checks_integrity() {
+        let req = BindingRequest::parse(REQUEST).unwrap();
+        assert!(req.check_integrity(PASSWORD));
+        assert!(!req.check_integrity("wrong"));
+    }
+
+    #[test]
+    fn fingerprint_matches_rfc() {
+        let fingerprint = read_u32(REQUEST, REQUEST.len() - 4);
+        assert_eq!(
+            crc32(&REQUEST[..REQUEST.len() - 8]) ^ FINGERPRINT_XOR,
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn signs_response() {
+        let mut transaction_id = [0u8; 12];
+        transaction_id.copy_from_slice(&REQUEST[8..20]);
+        let msg = binding_success(
+            &transaction_id,
+            "192.0.2.1:32853".parse().unwrap(),
+            PASSWORD,
+        )
+        .unwrap();
+
+        assert_eq!(read_u16(&msg, 0), BINDING_SUCCESS);
+        assert_eq!(usize::from(read_u16(&msg, 2)), msg.len() - HEADER_LEN);
+        // RFC 5769, 2.2: the XOR-MAPPED-ADDRESS of the IPv4 response.
+        assert_eq!(
+            &msg[HEADER_LEN..HEADER_LEN + 12],
+            &[0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43]
+        );
+
+        let fingerprint_at = msg.len() - 8;
+        assert_eq!(read_u16(&msg, fingerprint_at), ATTR_FINGERPRINT);
+        assert_eq!(
+            crc32(&msg[..fingerprint_at]) ^ FINGERPRINT_XOR,
+            read_u32(&msg, fingerprint_at + 4)
+        );
+
+        let integrity_at = fingerprint_at - 4 - INTEGRITY_LEN;
+        assert_eq!(read_u16(&msg, integrity_at), ATTR_MESSAGE_INTEGRITY);
+        let mut signed = msg[..integrity_at].to_vec();
+        set_len(&mut signed, 4 + INTEGRITY_LEN);
+        let mac = hmac_sha1(PASSWORD.as_bytes(), &[&signed]).unwrap();
+        assert_eq!(&msg[integrity_at + 4..fingerprint_at], &mac[..]);
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,152 @@
+use audio_sharing_pc::exit_listener;
+use audio_sharing_pc::net_server::pkt;
+use audio_sharing_pc::net_server::{self, ByeReason, NetServer};
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+
+/// A client speaking the wire protocol directly, as the Android one does.
+struct Client {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+    /// The server sends every packet twice, the copy is skipped.
+    last: Vec<u8>,
+}
+
+impl Client {
+    fn new(server: &NetServer) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(server.local_addrs()[0]).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        Self {
+            socket,
+            buf: vec![0; 64 * 1024],
+            last: Vec::new(),
+        }
+    }
+
+    fn send(&self, request: &[u8]) {
+        self.socket.send(request).unwrap();
+    }
+
+    fn recv(&mut self) -> &[u8] {
+        let n = self.socket.recv(&mut self.buf).unwrap();
+        &self.buf[..n]
+    }
+
+    fn recv_pkt(&mut self) -> &[u8] {
+        loop {
+            let n = self.socket.recv(&mut self.buf).unwrap();
+            if self.buf[..n] != self.last[..] {
+                self.last.clear();
+                self.last.extend_from_slice(&self.buf[..n]);
+                break;
+            }
+        }
+        &self.last
+    }
+}
+
+fn start_server(settings: net_server::Settings) -> (NetServer, exit_listener::ExitTrigger) {
+    let (stopper, trigger) = exit_listener::exit_event();
+    let server = NetServer::new(settings, stopper).unwrap();
+    (server, trigger)
+}
+
+fn server_settings() -> net_server::Settings {
+    net_server::Settings {
+        addrs: vec!["127.0.0.1:0".parse().unwrap()],
+        ..Default::default()
+    }
+}
+
+fn connect(server: &NetServer) -> Client {
+    let client = Client::new(server);
+    client.send(b"start");
+    // The `start` request is handled by the server's thread.
+    thread::sleep(Duration::from_millis(100));
+    client
+}
+
+fn audio(seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut res = seq.to_be_bytes().to_vec();
+    res.extend_from_slice(payload);
+    res
+}
+
+fn silence(seq: u32, frames: u32) -> Vec<u8> {
+    let mut res = vec![0, 0, 0, 0, 3];
+    res.extend_from_slice(&seq.to_be_bytes());
+    res.extend_from_slice(&frames.to_be_bytes());
+    res
+}
+
+/// A goodbye for a shutdown, without a reconnect hint.
+fn bye() -> Vec<u8> {
+    vec![0, 0, 0, 0, 1, 1, 0, 0, 0, 0]
+}
+
+#[test]
+fn answers_info_raw() {
+    let (server, _trigger) = start_server(server_settings());
+    let mut client = Client::new(&server);
+
+    client.send(b"info");
+
+    assert_eq!(client.recv(), b"Hi, how are you?");
+}
+
+#[test]
+fn answers_info_tagged_on_request() {
+    let (server, _trigger) = start_server(server_settings());
+    let mut client = Client::new(&server);
+
+    client.send(pkt::TAGGED_INFO_REQUEST);
+
+    assert_eq!(client.recv(), &pkt::info_pkt(b"Hi, how are you?")[..]);
+}
+
+#[test]
+fn streams_and_says_goodbye() {
+    let (mut server, _trigger) = start_server(server_settings());
+    let mut client = connect(&server);
+
+    server.send_to_all(b"first").unwrap();
+    server.send_silence(1024).unwrap();
+    server.close(ByeReason::Shutdown);
+
+    assert_eq!(client.recv_pkt(), &audio(1, b"first")[..]);
+    assert_eq!(client.recv_pkt(), &silence(2, 1024)[..]);
+    assert_eq!(client.recv_pkt(), &bye()[..]);
+}
+
+#[test]
+fn stops_on_trigger() {
+    let (server, trigger) = start_server(server_settings());
+    let mut client = connect(&server);
+
+    trigger.trigger().unwrap();
+
+    assert_eq!(client.recv_pkt(), &bye()[..]);
+}
+
+#[cfg(feature = "impairment")]
+#[test]
+fn delivers_delayed_packets_on_shutdown() {
+    let mut settings = server_settings();
+    settings.shutdown_timeout = Duration::from_secs(1);
+    settings.impairment = Some(net_server::impairment::Settings {
+        delay: Duration::from_millis(200),
+        ..Default::default()
+    });
+    let (mut server, _trigger) = start_server(settings);
+    let mut client = connect(&server);
+
+    server.send_to_all(b"last").unwrap();
+    server.close(ByeReason::Shutdown);
+
+    assert_eq!(client.recv_pkt(), &audio(1, b"last")[..]);
+    assert_eq!(client.recv_pkt(), &bye()[..]);
+}